@@ -1,669 +1,8135 @@
-use chrono;
-use clap::Parser;
+use chrono::Datelike;
+use clap::{Parser, ValueEnum};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::cmp;
 use std::collections;
+use std::env;
+use std::ffi;
+use std::fmt;
 use std::fs;
 use std::io;
+use std::io::{IsTerminal, Write};
+use std::mem;
 use std::path;
 use std::process;
+use std::str;
+use std::sync;
+use std::sync::atomic;
+use std::thread;
 use std::time;
 use walkdir::WalkDir;
 
 /// Simple tool for deleting files exponentially based on their times in a specified path
 #[derive(Parser, Debug)]
-#[command(version = "0.1.2", about, author = "Zonkil9", long_about = None)]
+#[command(
+    version = "0.1.2",
+    about,
+    author = "Zonkil9",
+    long_about = None,
+    after_help = "Every option can also be set with an EXPDEL_* environment variable, e.g. EXPDEL_KEEP=2 for --keep. \
+                  Options given on the command line take precedence over the environment."
+)]
 struct Args {
     /// Path to the directory
-    #[arg(short = 'p', long)]
-    path: String,
+    #[arg(
+        short = 'p',
+        long,
+        required_unless_present_any = ["paths_file", "resume"],
+        env = "EXPDEL_PATH"
+    )]
+    path: Option<String>,
 
-    /// Sort by: mtime (modification time), ctime (creation time), atime (access time)
-    #[arg(short = 's', long, default_value = "ctime")]
-    sort: String,
+    /// Read the directories to clean from this file, one per line. Lines starting with # are comments.
+    /// Can be combined with --path.
+    #[arg(long, value_name = "FILE", env = "EXPDEL_PATHS_FILE")]
+    paths_file: Option<String>,
+
+    /// If a path to process points at a file, process the directory holding it instead of failing,
+    /// e.g. for paths dropped or completed in a shell.
+    #[arg(long, default_value_t = false, env = "EXPDEL_INFER_DIR")]
+    infer_dir: bool,
+
+    /// Sort by: mtime (modification time), ctime (creation time), atime (access time).
+    /// Also accepts modified/created/accessed and m/c/a.
+    #[arg(short = 's', long, default_value = "ctime", env = "EXPDEL_SORT")]
+    sort: SortType,
+
+    /// Take the file date from its name instead of its times, e.g. "\d{4}-\d{2}-\d{2}" for backup-2024-01-15.tar.gz.
+    /// The first capture group (or the whole match) must be a date like 2024-01-15 or 20240115.
+    /// Files without a date in the name fall back to --sort.
+    #[arg(long, value_name = "REGEX", env = "EXPDEL_DATE_FROM_NAME")]
+    date_from_name: Option<regex::Regex>,
+
+    /// Round all file times down to the timestamp resolution of the filesystem, e.g. 2 seconds on FAT,
+    /// so files with close times are sorted and grouped the same way on every filesystem.
+    /// The resolution is found with a probe file in each target, whose time is restored afterwards.
+    /// Without this option, a coarse resolution is only warned about.
+    #[arg(long, default_value_t = false, env = "EXPDEL_ROUND_TIMES")]
+    round_times: bool,
+
+    /// Unit of the time segments: days, hours or weeks
+    #[arg(long, value_enum, default_value_t = AgeUnit::Days, env = "EXPDEL_AGE_UNIT")]
+    age_unit: AgeUnit,
+
+    /// Compute the file ages from now, or from the newest file of each directory,
+    /// e.g. for archived snapshots that were not touched for a long time.
+    #[arg(long, value_enum, default_value_t = AgeReference::Now, env = "EXPDEL_AGE_RELATIVE_TO")]
+    age_relative_to: AgeReference,
+
+    /// Use these time segment boundaries instead of powers of two, e.g. 1,7,30,90,365 (in --age-unit).
+    /// The last segment holds everything older than the last boundary.
+    #[arg(long, value_name = "N,N,...", value_parser = parse_bucket_boundaries, env = "EXPDEL_BUCKETS")]
+    buckets: Option<BucketBoundaries>,
+
+    /// Load the time segment boundaries and the files to keep in each of them from this file,
+    /// one BOUNDARY,KEEP line per segment, e.g. "7,5", and a last "*,KEEP" line for the files older
+    /// than the last boundary. Lines starting with # are comments.
+    /// Cannot be used with --buckets or the other ways of choosing the files to keep.
+    #[arg(long, value_name = "FILE", env = "EXPDEL_POLICY_FILE")]
+    policy_file: Option<String>,
+
+    /// Before listing the files of a directory, print the ages each of its time segments holds,
+    /// e.g. "Bucket 8: files aged 5-8 days".
+    #[arg(long, default_value_t = false, env = "EXPDEL_EXPLAIN")]
+    explain: bool,
+
+    /// Merge adjacent time segments with fewer than N files into the next older one before choosing
+    /// the files to keep, so sparse segments do not each keep --keep files.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        env = "EXPDEL_MERGE_BELOW"
+    )]
+    merge_below: usize,
+
+    /// Delete the older copies of files with the same contents, keeping only the newest one, before
+    /// choosing the files to keep. Copies are looked for within each time segment, each directory,
+    /// or across the whole scan. Files matching --protect are never deleted as copies.
+    #[arg(long, value_enum, value_name = "SCOPE", env = "EXPDEL_DEDUPE_SCOPE")]
+    dedupe_scope: Option<DedupeScope>,
 
     /// Number of files to keep per time segment
-    #[arg(short = 'k', long)]
-    keep: u32,
+    #[arg(short = 'k', long, required_unless_present_any = ["keep_total", "keep_percent", "calendar", "target_size", "count_only", "histogram", "dump_buckets", "benchmark", "resume", "policy_file"], env = "EXPDEL_KEEP")]
+    keep: Option<u32>,
+
+    /// Keep at most this many of the newest files in each directory, regardless of time segments.
+    /// Cannot be used with --keep.
+    #[arg(long, env = "EXPDEL_KEEP_TOTAL")]
+    keep_total: Option<usize>,
+
+    /// Keep this percentage of the files of each time segment instead of a fixed number, rounded up,
+    /// so at least one file is kept in every time segment unless P is 0.
+    /// Cannot be used with --keep, --keep-total, --calendar or --target-size.
+    #[arg(long, value_name = "P", value_parser = clap::value_parser!(u32).range(..=100), env = "EXPDEL_KEEP_PERCENT")]
+    keep_percent: Option<u32>,
+
+    /// Keep at least N files in every time segment, on top of what --keep-percent, --keep-total,
+    /// --calendar or --target-size keep, so no time segment is emptied.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        env = "EXPDEL_BUCKET_KEEP_MIN"
+    )]
+    bucket_keep_min: u32,
+
+    /// Keep every file of the youngest time segment, e.g. those younger than a day, whatever the
+    /// other settings, so files that were just created are never deleted.
+    #[arg(long, default_value_t = false, env = "EXPDEL_PROTECT_FIRST_BUCKET")]
+    protect_first_bucket: bool,
+
+    /// Keep a different number of files per time segment in directories ending with DIR, e.g. "logs=5".
+    /// Can be given multiple times, the first matching one is used. Other directories use --keep.
+    #[arg(long, value_name = "DIR=N", value_parser = parse_keep_override, env = "EXPDEL_KEEP_FOR")]
+    keep_for: Vec<KeepOverride>,
+
+    /// Delete the oldest files, oldest time segment first, until the files left in each directory
+    /// take at most SIZE, e.g. 500M or 10GiB. Cannot be used with --keep, --keep-total or --calendar.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, env = "EXPDEL_TARGET_SIZE")]
+    target_size: Option<u64>,
+
+    /// Calendar mode: keep the first file of each of the last days, weeks and months
+    /// (see --daily, --weekly and --monthly) instead of using time segments.
+    /// Cannot be used with --keep or --keep-total.
+    #[arg(long, default_value_t = false, env = "EXPDEL_CALENDAR")]
+    calendar: bool,
+
+    /// Number of days to keep one file for in calendar mode.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 7,
+        requires = "calendar",
+        env = "EXPDEL_DAILY"
+    )]
+    daily: u32,
+
+    /// Number of weeks to keep one file for in calendar mode.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 4,
+        requires = "calendar",
+        env = "EXPDEL_WEEKLY"
+    )]
+    weekly: u32,
+
+    /// Number of months to keep one file for in calendar mode.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 12,
+        requires = "calendar",
+        env = "EXPDEL_MONTHLY"
+    )]
+    monthly: u32,
 
     /// FOR EXPERTS ONLY! Use with caution.
     /// Automatically confirm deletion without prompting. Cannot be used with --print_only.
-    #[arg(short = 'f', long, default_value_t = false)]
+    #[arg(short = 'f', long, default_value_t = false, env = "EXPDEL_FORCE")]
     force: bool,
 
+    /// Automatically confirm deletion when fewer than N files would be deleted, still prompting for larger batches.
+    #[arg(long, value_name = "N", env = "EXPDEL_AUTO_CONFIRM_UNDER")]
+    auto_confirm_under: Option<usize>,
+
     ///This is a Print only mode, so-called "dry run". No files will be deleted.
-    ///Cannot be used with --force or --quiet.
-    #[arg(short = 'o', long, default_value_t = false)]
+    ///Cannot be used with --force. With --quiet, only the --json-summary line is printed.
+    #[arg(short = 'o', long, default_value_t = false, env = "EXPDEL_PRINT_ONLY")]
     print_only: bool,
 
+    /// With --print-only, exit with code 4 if the plan deletes any file and 0 if it deletes none,
+    /// e.g. to fail a CI check when a cleanup is overdue.
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "print_only",
+        env = "EXPDEL_EXIT_NONZERO_IF_DELETIONS"
+    )]
+    exit_nonzero_if_deletions: bool,
+
+    /// Like --print-only, but also check that every file to be deleted could actually be deleted,
+    /// e.g. that its directory is writable, and report the ones that could not.
+    /// Cannot be used with --force or --quiet.
+    #[arg(long, default_value_t = false, env = "EXPDEL_CHECK_DELETABLE")]
+    check_deletable: bool,
+
+    /// Never delete files which are the target of a symlink in the scanned directories,
+    /// or hard links of a kept file.
+    #[arg(long, default_value_t = false, env = "EXPDEL_KEEP_LINKED")]
+    keep_linked: bool,
+
+    /// Never delete files whose name matches this glob pattern, e.g. "*.keep". Can be given multiple times.
+    #[arg(long, value_name = "PATTERN", env = "EXPDEL_PROTECT")]
+    protect: Vec<glob::Pattern>,
+
+    /// Within each time segment, keep files whose name matches this glob pattern, e.g. "*full*", before the others,
+    /// whatever their age. Applies to --keep and --keep-percent. Can be given multiple times.
+    #[arg(long, value_name = "PATTERN", env = "EXPDEL_PREFER_KEEP")]
+    prefer_keep: Vec<glob::Pattern>,
+
+    /// Only process files with these extensions, e.g. "log,bak,tmp". The leading dot is optional.
+    /// Other files are left untouched.
+    #[arg(long, value_name = "EXT,...", value_delimiter = ',', value_parser = parse_extension, env = "EXPDEL_EXT")]
+    ext: Vec<String>,
+
+    /// Apply the time segments and --keep separately to each file extension in a directory.
+    #[arg(long, default_value_t = false, env = "EXPDEL_GROUP_BY_EXTENSION")]
+    group_by_extension: bool,
+
+    /// Compare file extensions case-insensitively in --ext, --group-by-extension and --protect,
+    /// so Photo.JPG and photo.jpg are treated the same. Pass false to compare them exactly.
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set, env = "EXPDEL_CASE_INSENSITIVE_EXT")]
+    case_insensitive_ext: bool,
+
+    /// Delete at most N files in this run, the oldest ones. The other files to be deleted are kept until a later run.
+    #[arg(long, value_name = "N", env = "EXPDEL_MAX_DELETE")]
+    max_delete: Option<usize>,
+
+    /// Delete only the oldest of the files to be deleted that free at least SIZE in total, e.g. 5G.
+    /// The other files to be deleted are kept until a later run.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, env = "EXPDEL_FREE_AT_LEAST")]
+    free_at_least: Option<u64>,
+
+    /// Count a symlink as the size of its target instead of the link itself, in the summary,
+    /// --target-size, --free-at-least and the freed bytes, e.g. for links listed in a resumed --journal.
+    /// Only the link is ever deleted, whichever size is counted.
+    #[arg(long, default_value_t = false, env = "EXPDEL_DEREFERENCE_SIZE")]
+    dereference_size: bool,
+
+    /// Abort without deleting anything if more than N files would be deleted,
+    /// e.g. to guard automated runs against a changed configuration.
+    #[arg(long, value_name = "N", env = "EXPDEL_ABORT_IF_OVER")]
+    abort_if_over: Option<usize>,
+
+    /// Abort without deleting anything if the files to be deleted take more than SIZE, e.g. 10G.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, env = "EXPDEL_ABORT_IF_OVER_BYTES")]
+    abort_if_over_bytes: Option<u64>,
+
+    /// Delete the files of each directory oldest first or newest first, instead of in the order of the plan.
+    /// With oldest, an interrupted run has removed the least valuable files.
+    #[arg(long, value_enum, value_name = "ORDER", env = "EXPDEL_DELETE_ORDER")]
+    delete_order: Option<DeleteOrder>,
+
+    /// Write the files to delete to this journal and mark each one once deleted, so an interrupted run can be resumed.
+    /// The journal is removed when every file was deleted.
+    #[arg(long, value_name = "FILE", env = "EXPDEL_JOURNAL")]
+    journal: Option<String>,
+
+    /// Delete the files left in the --journal of an interrupted run, without scanning or planning again.
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "journal",
+        env = "EXPDEL_RESUME"
+    )]
+    resume: bool,
+
+    /// Stop deleting once the run has taken DURATION, e.g. 10m, and exit with code 3.
+    /// Together with --journal, the rest can be deleted later with --resume.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "EXPDEL_MAX_RUNTIME")]
+    max_runtime: Option<time::Duration>,
+
+    /// Do nothing unless the filesystem of a directory is more than PERCENT full, e.g. for a frequent cron job.
+    /// Directories on filesystems below the threshold are skipped.
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(..=100), env = "EXPDEL_WHEN_ABOVE")]
+    when_above: Option<u32>,
+
+    /// Do nothing unless fewer than PERCENT of the inodes of the filesystem of a directory are free,
+    /// for filesystems running out of inodes before space (Unix only).
+    /// Directories on filesystems with enough free inodes are skipped.
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(..=100), env = "EXPDEL_WHEN_INODES_BELOW")]
+    when_inodes_below: Option<u32>,
+
+    /// After deleting each file, check that it is really gone, for network mounts reporting success without
+    /// removing anything. Files still there are reported as failed deletions.
+    #[arg(long, default_value_t = false, env = "EXPDEL_VERIFY")]
+    verify: bool,
+
+    /// Number of times to retry a deletion failing with a transient error, e.g. on network filesystems.
+    #[arg(long, default_value_t = 0, env = "EXPDEL_RETRIES")]
+    retries: u32,
+
+    /// Delay between retries in milliseconds.
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 100,
+        env = "EXPDEL_RETRY_DELAY"
+    )]
+    retry_delay: u64,
+
+    /// Move the files to the trash of the OS (Recycle Bin, Trash) instead of deleting them permanently,
+    /// so they can be restored from the file manager. Falls back to deleting them, with a warning,
+    /// where the platform has no trash or, on Linux and BSD, neither $XDG_DATA_HOME nor $HOME is set.
+    /// A file that cannot be moved to the trash for any other reason is left in place and counted as failed.
+    #[arg(long, default_value_t = false, env = "EXPDEL_RECYCLE")]
+    recycle: bool,
+
+    /// Rename the files to delete in place by appending SUFFIX, e.g. ".deleted", instead of deleting them,
+    /// so a later sweep can handle them. Files already ending with SUFFIX are left out of the plan.
+    /// Cannot be used with --recycle.
+    #[arg(long, value_name = "SUFFIX", value_parser = clap::builder::NonEmptyStringValueParser::new(), env = "EXPDEL_MARK")]
+    mark: Option<String>,
+
+    /// What --mark does when the marked name already exists: skip leaves the file as it is,
+    /// overwrite replaces the existing file and rename adds a number, e.g. file.txt.1.deleted.
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = OnConflict::Rename, env = "EXPDEL_ON_CONFLICT")]
+    on_conflict: OnConflict,
+
+    /// Move the files to delete to a .expdel-quarantine folder of their directory, in a subfolder named
+    /// after the day, instead of deleting them, and permanently delete the folders of the days more than
+    /// DAYS days ago. Cannot be used with --recycle or --mark.
+    #[arg(long, value_name = "DAYS", env = "EXPDEL_QUARANTINE_DAYS")]
+    quarantine_days: Option<u32>,
+
+    /// Only count the files in each time segment, without planning any deletion.
+    /// Cannot be used with --force.
+    #[arg(long, default_value_t = false, env = "EXPDEL_COUNT_ONLY")]
+    count_only: bool,
+
+    /// Like --count-only, but draw a bar chart of the files (and their bytes) in each time segment,
+    /// as wide as the terminal (or $COLUMNS when stdout is not one). Cannot be used with --force.
+    #[arg(long, default_value_t = false, env = "EXPDEL_HISTOGRAM")]
+    histogram: bool,
+
+    /// Like --count-only, but print the time segments of each directory as JSON: their boundary,
+    /// the ages they hold, the number and bytes of their files and the file paths.
+    /// Cannot be used with --force.
+    #[arg(long, default_value_t = false, env = "EXPDEL_DUMP_BUCKETS")]
+    dump_buckets: bool,
+
+    /// Only time scanning the directories and sorting their files into time segments, without planning
+    /// any deletion, and print the files per second and the bytes of the files scanned.
+    /// Cannot be used with --force.
+    #[arg(long, default_value_t = false, env = "EXPDEL_BENCHMARK")]
+    benchmark: bool,
+
+    /// Scan each directory N times with --benchmark.
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), requires = "benchmark", env = "EXPDEL_BENCHMARK_ITERATIONS")]
+    benchmark_iterations: u32,
+
+    /// Take a lock before scanning, so a second ExpDel on the same directory fails instead of racing.
+    /// Without a FILE, .expdel.lock in each target directory is used.
+    #[arg(long, value_name = "FILE", num_args = 0..=1, env = "EXPDEL_LOCK")]
+    lock: Option<Option<String>>,
+
+    /// Run this shell command once after deleting, e.g. to refresh an index or send a notification.
+    /// It gets EXPDEL_DELETED_COUNT, EXPDEL_FREED_BYTES and EXPDEL_FAILED_COUNT in its environment.
+    #[arg(long, value_name = "CMD", env = "EXPDEL_POST_HOOK")]
+    post_hook: Option<String>,
+
+    /// Also log the start of the run, each failed deletion and the summary to the local syslog (Unix only).
+    #[arg(long, default_value_t = false, env = "EXPDEL_SYSLOG")]
+    syslog: bool,
+
+    /// Do not append this run to the history file in $XDG_STATE_HOME/expdel/history.jsonl.
+    #[arg(long, default_value_t = false, env = "EXPDEL_NO_HISTORY")]
+    no_history: bool,
+
+    /// Refuse to delete unless the same command was run with --print-only within the last MINUTES (60 by default).
+    /// Pass it to the --print-only run as well, so the preview is recorded in the state directory.
+    #[arg(long, value_name = "MINUTES", num_args = 0..=1, default_missing_value = "60", env = "EXPDEL_REQUIRE_PREVIEW")]
+    require_preview: Option<u64>,
+
+    /// Never delete files first seen by ExpDel less than DURATION ago, e.g. 7d or 12h, whatever their time.
+    /// When each file was first seen is recorded in $XDG_STATE_HOME/expdel/first_seen.json.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, env = "EXPDEL_GRACE")]
+    grace: Option<time::Duration>,
+
+    /// Hash every file before deleting it and record the digest in the history file and the JSON plan.
+    #[arg(long, value_enum, value_name = "ALGO", env = "EXPDEL_RECORD_CHECKSUM")]
+    record_checksum: Option<ChecksumAlgorithm>,
+
     /// Recursive mode: also process files in subdirectories.
-    #[arg(short = 'r', long, default_value_t = false)]
+    #[arg(short = 'r', long, default_value_t = false, env = "EXPDEL_RECURSIVE")]
     recursive: bool,
 
+    /// Scan up to N directories at the same time in recursive mode, e.g. for deep trees on fast storage.
+    /// The plan and its output are the same as with one.
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), env = "EXPDEL_DIR_JOBS")]
+    dir_jobs: u16,
+
+    /// Read the metadata of up to N files of a directory at the same time, e.g. on network storage
+    /// with a high latency per file. Independent of --dir-jobs. The plan is the same as with one.
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), env = "EXPDEL_STAT_JOBS")]
+    stat_jobs: u16,
+
+    /// In recursive mode, do not scan the directories whose modification time is the same as when
+    /// the last run not in print-only mode scanned them, i.e. where no file was added, removed or renamed.
+    /// The times are recorded in $XDG_STATE_HOME/expdel/dir_mtimes.json.
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "recursive",
+        env = "EXPDEL_SKIP_UNCHANGED_DIRS"
+    )]
+    skip_unchanged_dirs: bool,
+
+    /// In recursive mode, also treat the empty directories as candidates, in the time segment of their
+    /// own time, e.g. to clean up the folders left behind by another tool. Directories that are
+    /// not empty any more when they are deleted are left in place.
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "recursive",
+        env = "EXPDEL_INCLUDE_EMPTY_DIRS"
+    )]
+    include_empty_dirs: bool,
+
+    /// Skip hidden files (starting with a dot, e.g. .env) and, in recursive mode, hidden subdirectories (e.g. .cache).
+    #[arg(long, default_value_t = false, env = "EXPDEL_SKIP_HIDDEN")]
+    skip_hidden: bool,
+
+    /// Do not descend into directories on other filesystems, e.g. mount points, in recursive mode.
+    #[arg(long, default_value_t = false, env = "EXPDEL_SAME_FILESYSTEM")]
+    same_filesystem: bool,
+
+    /// Read the metadata of the files in inode order, which is much faster on spinning disks.
+    /// Does not change the plan.
+    #[arg(long, default_value_t = false, env = "EXPDEL_INODE_SORT")]
+    inode_sort: bool,
+
+    /// List the time segments oldest first, which also deletes the oldest segments first.
+    /// Does not change which files are deleted.
+    #[arg(
+        long,
+        visible_alias = "reverse-buckets",
+        default_value_t = false,
+        env = "EXPDEL_REVERSE"
+    )]
+    reverse: bool,
+
+    /// Show the owner and permission bits of each file in the plan (Unix only).
+    #[arg(long, default_value_t = false, env = "EXPDEL_SHOW_OWNER")]
+    show_owner: bool,
+
+    /// Print each file of the plan with this template instead of "path | time", e.g. "{path}\t{time}\t{action}".
+    /// Placeholders: {path}, {time}, {bucket}, {action} (keep, protected, delete or duplicate), {size} and {owner}.
+    /// \t and \n are a tab and a newline, {{ and }} are literal braces.
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_template, env = "EXPDEL_TEMPLATE")]
+    template: Option<Template>,
+
+    /// Leave directories with fewer than N files untouched in recursive mode.
+    #[arg(long, value_name = "N", default_value_t = 0, env = "EXPDEL_MIN_FILES")]
+    min_files: usize,
+
+    /// Abort the scan when the metadata of an entry cannot be read, instead of skipping it with a warning.
+    #[arg(long, default_value_t = false, env = "EXPDEL_STAT_ERRORS_FATAL")]
+    stat_errors_fatal: bool,
+
+    /// What to do when a subdirectory cannot be read in recursive mode, e.g. without the permission:
+    /// skip it with a warning, abort the scan, or skip it silently.
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = WalkErrors::Warn, env = "EXPDEL_WALK_ERRORS")]
+    walk_errors: WalkErrors,
+
     /// Quiet mode: no output, except for errors. Silent deletion.
-    /// Cannot be used with --print_only.
-    #[arg(short = 'q', long, default_value_t = false)]
+    /// Cannot be used with --verbose. With --print-only, only the --json-summary line is printed.
+    #[arg(short = 'q', long, default_value_t = false, env = "EXPDEL_QUIET")]
     quiet: bool,
+
+    /// Do not print an error for every file that could not be deleted, only a count per kind of error at the end.
+    #[arg(long, default_value_t = false, env = "EXPDEL_QUIET_ERRORS")]
+    quiet_errors: bool,
+
+    /// Print how many files were kept and deleted in each directory, even in quiet mode.
+    #[arg(long, default_value_t = false, env = "EXPDEL_REPORT_KEPT")]
+    report_kept: bool,
+
+    /// Print how long scanning and deleting took, and the files per second, even in quiet mode.
+    /// Also printed in verbose mode. Printed on stderr when stdout holds a plan in another format
+    /// than human, the --list paths or the --json-summary.
+    #[arg(long, default_value_t = false, env = "EXPDEL_TIMING")]
+    timing: bool,
+
+    /// Also print a one-line JSON summary of the run (counts, bytes and duration) after the usual output.
+    #[arg(long, default_value_t = false, env = "EXPDEL_JSON_SUMMARY")]
+    json_summary: bool,
+
+    /// Verbose mode: print additional details, e.g. skipped entries.
+    /// Cannot be used with --quiet or --summary-only.
+    #[arg(short = 'v', long, default_value_t = false, env = "EXPDEL_VERBOSE")]
+    verbose: bool,
+
+    /// Output format of the plan: human (readable listing), json (for saving and scripting), csv,
+    /// jsonl (one JSON object per file, written line by line once the plan is final),
+    /// or markdown (a table of the files and a summary, e.g. to paste into a ticket)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human, env = "EXPDEL_FORMAT")]
+    format: OutputFormat,
+
+    /// Print a single line for directories where no file will be deleted, instead of listing their files.
+    /// Useful for recursive runs over many directories.
+    #[arg(long, default_value_t = false, env = "EXPDEL_CONDENSE")]
+    condense: bool,
+
+    /// In recursive mode, show the plan as a tree of the directories with the number of files kept
+    /// and to be deleted in each of them, instead of listing every file.
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "recursive",
+        env = "EXPDEL_TREE"
+    )]
+    tree: bool,
+
+    /// Only print the paths of the kept or of the deleted files, one per line, e.g. to pipe them into another tool.
+    /// Cannot be used with --format.
+    #[arg(long, value_enum, value_name = "SET", env = "EXPDEL_LIST")]
+    list: Option<ListChoice>,
+
+    /// End the --list paths with NUL instead of a newline, for xargs -0 and paths containing newlines.
+    #[arg(
+        long = "print0",
+        default_value_t = false,
+        requires = "list",
+        env = "EXPDEL_PRINT0"
+    )]
+    print0: bool,
+
+    /// Show the file times as ages like "5 days ago" instead of dates
+    #[arg(long, default_value_t = false, env = "EXPDEL_RELATIVE_TIME")]
+    relative_time: bool,
+
+    /// Show the paths of the plan relative to the directory given with --path (or listed in --paths-file),
+    /// in every output format. The files are still deleted by their full path.
+    #[arg(long, default_value_t = false, env = "EXPDEL_RELATIVE")]
+    relative: bool,
+
+    /// Color the plan: auto (only on a terminal and without NO_COLOR set), always or never
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto, env = "EXPDEL_COLOR")]
+    color: ColorChoice,
+
+    /// Write the plan to this file instead of the terminal. Prompts and errors are still shown on the terminal.
+    #[arg(long, value_name = "PATH", env = "EXPDEL_OUTPUT_FILE")]
+    output_file: Option<String>,
+
+    /// Start the CSV or JSON plan written with --output-file with a UTF-8 byte order mark,
+    /// so spreadsheet programs like Excel detect the encoding of the file names.
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "output_file",
+        env = "EXPDEL_BOM"
+    )]
+    bom: bool,
+
+    /// Compare the plan with a plan previously saved with --format json
+    #[arg(long, value_name = "PLAN.json", env = "EXPDEL_COMPARE")]
+    compare: Option<String>,
+
+    /// Fail, before deleting anything, unless the kept and deleted files are exactly those of a plan
+    /// previously saved with --format json, and print the differences, e.g. to check a policy in CI.
+    #[arg(long, value_name = "PLAN.json", env = "EXPDEL_ASSERT_PLAN")]
+    assert_plan: Option<String>,
+
+    /// Format of the errors on stderr: human (prose) or json (one object per line with code, message and path)
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = ErrorFormat::Human, env = "EXPDEL_ERROR_FORMAT")]
+    error_format: ErrorFormat,
+
+    /// Summary only mode: print the time segments and a final summary, but not every single file.
+    /// Cannot be used with --quiet or --verbose.
+    #[arg(long, default_value_t = false, env = "EXPDEL_SUMMARY_ONLY")]
+    summary_only: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, Serialize)]
+#[allow(clippy::enum_variant_names)]
 enum SortType {
     MTime,
+    #[default]
     CTime,
     ATime,
 }
 
-macro_rules! println_if_not_quiet {
-    ($quiet:expr, $($arg:tt)*) => {
-        if !$quiet {
-            println!($($arg)*);
+impl str::FromStr for SortType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mtime" | "modified" | "m" => Ok(SortType::MTime),
+            "ctime" | "created" | "c" => Ok(SortType::CTime),
+            "atime" | "accessed" | "a" => Ok(SortType::ATime),
+            _ => Err(format!(
+                "invalid sort type '{}', expected mtime, ctime or atime",
+                s
+            )),
         }
-    };
+    }
 }
 
-fn main() {
-    let args = Args::parse();
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+enum AgeUnit {
+    Hours,
+    #[default]
+    Days,
+    Weeks,
+}
 
-    if args.quiet && args.print_only {
-        eprintln!("Error: --quiet and --print_only cannot be used together.");
-        process::exit(1);
+impl AgeUnit {
+    fn seconds(self) -> u64 {
+        match self {
+            AgeUnit::Hours => 3600,
+            AgeUnit::Days => 86400,
+            AgeUnit::Weeks => 7 * 86400,
+        }
     }
 
-    if args.print_only && args.force {
-        eprintln!("Error: --print_only and --force cannot be used together.");
-        process::exit(1);
+    fn name(self) -> &'static str {
+        match self {
+            AgeUnit::Hours => "hours",
+            AgeUnit::Days => "days",
+            AgeUnit::Weeks => "weeks",
+        }
     }
+}
 
-    let path = path::Path::new(&args.path);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ChecksumAlgorithm {
+    Sha256,
+    Sha512,
+}
 
-    if !path.exists() {
-        eprintln!("Error: The provided path does not exist.");
-        process::exit(1);
-    }
-    if path.is_file() {
-        eprintln!("Error: The provided path is a file, not a directory.");
-        process::exit(1);
+impl ChecksumAlgorithm {
+    /// Hex digest of the file contents
+    fn digest_file(self, file: &path::Path) -> io::Result<String> {
+        let mut input = fs::File::open(file)?;
+        match self {
+            ChecksumAlgorithm::Sha256 => {
+                let mut hasher = sha2::Sha256::new();
+                io::copy(&mut input, &mut hasher)?;
+                Ok(format!("{:x}", hasher.finalize()))
+            }
+            ChecksumAlgorithm::Sha512 => {
+                let mut hasher = sha2::Sha512::new();
+                io::copy(&mut input, &mut hasher)?;
+                Ok(format!("{:x}", hasher.finalize()))
+            }
+        }
     }
+}
 
-    let sort_type = match args.sort.to_lowercase().as_str() {
-        "mtime" => SortType::MTime,
-        "ctime" => SortType::CTime,
-        "atime" => SortType::ATime,
-        _ => {
-            eprintln!("Invalid sort type. Defaulting to ctime.");
-            SortType::CTime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self, is_terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Auto => is_terminal && !no_color,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
         }
-    };
+    }
+}
 
-    let (_to_keep, to_delete) =
-        exp_sort_and_list_to_del(args.quiet, &path, &sort_type, args.keep, args.recursive)
-            .unwrap_or_else(|err| {
-                eprintln!("Error: {}", err);
-                (Vec::new(), Vec::new())
-            });
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ListChoice {
+    Kept,
+    Deleted,
+}
 
-    if !args.force && !args.print_only && !args.quiet && !to_delete.is_empty() {
-        if _to_keep.is_empty() {
-            println!("WARNING! No files will be kept, you want ALL files to be deleted.");
-        }
-        println!("\nDo you want to proceed with deletion? There is no undo. (yes/no)");
-        let mut confirmation = String::new();
-        io::stdin()
-            .read_line(&mut confirmation)
-            .expect("Failed to read line");
-        if confirmation.trim().to_lowercase() != "yes" {
-            println!("Operation cancelled.");
-            return;
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum ErrorFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+/// An error reported on stderr, as prose or as JSON with --error-format json
+#[derive(Debug, Serialize)]
+struct CliError {
+    /// Stable identifier to match on, e.g. "not_a_directory"
+    code: &'static str,
+    message: String,
+    /// The offending file or directory, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<path::PathBuf>,
+    /// Files planned otherwise than in --assert-plan
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    mismatches: Vec<PlanMismatch>,
+}
+
+/// A file planned otherwise than in --assert-plan
+#[derive(Debug, Serialize)]
+struct PlanMismatch {
+    /// "-" for an action of the expected plan, "+" for one of this plan
+    sign: char,
+    action: &'static str,
+    path: path::PathBuf,
+}
+
+impl CliError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        CliError {
+            code,
+            message: message.into(),
+            path: None,
+            mismatches: Vec::new(),
         }
     }
 
-    if !args.print_only {
-        if !to_delete.is_empty() {
-            delete_files(args.quiet, &to_delete).unwrap_or_else(|err| {
-                eprintln!("Error during deletion: {}", err);
-            });
-        } else {
-            println!("No files to delete.");
+    fn with_path(mut self, path: impl AsRef<path::Path>) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    fn with_mismatches(mut self, mismatches: Vec<PlanMismatch>) -> Self {
+        self.mismatches = mismatches;
+        self
+    }
+
+    /// The error, after the mismatches of --assert-plan as "- action path" lines in human format
+    fn to_line(&self, format: ErrorFormat) -> String {
+        match format {
+            ErrorFormat::Human => self
+                .mismatches
+                .iter()
+                .map(|mismatch| {
+                    format!(
+                        "{} {} {}\n",
+                        mismatch.sign,
+                        mismatch.action,
+                        mismatch.path.display()
+                    )
+                })
+                .chain([format!("Error: {}", self.message)])
+                .collect(),
+            ErrorFormat::Json => serde_json::to_string(self).expect("Error is always serializable"),
         }
-    } else {
-        println!("\nPrint-only enabled, no files were deleted.");
     }
-}
 
-fn get_time_type(meta: &fs::Metadata, sort_type: &SortType) -> time::SystemTime {
-    match sort_type {
-        SortType::MTime => meta.modified().unwrap_or_else(|_| time::UNIX_EPOCH),
-        SortType::ATime => meta.accessed().unwrap_or_else(|_| time::UNIX_EPOCH),
-        SortType::CTime => meta.created().unwrap_or_else(|_| time::UNIX_EPOCH),
+    fn print(&self, format: ErrorFormat) {
+        eprintln!("{}", self.to_line(format));
+    }
+
+    /// Prints the error and exits with code 1
+    fn exit(&self, format: ErrorFormat) -> ! {
+        self.exit_with(EXIT_FAILURE, format);
+    }
+
+    /// Prints the error and exits with the code, e.g. EXIT_PARTIAL_FAILURE
+    fn exit_with(&self, code: i32, format: ErrorFormat) -> ! {
+        self.print(format);
+        process::exit(code);
     }
 }
 
-fn group_files_by_bucket(
-    path: &path::Path,
-    sort_type: &SortType,
-) -> io::Result<collections::BTreeMap<u64, Vec<(path::PathBuf, time::SystemTime)>>> {
-    let now = time::SystemTime::now();
-    let mut groups: collections::BTreeMap<u64, Vec<(path::PathBuf, time::SystemTime)>> =
-        collections::BTreeMap::new();
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum OnConflict {
+    Skip,
+    Overwrite,
+    #[default]
+    Rename,
+}
 
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let meta = entry.metadata()?;
-        if !meta.is_file() {
-            continue; // Skip directories and other non-file entries
-        }
-        let file_time = get_time_type(&meta, &sort_type);
-        if let Ok(age) = now.duration_since(file_time) {
-            let days = age.as_secs() / 86400;
-            let bucket = if days == 0 {
-                1
-            } else {
-                1 << (days.checked_ilog2().unwrap() + if days.is_power_of_two() { 0 } else { 1 })
-            };
-            groups
-                .entry(bucket)
-                .or_default()
-                .push((entry.path(), file_time));
+/// Time the file ages are computed from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize)]
+enum AgeReference {
+    #[default]
+    Now,
+    Newest,
+}
+
+/// Handling of the directories that cannot be read in recursive mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum WalkErrors {
+    #[default]
+    Warn,
+    Fail,
+    Ignore,
+}
+
+/// Where --dedupe-scope looks for copies of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+enum DedupeScope {
+    Bucket,
+    Directory,
+    Global,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DeleteOrder {
+    Oldest,
+    Newest,
+}
+
+impl DeleteOrder {
+    /// Sorts the files to delete by their planned time
+    fn sort(
+        self,
+        files: &mut [path::PathBuf],
+        times: &collections::HashMap<&path::Path, time::SystemTime>,
+    ) {
+        files.sort_by_key(|file| times.get(file.as_path()).copied());
+        if self == DeleteOrder::Newest {
+            files.reverse();
         }
     }
-    if groups.is_empty() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "No files found in the directory. Remember that the program only works with files, not directories.",
-        ));
-    }
-    Ok(groups)
 }
 
-fn group_files_by_bucket_recursive(
-    root: &path::Path,
-    sort_type: &SortType,
-) -> io::Result<
-    collections::BTreeMap<
-        path::PathBuf,
-        collections::BTreeMap<u64, Vec<(path::PathBuf, time::SystemTime)>>,
-    >,
-> {
-    let mut all_groups = collections::BTreeMap::new();
-    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
-        if entry.file_type().is_dir() {
-            let dir_path = entry.path();
-            let groups = group_files_by_bucket(dir_path, sort_type)?;
-            if !groups.is_empty() {
-                all_groups.insert(dir_path.to_path_buf(), groups);
-            } else {
-                println_if_not_quiet!(
-                    false,
-                    "Directory {} is empty. Skipping.",
-                    dir_path.display()
-                );
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Human,
+    Json,
+    Csv,
+    Jsonl,
+    Markdown,
+}
+
+/// Line printed for each file of the plan, given with --template
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template(Vec<TemplatePart>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Text(String),
+    Path,
+    Time,
+    Bucket,
+    Action,
+    Size,
+    Owner,
+}
+
+impl Template {
+    /// Line of a file, `shown` being its path as shown in the plan and `time` already formatted
+    fn render(
+        &self,
+        file: &path::Path,
+        shown: &path::Path,
+        time: &str,
+        bucket: u64,
+        action: &str,
+        dereference_size: bool,
+    ) -> String {
+        let mut line = String::new();
+        for part in &self.0 {
+            match part {
+                TemplatePart::Text(text) => line.push_str(text),
+                TemplatePart::Path => line.push_str(&shown.display().to_string()),
+                TemplatePart::Time => line.push_str(time),
+                TemplatePart::Bucket => line.push_str(&bucket_name(bucket)),
+                TemplatePart::Action => line.push_str(action),
+                TemplatePart::Size => line.push_str(&file_size(file, dereference_size).to_string()),
+                TemplatePart::Owner => line.push_str(&file_owner(file).unwrap_or_default()),
             }
         }
+        line
     }
+}
 
-    if all_groups.is_empty() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "No files found in the directory or its subdirectories. Remember that the program only works with files, not directories.",
-        ));
-    }
+/// Increasing upper bounds of the time segments, given with --buckets
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct BucketBoundaries(Vec<u64>);
 
-    Ok(all_groups)
-}
+/// Time segment of the files older than the last custom boundary
+const OLDEST_BUCKET: u64 = u64::MAX;
 
-fn exp_sort_and_list_to_del(
-    quiet: bool,
-    path: &path::Path,
-    sort_type: &SortType,
-    files_to_keep: u32,
-    recursive: bool,
-) -> io::Result<(Vec<path::PathBuf>, Vec<path::PathBuf>)> {
-    if recursive {
-        let all_groups = group_files_by_bucket_recursive(path, sort_type)?;
-        let mut to_keep = Vec::new();
-        let mut to_delete = Vec::new();
-        for (dir, groups) in all_groups {
-            let (keep, delete) =
-                process_groups(quiet, &groups, sort_type, files_to_keep, &dir);
-            to_keep.extend(keep);
-            to_delete.extend(delete);
-        }
-        Ok((to_keep, to_delete))
+/// Time segment as shown in the plan, "oldest" past the last custom boundary
+fn bucket_name(bucket: u64) -> String {
+    if bucket == OLDEST_BUCKET {
+        "oldest".to_string()
     } else {
-        let groups = group_files_by_bucket(path, sort_type)?;
-        Ok(process_groups(quiet, &groups, sort_type, files_to_keep, path))
+        bucket.to_string()
     }
 }
 
-fn delete_files(quiet: bool, files: &[path::PathBuf]) -> io::Result<()> {
-    println_if_not_quiet!(quiet, "\nDeleting files...");
-    for file in files {
-        match fs::remove_file(file) {
-            Ok(_) => println_if_not_quiet!(quiet, "File deleted: {}", file.display()),
-            Err(e) => eprintln!("Error during deletion {}: {}", file.display(), e),
-        }
+impl BucketBoundaries {
+    /// The first boundary the age is below of
+    fn bucket_for(&self, age: u64) -> u64 {
+        self.0
+            .iter()
+            .copied()
+            .find(|boundary| age < *boundary)
+            .unwrap_or(OLDEST_BUCKET)
     }
-    Ok(())
-}
 
-fn process_groups(
-    quiet: bool,
-    groups: &collections::BTreeMap<u64, Vec<(path::PathBuf, time::SystemTime)>>,
-    sort_type: &SortType,
-    files_to_keep: u32,
-    dir: &path::Path,
-) -> (Vec<path::PathBuf>, Vec<path::PathBuf>) {
-    let mut to_keep = Vec::new();
-    let mut to_delete = Vec::new();
-    println_if_not_quiet!(
-        quiet,
-        "\nOpening {}, sorting by {:?} and keeping {} files",
-        dir.display(),
-        sort_type,
-        files_to_keep
-    );
-    for (bucket, files) in groups.iter() {
-        println_if_not_quiet!(
-            quiet,
-            "\nYounger than {} days but older than {} days:",
-            bucket,
-            bucket / 2
-        );
-        let sorted: Vec<_> = files.iter().sorted_by_key(|(_, t)| *t).collect();
-        let split_idx = files_to_keep.min(sorted.len() as u32) as usize;
-        let (keep, delete) = sorted.split_at(split_idx);
-        if delete.is_empty() {
-            println_if_not_quiet!(quiet, "No files to delete in this group.");
-        }
-        for (file, time) in keep {
-            let datetime: chrono::DateTime<chrono::Local> = (*time).into();
-            println_if_not_quiet!(
-                quiet,
-                "{} | {}",
-                file.display(),
-                datetime.format("%Y-%m-%d %H:%M:%S")
-            );
-            to_keep.push(file.clone());
+    /// Youngest and oldest age of the files in a time segment, None for the oldest one
+    fn ages(&self, bucket: u64) -> (u64, Option<u64>) {
+        let last = self.0.last().copied().unwrap_or(0);
+        if bucket == OLDEST_BUCKET {
+            return (last, None);
         }
-        for (file, time) in delete {
-            let datetime: chrono::DateTime<chrono::Local> = (*time).into();
-            println_if_not_quiet!(
-                quiet,
-                "{} | {} <-- to be deleted",
-                file.display(),
-                datetime.format("%Y-%m-%d %H:%M:%S")
-            );
-            to_delete.push(file.clone());
+        let previous = self
+            .0
+            .iter()
+            .copied()
+            .take_while(|boundary| *boundary < bucket)
+            .last()
+            .unwrap_or(0);
+        (previous, Some(bucket - 1))
+    }
+
+    fn label(&self, bucket: u64, age_unit: AgeUnit) -> String {
+        let last = self.0.last().copied().unwrap_or(0);
+        if bucket == OLDEST_BUCKET {
+            return format!("Older than {} {}", last, age_unit.name());
         }
+        let previous = self
+            .0
+            .iter()
+            .copied()
+            .take_while(|boundary| *boundary < bucket)
+            .last()
+            .unwrap_or(0);
+        format!(
+            "Younger than {} {} but older than {} {}",
+            bucket,
+            age_unit.name(),
+            previous,
+            age_unit.name()
+        )
     }
-    (to_keep, to_delete)
 }
 
-    // Unit tests
-#[cfg(test)]
-mod tests {
-        #[cfg(unix)]
-        use std::os::unix::fs::PermissionsExt;
+/// Time segments and the files to keep in each of them, read from --policy-file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct RetentionPolicy {
+    boundaries: BucketBoundaries,
+    /// Files to keep per boundary, then for the oldest time segment
+    keep: Vec<u32>,
+}
 
-    use super::*;
-    use filetime::{FileTime, set_file_times};
-    use gag::BufferRedirect;
-    use rand::Rng;
-    use std::io::Read;
-    use std::io::Write;
-    use std::thread;
-    use tempfile::tempdir;
+impl RetentionPolicy {
+    fn keep_for(&self, bucket: u64) -> u32 {
+        let index = self
+            .boundaries
+            .0
+            .iter()
+            .position(|boundary| *boundary == bucket)
+            .unwrap_or(self.boundaries.0.len());
+        self.keep[index]
+    }
+}
 
-    #[test]
-    fn test_get_time_type() {
-        println!("Testing get_time_type function");
+/// Number of files to keep in directories ending with the given path, with --keep-for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct KeepOverride {
+    dir: path::PathBuf,
+    keep: u32,
+}
 
-        let meta = fs::metadata("Cargo.toml").unwrap();
-        let mtime = get_time_type(&meta, &SortType::MTime);
-        let atime = get_time_type(&meta, &SortType::ATime);
-        let ctime = get_time_type(&meta, &SortType::CTime);
+/// Number of calendar periods to keep the first file of, with --calendar
+#[derive(Debug, Clone, Copy, Serialize)]
+struct CalendarRetention {
+    daily: u32,
+    weekly: u32,
+    monthly: u32,
+}
 
-        assert!(mtime > time::UNIX_EPOCH);
-        assert!(atime > time::UNIX_EPOCH);
-        assert!(ctime > time::UNIX_EPOCH);
+/// Settings deciding which files are kept and which are deleted
+#[derive(Debug, Default)]
+struct PlanOptions {
+    sort_type: SortType,
+    /// Pattern extracting the file date from its name, used instead of `sort_type` when it matches
+    date_from_name: Option<regex::Regex>,
+    /// Unit of the file ages the time segments are based on
+    age_unit: AgeUnit,
+    /// Time the file ages are computed from
+    age_reference: AgeReference,
+    /// Custom time segments, instead of powers of two
+    boundaries: Option<BucketBoundaries>,
+    /// Print the ages held by the time segments of each directory
+    explain: bool,
+    /// Time segments with fewer files are merged into the next older one
+    merge_below: usize,
+    /// Delete the older copies of identical files first
+    dedupe_scope: Option<DedupeScope>,
+    /// Resolution to round the file times down to, with --round-times
+    time_resolution: Option<time::Duration>,
+    /// Files to keep per time segment
+    keep: u32,
+    /// Files to keep per time segment in matching directories, instead of `keep`
+    keep_for: Vec<KeepOverride>,
+    /// Files to keep in each custom time segment, overrides `keep` and `keep_for`
+    policy: Option<RetentionPolicy>,
+    /// Files to keep per directory across all time segments, overrides `keep`
+    keep_total: Option<usize>,
+    /// Percentage of the files to keep per time segment, overrides `keep`
+    keep_percent: Option<u32>,
+    /// Files kept in every time segment, whatever the other settings
+    bucket_keep_min: u32,
+    /// Keep all the files of the youngest time segment
+    protect_first_bucket: bool,
+    /// Calendar periods to keep one file for, overrides `keep` and `keep_total`
+    calendar: Option<CalendarRetention>,
+    /// Bytes the files of a directory may take, overrides `keep` and `keep_total`
+    target_size: Option<u64>,
+    /// Count symlinks as the size of their target
+    dereference_size: bool,
+    /// Process each file extension separately
+    group_by_extension: bool,
+    /// Compare file extensions exactly instead of ignoring their case
+    case_sensitive_ext: bool,
+    /// Files matching any of these are kept, even if their time segment would delete them
+    protect: Vec<glob::Pattern>,
+    /// Files matching any of these are kept first within their time segment
+    prefer_keep: Vec<glob::Pattern>,
+    /// Lowercase extensions of the only files to process, all files if empty
+    extensions: Vec<String>,
+    /// Keep symlink targets and hard links of kept files
+    keep_linked: bool,
+    recursive: bool,
+    /// Directories scanned at the same time in recursive mode
+    dir_jobs: u16,
+    /// Files of a directory whose metadata is read at the same time
+    stat_jobs: u16,
+    /// Directories not to scan again, with --skip-unchanged-dirs
+    dir_cache: Option<DirCache>,
+    /// Plan the empty directories like files
+    include_empty_dirs: bool,
+    /// Leave hidden files out, and do not descend into hidden directories in recursive mode
+    skip_hidden: bool,
+    /// Do not descend into other filesystems in recursive mode
+    same_filesystem: bool,
+    /// Stat the directory entries in inode order
+    inode_sort: bool,
+    /// Show the owner and mode of the files in the plan
+    show_owner: bool,
+    /// Custom line for each file of the plan
+    template: Option<Template>,
+    /// Go through the time segments oldest first
+    reverse: bool,
+    /// Directories with fewer files are skipped in recursive mode
+    min_files: usize,
+    /// Abort instead of skipping entries whose metadata cannot be read
+    stat_errors_fatal: bool,
+    /// Unreadable directories in recursive mode
+    walk_errors: WalkErrors,
+    /// Files already renamed with this --mark suffix are skipped
+    mark: Option<String>,
+}
+
+impl PlanOptions {
+    /// Time segment of a file with the given age in `age_unit`
+    fn bucket_for(&self, age: u64) -> u64 {
+        match &self.boundaries {
+            Some(boundaries) => boundaries.bucket_for(age),
+            None => bucket_for_age(age),
+        }
     }
 
-    #[test]
-    fn test_listing_simple() {
-        println!("Testing a normal directory structure");
+    fn bucket_label(&self, bucket: u64) -> String {
+        match &self.boundaries {
+            Some(boundaries) => boundaries.label(bucket, self.age_unit),
+            None => bucket_label(bucket, self.age_unit),
+        }
+    }
 
-        let dir = tempdir().unwrap();
-        let mut rng = rand::rng();
+    /// Youngest and oldest age in `age_unit` of the files in a time segment, None for no limit
+    fn bucket_ages(&self, bucket: u64) -> (u64, Option<u64>) {
+        match &self.boundaries {
+            Some(boundaries) => boundaries.ages(bucket),
+            None if bucket == 1 => (0, Some(1)),
+            None => (bucket / 2 + 1, Some(bucket)),
+        }
+    }
 
-        for i in 0..500 {
-            let file_path = dir.path().join(format!("file{}.txt", i));
-            let mut file = fs::File::create(&file_path).unwrap();
+    /// Line of --explain describing the ages held by a time segment
+    fn explain_bucket(&self, bucket: u64) -> String {
+        let unit = self.age_unit.name();
+        match self.bucket_ages(bucket) {
+            (youngest, Some(oldest)) if youngest == oldest => {
+                format!("Bucket {}: files aged {} {}", bucket, youngest, unit)
+            }
+            (youngest, Some(oldest)) => format!(
+                "Bucket {}: files aged {}-{} {}, spanning {} {}",
+                bucket,
+                youngest,
+                oldest,
+                unit,
+                oldest - youngest + 1,
+                unit
+            ),
+            (youngest, None) => format!("Oldest bucket: files aged {} {} or more", youngest, unit),
+        }
+    }
+
+    /// Files to keep in a time segment of the directory
+    fn keep_in(&self, dir: &path::Path, bucket: u64) -> u32 {
+        if let Some(policy) = &self.policy {
+            return policy.keep_for(bucket);
+        }
+        self.keep_for
+            .iter()
+            .find(|keep_override| dir.ends_with(&keep_override.dir))
+            .map_or(self.keep, |keep_override| keep_override.keep)
+    }
+}
+
+/// Modification times of the directories scanned in recursive mode, with --skip-unchanged-dirs
+#[derive(Debug, Default)]
+struct DirCache {
+    /// Recorded by the last run
+    previous: DirMtimes,
+    /// Recorded by this run, for the next one
+    current: sync::Mutex<DirMtimes>,
+}
+
+impl DirCache {
+    /// Whether the directory was not modified since the last run, recording its time for the next one otherwise.
+    /// Directories are recorded by their canonical path, the same from any working directory.
+    fn unchanged(&self, dir: &path::Path) -> bool {
+        let Ok(mtime) = fs::metadata(dir).and_then(|meta| meta.modified()) else {
+            return false;
+        };
+        let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        if self.previous.get(&dir) == Some(&mtime) {
+            return true;
+        }
+        self.current
+            .lock()
+            .expect("Directory cache poisoned")
+            .insert(dir, mtime);
+        false
+    }
+
+    /// The recorded times, updated with those of the directories modified since the last run
+    fn merged(&self) -> DirMtimes {
+        let mut merged = self.previous.clone();
+        merged.extend(
+            self.current
+                .lock()
+                .expect("Directory cache poisoned")
+                .drain(),
+        );
+        merged
+    }
+}
+
+/// Settings deciding how the planned files are deleted
+#[derive(Debug, Default)]
+struct DeleteOptions {
+    /// Retries of a deletion failing with a transient error
+    retries: u32,
+    retry_delay: time::Duration,
+    /// Only count the failed deletions, without printing each of them
+    quiet_errors: bool,
+    /// Journal to mark the deleted files in, with --journal
+    journal: Option<path::PathBuf>,
+    /// Move the files to the trash instead of deleting them
+    recycle: bool,
+    /// Rename the files with this suffix instead of deleting them
+    mark: Option<String>,
+    /// What to do when the marked name is taken
+    on_conflict: OnConflict,
+    /// Move the files to the quarantine folder of this day instead of deleting them
+    quarantine: Option<chrono::NaiveDate>,
+    /// Check that each deleted file is gone
+    verify: bool,
+    /// Stop deleting at this time, with --max-runtime
+    deadline: Option<time::Instant>,
+    /// Format of the error printed for each failed deletion
+    error_format: ErrorFormat,
+    /// Count the freed bytes of a symlink as the size of its target
+    dereference_size: bool,
+}
+
+/// How much output the program produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    Quiet,
+    Summary,
+    Normal,
+    Verbose,
+}
+
+impl Verbosity {
+    fn from_flags(quiet: bool, summary_only: bool, verbose: bool) -> Self {
+        if quiet {
+            Verbosity::Quiet
+        } else if summary_only {
+            Verbosity::Summary
+        } else if verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
+
+    /// Prints a regular message, suppressed in quiet mode
+    fn info(self, args: fmt::Arguments) {
+        if self >= Verbosity::Summary {
+            println!("{}", args);
+        }
+    }
+
+    /// Prints a line about a single file, suppressed in quiet and summary only modes
+    fn listing(self, args: fmt::Arguments) {
+        if self >= Verbosity::Normal {
+            println!("{}", args);
+        }
+    }
+
+    /// Prints an additional message, shown only in verbose mode
+    fn detail(self, args: fmt::Arguments) {
+        if self >= Verbosity::Verbose {
+            println!("{}", args);
+        }
+    }
+}
+
+/// Exit code of an error stopping the run before any deletion
+const EXIT_FAILURE: i32 = 1;
+
+/// Exit code used when the deletion was interrupted with Ctrl-C
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Exit code used when some of the files could not be deleted
+const EXIT_PARTIAL_FAILURE: i32 = 2;
+
+/// Exit code used when the deletion stopped at the --max-runtime deadline
+const EXIT_TIME_BUDGET: i32 = 3;
+
+/// Exit code of a --print-only run that would delete files, with --exit-nonzero-if-deletions
+const EXIT_WOULD_DELETE: i32 = 4;
+
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+/// Lock file taken in each target directory with --lock
+const LOCK_FILE_NAME: &str = ".expdel.lock";
+
+/// Folder of each directory the files are moved to with --quarantine-days
+const QUARANTINE_DIR_NAME: &str = ".expdel-quarantine";
+
+/// Byte order mark written at the start of the output file with --bom
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Number of JSON lines written between flushes of the plan with --format jsonl
+const JSONL_FLUSH_INTERVAL: usize = 1000;
+
+/// Set by the Ctrl-C handler, checked between deletions
+static INTERRUPTED: atomic::AtomicBool = atomic::AtomicBool::new(false);
+
+/// Outcome of deleting the planned files
+#[derive(Debug, Default)]
+struct DeletionReport {
+    deleted: usize,
+    freed_bytes: u64,
+    failed: usize,
+    /// Stopped at the --max-runtime deadline
+    timed_out: bool,
+    /// Failed deletions per kind of error
+    errors: collections::HashMap<io::ErrorKind, usize>,
+    /// Each failed deletion with its error
+    failures: Vec<(path::PathBuf, String)>,
+    /// Files left in place as their marked name was taken, with --on-conflict skip
+    conflicts: usize,
+    interrupted: bool,
+}
+
+/// Logs the run to syslog with --syslog, ignoring messages that cannot be sent
+struct Syslog {
+    #[cfg(unix)]
+    logger: Option<syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>>,
+}
+
+impl Syslog {
+    #[cfg(unix)]
+    fn connect(enabled: bool) -> Syslog {
+        let formatter = syslog::Formatter3164 {
+            hostname: None,
+            process: "expdel".to_string(),
+            ..Default::default()
+        };
+        let logger = enabled.then(|| syslog::unix(formatter)).and_then(|logger| {
+            logger
+                .map_err(|err| eprintln!("Warning: could not connect to syslog: {}", err))
+                .ok()
+        });
+        Syslog { logger }
+    }
+
+    #[cfg(not(unix))]
+    fn connect(enabled: bool) -> Syslog {
+        if enabled {
+            eprintln!("Warning: --syslog is only available on Unix.");
+        }
+        Syslog {}
+    }
+
+    fn info(&mut self, message: &str) {
+        #[cfg(unix)]
+        if let Some(logger) = &mut self.logger {
+            let _ = logger.info(message);
+        }
+        #[cfg(not(unix))]
+        let _ = message;
+    }
+
+    fn warning(&mut self, message: &str) {
+        #[cfg(unix)]
+        if let Some(logger) = &mut self.logger {
+            let _ = logger.warning(message);
+        }
+        #[cfg(not(unix))]
+        let _ = message;
+    }
+
+    fn err(&mut self, message: &str) {
+        #[cfg(unix)]
+        if let Some(logger) = &mut self.logger {
+            let _ = logger.err(message);
+        }
+        #[cfg(not(unix))]
+        let _ = message;
+    }
+}
+
+/// Printed at the end of the run with --json-summary
+#[derive(Debug, Serialize)]
+struct RunSummary {
+    kept: usize,
+    kept_bytes: u64,
+    to_delete: usize,
+    to_delete_bytes: u64,
+    deleted: usize,
+    freed_bytes: u64,
+    failed: usize,
+    skipped: usize,
+    print_only: bool,
+    duration_seconds: f64,
+}
+
+/// A directory and its time segments, printed with --dump-buckets
+#[derive(Debug, Serialize)]
+struct DirectoryBuckets {
+    path: path::PathBuf,
+    age_unit: &'static str,
+    buckets: Vec<BucketDump>,
+}
+
+/// A time segment of a directory, printed with --dump-buckets
+#[derive(Debug, Serialize)]
+struct BucketDump {
+    /// Upper bound of the ages held, None for the segment older than the last custom boundary
+    boundary: Option<u64>,
+    label: String,
+    youngest: u64,
+    /// None for no limit
+    oldest: Option<u64>,
+    files: usize,
+    bytes: u64,
+    paths: Vec<path::PathBuf>,
+}
+
+/// A line of the history file, appended after every deletion
+#[derive(Debug, Serialize)]
+struct HistoryRecord {
+    timestamp: String,
+    path: path::PathBuf,
+    deleted: usize,
+    freed_bytes: u64,
+    /// Digests of the deleted files, with --record-checksum
+    #[serde(skip_serializing_if = "collections::BTreeMap::is_empty")]
+    checksums: collections::BTreeMap<path::PathBuf, String>,
+}
+
+/// Whether a planned file is kept or deleted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Keep,
+    Delete,
+}
+
+/// A file considered by the plan, along with the time segment it fell into
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PlannedFile {
+    path: path::PathBuf,
+    time: time::SystemTime,
+    bucket: u64,
+    action: Action,
+    /// Digest of a file to be deleted, with --record-checksum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+}
+
+/// Why an entry was left out of the plan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SkipReason {
+    Directory,
+    Symlink,
+    NotRegularFile,
+    /// Hidden directory, with --skip-hidden
+    Hidden,
+    /// Directory on another filesystem, with --same-filesystem
+    OtherFilesystem,
+    /// Directory with fewer files than --min-files
+    TooFewFiles,
+    /// The file time is in the future, so it has no age
+    FutureTime,
+    /// The metadata could not be read
+    StatError(String),
+    /// Lock file of a running ExpDel, with --lock
+    LockFile,
+    /// File without one of the --ext extensions
+    OtherExtension,
+    /// File already renamed with the --mark suffix
+    Marked,
+    /// Hidden file, with --skip-hidden
+    HiddenFile,
+    /// Folder of the files quarantined with --quarantine-days
+    Quarantine,
+    /// Directory not modified since the last run, with --skip-unchanged-dirs
+    Unchanged,
+    /// Directory that could not be read in recursive mode
+    WalkError(String),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReason::Directory => write!(f, "not a file (directory)"),
+            SkipReason::Symlink => write!(f, "not a file (symlink)"),
+            SkipReason::NotRegularFile => write!(f, "not a file"),
+            SkipReason::Hidden => write!(f, "hidden directory"),
+            SkipReason::OtherFilesystem => write!(f, "on another filesystem"),
+            SkipReason::TooFewFiles => write!(f, "fewer files than --min-files"),
+            SkipReason::FutureTime => write!(f, "file time is in the future"),
+            SkipReason::StatError(err) => write!(f, "could not read metadata: {}", err),
+            SkipReason::LockFile => write!(f, "ExpDel lock file"),
+            SkipReason::OtherExtension => write!(f, "extension not selected with --ext"),
+            SkipReason::Marked => write!(f, "already marked with --mark"),
+            SkipReason::HiddenFile => write!(f, "hidden file"),
+            SkipReason::Quarantine => write!(f, "ExpDel quarantine folder"),
+            SkipReason::Unchanged => write!(f, "directory unchanged since the last run"),
+            SkipReason::WalkError(err) => write!(f, "could not read directory: {}", err),
+        }
+    }
+}
+
+/// An entry left out of the plan, and why
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SkippedEntry {
+    path: path::PathBuf,
+    reason: SkipReason,
+}
+
+impl SkippedEntry {
+    fn new(path: path::PathBuf, reason: SkipReason) -> Self {
+        SkippedEntry { path, reason }
+    }
+}
+
+/// Result of planning, with the files in the order they were listed
+#[derive(Debug, Default)]
+struct Plan {
+    files: Vec<PlannedFile>,
+    skipped: Vec<SkippedEntry>,
+}
+
+impl Plan {
+    fn paths(&self, action: Action) -> Vec<path::PathBuf> {
+        self.files
+            .iter()
+            .filter(|file| file.action == action)
+            .map(|file| file.path.clone())
+            .collect()
+    }
+
+    /// Flattens the plan into the files to keep and the files to delete
+    fn split(&self) -> (Vec<path::PathBuf>, Vec<path::PathBuf>) {
+        (self.paths(Action::Keep), self.paths(Action::Delete))
+    }
+
+    /// Records when the planned files were first seen, and keeps the files to delete first seen less than
+    /// `grace` before `now`. Returns the kept files.
+    fn keep_recently_seen(
+        &mut self,
+        first_seen: &mut FirstSeen,
+        now: time::SystemTime,
+        grace: time::Duration,
+    ) -> Vec<path::PathBuf> {
+        let now_secs = now
+            .duration_since(time::UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or(0);
+        let mut kept = Vec::new();
+        for file in &mut self.files {
+            let key = fs::canonicalize(&file.path).unwrap_or_else(|_| file.path.clone());
+            let seen = *first_seen.entry(key).or_insert(now_secs);
+            if file.action == Action::Delete && now_secs.saturating_sub(seen) < grace.as_secs() {
+                file.action = Action::Keep;
+                kept.push(file.path.clone());
+            }
+        }
+        kept
+    }
+
+    /// Keeps all but the oldest `max` files to delete, returns how many files were to be deleted
+    fn cap_deletions(&mut self, max: usize) -> usize {
+        let mut to_delete: Vec<_> = self
+            .files
+            .iter_mut()
+            .filter(|file| file.action == Action::Delete)
+            .collect();
+        let eligible = to_delete.len();
+        to_delete.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.path.cmp(&b.path)));
+        for file in to_delete.into_iter().skip(max) {
+            file.action = Action::Keep;
+        }
+        eligible
+    }
+
+    /// Keeps the files to delete left once the oldest ones add up to `bytes`, returns how many files were to be deleted
+    fn cap_freed(&mut self, bytes: u64, dereference_size: bool) -> usize {
+        let mut to_delete: Vec<_> = self
+            .files
+            .iter_mut()
+            .filter(|file| file.action == Action::Delete)
+            .collect();
+        let eligible = to_delete.len();
+        to_delete.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.path.cmp(&b.path)));
+        let mut freed = 0;
+        for file in to_delete {
+            if freed >= bytes {
+                file.action = Action::Keep;
+            } else {
+                freed += file_size(&file.path, dereference_size);
+            }
+        }
+        eligible
+    }
+}
+
+/// The plan as printed with --format json
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedPlan {
+    keep: Vec<path::PathBuf>,
+    delete: Vec<path::PathBuf>,
+    /// Every file with its time segment, missing in plans saved by older versions
+    #[serde(default)]
+    files: Vec<PlannedFile>,
+    /// Entries left out of the plan
+    #[serde(default)]
+    skipped: Vec<SkippedEntry>,
+}
+
+/// Differences between a previously saved plan and the current one
+#[derive(Debug, Default)]
+struct PlanDiff {
+    /// Files to be deleted now, which were not to be deleted before
+    newly_deleted: Vec<path::PathBuf>,
+    /// Files to be deleted before, which are kept now
+    no_longer_deleted: Vec<path::PathBuf>,
+}
+
+/// Destination of the plan, either the terminal or the file given with --output-file
+struct Report {
+    verbosity: Verbosity,
+    out: Box<dyn io::Write>,
+    /// Color the kept and deleted files
+    color: bool,
+    /// Show the file times relative to this time, with --relative-time
+    relative_to: Option<time::SystemTime>,
+    /// Show the paths relative to these directories, with --relative
+    relative_roots: Vec<path::PathBuf>,
+    /// Print one line for directories without files to delete
+    condense: bool,
+}
+
+impl Report {
+    fn stdout(verbosity: Verbosity) -> Self {
+        Report {
+            verbosity,
+            out: Box::new(io::stdout()),
+            color: false,
+            relative_to: None,
+            relative_roots: Vec::new(),
+            condense: false,
+        }
+    }
+
+    /// Report written to a file, starting with a UTF-8 byte order mark if `bom`
+    fn file(verbosity: Verbosity, file_path: &path::Path, bom: bool) -> io::Result<Self> {
+        let mut out = io::BufWriter::new(fs::File::create(file_path)?);
+        if bom {
+            out.write_all(UTF8_BOM)?;
+        }
+        Ok(Report {
+            verbosity,
+            out: Box::new(out),
+            color: false,
+            relative_to: None,
+            relative_roots: Vec::new(),
+            condense: false,
+        })
+    }
+
+    /// Writes a line regardless of verbosity, e.g. the JSON plan
+    fn line(&mut self, args: fmt::Arguments) {
+        writeln!(self.out, "{}", args).expect("Failed to write the plan");
+    }
+
+    /// Writes a regular line, suppressed in quiet mode
+    fn info(&mut self, args: fmt::Arguments) {
+        if self.verbosity >= Verbosity::Summary {
+            self.line(args);
+        }
+    }
+
+    /// Writes a line about a single file, suppressed in quiet and summary only modes
+    fn listing(&mut self, args: fmt::Arguments) {
+        if self.verbosity >= Verbosity::Normal {
+            self.line(args);
+        }
+    }
+
+    /// Wraps the text in an ANSI color, if colors are enabled
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Path of a file as shown in the plan, relative to the deepest of `relative_roots` holding it
+    fn shown_path<'a>(&self, file: &'a path::Path) -> &'a path::Path {
+        match self
+            .relative_roots
+            .iter()
+            .filter_map(|root| file.strip_prefix(root).ok())
+            .min_by_key(|relative| relative.components().count())
+        {
+            Some(relative) if relative.as_os_str().is_empty() => path::Path::new("."),
+            Some(relative) => relative,
+            None => file,
+        }
+    }
+
+    /// The plan with the paths as shown, None if they are shown unchanged
+    fn shown_plan(&self, plan: &SavedPlan) -> Option<SavedPlan> {
+        if self.relative_roots.is_empty() {
+            return None;
+        }
+        let shown = |files: &[path::PathBuf]| {
+            files
+                .iter()
+                .map(|file| self.shown_path(file).to_path_buf())
+                .collect()
+        };
+        Some(SavedPlan {
+            keep: shown(&plan.keep),
+            delete: shown(&plan.delete),
+            files: plan
+                .files
+                .iter()
+                .map(|file| PlannedFile {
+                    path: self.shown_path(&file.path).to_path_buf(),
+                    ..file.clone()
+                })
+                .collect(),
+            skipped: plan
+                .skipped
+                .iter()
+                .map(|entry| {
+                    SkippedEntry::new(
+                        self.shown_path(&entry.path).to_path_buf(),
+                        entry.reason.clone(),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// Formats the time of a file, as a date or relative to `relative_to`
+    fn timestamp(&self, time: time::SystemTime) -> String {
+        match self.relative_to {
+            Some(now) => relative_time(now.duration_since(time).unwrap_or_default()),
+            None => {
+                let datetime: chrono::DateTime<chrono::Local> = time.into();
+                datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+            }
+        }
+    }
+
+    /// Writes an additional line, shown only in verbose mode
+    fn detail(&mut self, args: fmt::Arguments) {
+        if self.verbosity >= Verbosity::Verbose {
+            self.line(args);
+        }
+    }
+
+    fn flush(&mut self) {
+        self.out.flush().expect("Failed to write the plan");
+    }
+}
+
+type Buckets = collections::BTreeMap<u64, Vec<(path::PathBuf, time::SystemTime)>>;
+
+fn main() {
+    let started = time::Instant::now();
+    let mut args = Args::parse();
+    let error_format = args.error_format;
+
+    // A silent dry run prints only the JSON summary, e.g. to monitor whether a cleanup is due
+    args.json_summary |= args.quiet && args.print_only;
+
+    if args.print_only && args.force {
+        CliError::new(
+            "conflicting_options",
+            "--print_only and --force cannot be used together.",
+        )
+        .exit(error_format);
+    }
+
+    if args.quiet && args.verbose {
+        CliError::new(
+            "conflicting_options",
+            "--quiet and --verbose cannot be used together.",
+        )
+        .exit(error_format);
+    }
+
+    if args.keep.is_some() && args.keep_total.is_some() {
+        CliError::new(
+            "conflicting_options",
+            "--keep and --keep-total cannot be used together.",
+        )
+        .exit(error_format);
+    }
+
+    if args.keep_percent.is_some()
+        && (args.keep.is_some()
+            || args.keep_total.is_some()
+            || args.calendar
+            || args.target_size.is_some())
+    {
+        CliError::new(
+            "conflicting_options",
+            "--keep-percent cannot be used with --keep, --keep-total, --calendar or --target-size.",
+        )
+        .exit(error_format);
+    }
+
+    if args.policy_file.is_some()
+        && (args.buckets.is_some()
+            || args.keep.is_some()
+            || !args.keep_for.is_empty()
+            || args.keep_total.is_some()
+            || args.keep_percent.is_some()
+            || args.calendar
+            || args.target_size.is_some())
+    {
+        CliError::new(
+            "conflicting_options",
+            "--policy-file cannot be used with --buckets, --keep, --keep-for, --keep-total, --keep-percent, --calendar or --target-size.",
+        )
+        .exit(error_format);
+    }
+
+    if args.quarantine_days.is_some() && (args.recycle || args.mark.is_some()) {
+        CliError::new(
+            "conflicting_options",
+            "--quarantine-days cannot be used with --recycle or --mark.",
+        )
+        .exit(error_format);
+    }
+
+    if args.mark.is_some() && args.recycle {
+        CliError::new(
+            "conflicting_options",
+            "--mark cannot be used with --recycle.",
+        )
+        .exit(error_format);
+    }
+
+    if args.calendar && (args.keep.is_some() || args.keep_total.is_some()) {
+        CliError::new(
+            "conflicting_options",
+            "--calendar cannot be used with --keep or --keep-total.",
+        )
+        .exit(error_format);
+    }
+
+    if args.target_size.is_some()
+        && (args.keep.is_some() || args.keep_total.is_some() || args.calendar)
+    {
+        CliError::new(
+            "conflicting_options",
+            "--target-size cannot be used with --keep, --keep-total or --calendar.",
+        )
+        .exit(error_format);
+    }
+
+    if args.count_only && args.force {
+        CliError::new(
+            "conflicting_options",
+            "--count-only and --force cannot be used together.",
+        )
+        .exit(error_format);
+    }
+
+    if args.histogram && args.force {
+        CliError::new(
+            "conflicting_options",
+            "--histogram and --force cannot be used together.",
+        )
+        .exit(error_format);
+    }
+
+    if args.dump_buckets && args.force {
+        CliError::new(
+            "conflicting_options",
+            "--dump-buckets and --force cannot be used together.",
+        )
+        .exit(error_format);
+    }
+
+    if args.benchmark && args.force {
+        CliError::new(
+            "conflicting_options",
+            "--benchmark and --force cannot be used together.",
+        )
+        .exit(error_format);
+    }
+
+    if args.check_deletable && (args.force || args.quiet) {
+        CliError::new(
+            "conflicting_options",
+            "--check-deletable cannot be used with --force or --quiet.",
+        )
+        .exit(error_format);
+    }
+    args.print_only |= args.check_deletable; // Checking never deletes anything
+
+    if args.summary_only && (args.quiet || args.verbose) {
+        CliError::new(
+            "conflicting_options",
+            "--summary-only cannot be used with --quiet or --verbose.",
+        )
+        .exit(error_format);
+    }
+
+    if args.list.is_some() && args.format != OutputFormat::Human {
+        CliError::new(
+            "conflicting_options",
+            "--list and --format cannot be used together.",
+        )
+        .exit(error_format);
+    }
+
+    // The JSON or CSV plan, or the --list paths, replace all regular output
+    let verbosity = if (args.format != OutputFormat::Human && args.output_file.is_none())
+        || args.list.is_some()
+    {
+        Verbosity::Quiet
+    } else {
+        Verbosity::from_flags(args.quiet, args.summary_only, args.verbose)
+    };
+
+    if args.resume {
+        resume(&args, verbosity);
+        return;
+    }
+
+    // The plan goes to the output file in full, even in quiet mode
+    let report_verbosity = match (args.format, &args.output_file) {
+        (OutputFormat::Human, Some(_)) => {
+            Verbosity::from_flags(false, args.summary_only, args.verbose)
+        }
+        (OutputFormat::Human, None) => verbosity,
+        _ => Verbosity::Quiet,
+    };
+    let mut report = match &args.output_file {
+        Some(output_file) => Report::file(
+            report_verbosity,
+            path::Path::new(output_file),
+            args.bom && matches!(args.format, OutputFormat::Csv | OutputFormat::Json),
+        )
+        .unwrap_or_else(|err| {
+            CliError::new(
+                "output_file",
+                format!("Could not create the output file {}: {}", output_file, err),
+            )
+            .with_path(output_file)
+            .exit(error_format)
+        }),
+        None => Report::stdout(report_verbosity),
+    };
+    report.relative_to = args.relative_time.then(time::SystemTime::now);
+    report.condense = args.condense;
+    report.color = args.color.enabled(
+        args.output_file.is_none() && io::stdout().is_terminal(),
+        env::var_os("NO_COLOR").is_some_and(|no_color| !no_color.is_empty()),
+    );
+
+    let previous_plan = args.compare.as_ref().map(|plan_path| {
+        load_plan(path::Path::new(plan_path)).unwrap_or_else(|err| {
+            CliError::new(
+                "load_plan",
+                format!("Could not load the plan {}: {}", plan_path, err),
+            )
+            .with_path(plan_path)
+            .exit(error_format)
+        })
+    });
+
+    let expected_plan = args.assert_plan.as_ref().map(|plan_path| {
+        load_plan(path::Path::new(plan_path)).unwrap_or_else(|err| {
+            CliError::new(
+                "load_plan",
+                format!("Could not load the plan {}: {}", plan_path, err),
+            )
+            .with_path(plan_path)
+            .exit(error_format)
+        })
+    });
+
+    let mut targets: Vec<path::PathBuf> = args.path.iter().map(path::PathBuf::from).collect();
+    if let Some(paths_file) = &args.paths_file {
+        targets.extend(
+            read_paths_file(path::Path::new(paths_file)).unwrap_or_else(|err| {
+                CliError::new(
+                    "paths_file",
+                    format!("Could not read the paths file {}: {}", paths_file, err),
+                )
+                .with_path(paths_file)
+                .exit(error_format)
+            }),
+        );
+    }
+    if targets.is_empty() {
+        CliError::new("no_directories", "No directories to process.").exit(error_format);
+    }
+    for path in &mut targets {
+        if !path.exists() {
+            CliError::new(
+                "path_not_found",
+                format!("The provided path does not exist: {}", path.display()),
+            )
+            .with_path(path)
+            .exit(error_format);
+        }
+        if path.is_file() && args.infer_dir {
+            *path = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => path::PathBuf::from("."),
+            };
+        } else if path.is_file() {
+            CliError::new(
+                "not_a_directory",
+                format!(
+                    "The provided path is a file, not a directory: {}",
+                    path.display()
+                ),
+            )
+            .with_path(path)
+            .exit(error_format);
+        }
+    }
+
+    if let Some(threshold) = args.when_above {
+        targets = targets_above(&mut report, targets, threshold, disk_usage_percent)
+            .unwrap_or_else(|(path, err)| {
+                CliError::new(
+                    "disk_usage",
+                    format!(
+                        "Could not read the disk usage of {}: {}",
+                        path.display(),
+                        err
+                    ),
+                )
+                .with_path(path)
+                .exit(error_format)
+            });
+        if targets.is_empty() {
+            report.info(format_args!(
+                "Nothing to do, disk usage is below {}%.",
+                threshold
+            ));
+            return;
+        }
+    }
+    if let Some(threshold) = args.when_inodes_below {
+        targets = targets_inodes_below(&mut report, targets, threshold, free_inodes_percent)
+            .unwrap_or_else(|(path, err)| {
+                CliError::new(
+                    "inode_usage",
+                    format!(
+                        "Could not read the free inodes of {}: {}",
+                        path.display(),
+                        err
+                    ),
+                )
+                .with_path(path)
+                .exit(error_format)
+            });
+        if targets.is_empty() {
+            report.info(format_args!(
+                "Nothing to do, more than {}% of the inodes are free.",
+                threshold
+            ));
+            return;
+        }
+    }
+
+    let mut syslog = Syslog::connect(args.syslog);
+    syslog.info(&format!(
+        "Starting on {}",
+        targets.iter().map(|path| path.display()).join(", ")
+    ));
+
+    // Held until the process exits, i.e. after the deletion
+    let _locks: Vec<fs::File> = match &args.lock {
+        Some(Some(lock_path)) => vec![path::PathBuf::from(lock_path)],
+        Some(None) => targets
+            .iter()
+            .map(|path| path.join(LOCK_FILE_NAME))
+            .collect(),
+        None => Vec::new(),
+    }
+    .iter()
+    .map(|lock_path| {
+        acquire_lock(lock_path).unwrap_or_else(|err| {
+            CliError::new(
+                "lock",
+                format!("Could not lock {}: {}", lock_path.display(), err),
+            )
+            .with_path(lock_path)
+            .exit(error_format)
+        })
+    })
+    .collect();
+
+    let policy = args.policy_file.as_ref().map(|policy_file| {
+        read_policy_file(path::Path::new(policy_file)).unwrap_or_else(|err| {
+            CliError::new(
+                "policy_file",
+                format!("Could not load the policy file {}: {}", policy_file, err),
+            )
+            .with_path(policy_file)
+            .exit(error_format)
+        })
+    });
+
+    // Coarse timestamps, e.g. on FAT, make files with close times tie
+    let mut time_resolution = time::Duration::ZERO;
+    for path in &targets {
+        let Some(resolution) = timestamp_resolution(path) else {
+            continue;
+        };
+        if resolution >= time::Duration::from_secs(1) && !args.round_times {
+            eprintln!(
+                "Warning: {} only stores file times to {} s, files with close times may be sorted arbitrarily. \
+                 Use --round-times to round all times to that resolution.",
+                path.display(),
+                resolution.as_secs()
+            );
+        }
+        time_resolution = time_resolution.max(resolution);
+    }
+
+    let options = PlanOptions {
+        sort_type: args.sort,
+        date_from_name: args.date_from_name,
+        age_unit: args.age_unit,
+        age_reference: args.age_relative_to,
+        boundaries: match &policy {
+            Some(policy) => Some(policy.boundaries.clone()),
+            None => args.buckets,
+        },
+        policy,
+        explain: args.explain,
+        merge_below: args.merge_below,
+        dedupe_scope: args.dedupe_scope,
+        time_resolution: (args.round_times && !time_resolution.is_zero())
+            .then_some(time_resolution),
+        keep: args.keep.unwrap_or(0),
+        keep_for: args.keep_for,
+        keep_total: args.keep_total,
+        keep_percent: args.keep_percent,
+        bucket_keep_min: args.bucket_keep_min,
+        protect_first_bucket: args.protect_first_bucket,
+        target_size: args.target_size,
+        dereference_size: args.dereference_size,
+        calendar: args.calendar.then_some(CalendarRetention {
+            daily: args.daily,
+            weekly: args.weekly,
+            monthly: args.monthly,
+        }),
+        group_by_extension: args.group_by_extension,
+        case_sensitive_ext: !args.case_insensitive_ext,
+        protect: args
+            .protect
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(&normalize_name(
+                    pattern.as_str(),
+                    !args.case_insensitive_ext,
+                ))
+                .unwrap_or_else(|_| pattern.clone())
+            })
+            .collect(),
+        prefer_keep: args
+            .prefer_keep
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(&normalize_name(
+                    pattern.as_str(),
+                    !args.case_insensitive_ext,
+                ))
+                .unwrap_or_else(|_| pattern.clone())
+            })
+            .collect(),
+        extensions: args
+            .ext
+            .iter()
+            .map(|extension| normalize_extension(extension, !args.case_insensitive_ext))
+            .collect(),
+        keep_linked: args.keep_linked,
+        recursive: args.recursive,
+        dir_jobs: args.dir_jobs,
+        stat_jobs: args.stat_jobs,
+        dir_cache: args.skip_unchanged_dirs.then(|| DirCache {
+            previous: dir_mtimes_path()
+                .map(|file| load_dir_mtimes(&file))
+                .unwrap_or_default(),
+            ..Default::default()
+        }),
+        include_empty_dirs: args.include_empty_dirs,
+        skip_hidden: args.skip_hidden,
+        same_filesystem: args.same_filesystem,
+        inode_sort: args.inode_sort,
+        show_owner: args.show_owner,
+        template: args.template,
+        reverse: args.reverse,
+        min_files: args.min_files,
+        stat_errors_fatal: args.stat_errors_fatal,
+        walk_errors: args.walk_errors,
+        mark: args.mark.clone(),
+    };
+
+    if args.count_only || args.histogram {
+        let histogram_width = args.histogram.then(terminal_width);
+        for path in &targets {
+            if let Err(err) = count_files(verbosity, path, &options, histogram_width) {
+                CliError::new("scan", err.to_string())
+                    .with_path(path)
+                    .exit(error_format);
+            }
+        }
+        return;
+    }
+
+    if args.dump_buckets {
+        let mut dump = Vec::new();
+        for path in &targets {
+            match dump_buckets(path, &options) {
+                Ok(dirs) => dump.extend(dirs),
+                Err(err) => CliError::new("scan", err.to_string())
+                    .with_path(path)
+                    .exit(error_format),
+            }
+        }
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&dump).expect("Time segments are always serializable")
+        );
+        return;
+    }
+
+    if args.benchmark {
+        for path in &targets {
+            if let Err(err) = benchmark(verbosity, path, &options, args.benchmark_iterations) {
+                CliError::new("scan", err.to_string())
+                    .with_path(path)
+                    .exit(error_format);
+            }
+        }
+        return;
+    }
+
+    // Purged with the deletion, once confirmed and past every abort check
+    let expired_quarantines: Vec<_> = match args.quarantine_days {
+        Some(days) => {
+            let today = chrono::Local::now().date_naive();
+            targets
+                .iter()
+                .flat_map(|path| expired_quarantine(path, args.recursive, days, today))
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    for expired in &expired_quarantines {
+        report.info(format_args!(
+            "Expired quarantine to be purged: {}",
+            expired.display()
+        ));
+    }
+
+    if args.relative {
+        report.relative_roots = targets.clone();
+    }
+
+    let scan_started = time::Instant::now();
+    let mut plan = Plan::default();
+    let mut deletions = Vec::new(); // Files to delete in each target, for its history record
+    let report_verbosity = report.verbosity;
+    if args.tree {
+        report.verbosity = Verbosity::Quiet; // The tree replaces the listing of each directory
+    }
+    for path in &targets {
+        let target_plan =
+            exp_sort_and_list_to_del(&mut report, path, &options).unwrap_or_else(|err| {
+                CliError::new("scan", err.to_string())
+                    .with_path(path)
+                    .print(error_format);
+                Plan::default()
+            });
+        deletions.push((path.as_path(), target_plan.paths(Action::Delete)));
+        plan.files.extend(target_plan.files);
+        plan.skipped.extend(target_plan.skipped);
+    }
+    report.verbosity = report_verbosity;
+    if args.tree {
+        for path in &targets {
+            report.info(format_args!(""));
+            let label = report.shown_path(path).display().to_string();
+            for line in tree_lines(path, &label, &plan.files) {
+                report.info(format_args!("{}", line));
+            }
+        }
+    }
+    if let Some(grace) = args.grace {
+        let first_seen_file = first_seen_path();
+        let mut first_seen = first_seen_file
+            .as_deref()
+            .map(load_first_seen)
+            .unwrap_or_default();
+        let recent = plan.keep_recently_seen(&mut first_seen, time::SystemTime::now(), grace);
+        first_seen.retain(|file, _| file.exists());
+        if let Err(err) = first_seen_file.and_then(|file| save_first_seen(&file, &first_seen)) {
+            eprintln!(
+                "Warning: could not record when the files were first seen: {}",
+                err
+            );
+        }
+        if !recent.is_empty() {
+            report.info(format_args!(
+                "\nKept because they were first seen within the grace period:"
+            ));
+            for file in &recent {
+                report.listing(format_args!("{}", report.shown_path(file).display()));
+            }
+            let recent: collections::HashSet<_> = recent.into_iter().collect();
+            for (_, files) in &mut deletions {
+                files.retain(|file| !recent.contains(file));
+            }
+        }
+    }
+    if let Some(max_delete) = args.max_delete {
+        let eligible = plan.cap_deletions(max_delete);
+        if eligible > max_delete {
+            eprintln!(
+                "Warning: {} files could be deleted, only the oldest {} will be (--max-delete).",
+                eligible, max_delete
+            );
+            let capped: collections::HashSet<_> = plan.paths(Action::Delete).into_iter().collect();
+            for (_, files) in &mut deletions {
+                files.retain(|file| capped.contains(file));
+            }
+        }
+    }
+    if let Some(free_at_least) = args.free_at_least {
+        let eligible = plan.cap_freed(free_at_least, args.dereference_size);
+        let capped: collections::HashSet<_> = plan.paths(Action::Delete).into_iter().collect();
+        if eligible > capped.len() {
+            eprintln!(
+                "Warning: {} files could be deleted, only the oldest {} will be to free {} bytes (--free-at-least).",
+                eligible,
+                capped.len(),
+                free_at_least
+            );
+            for (_, files) in &mut deletions {
+                files.retain(|file| capped.contains(file));
+            }
+        }
+    }
+    if let Some(order) = args.delete_order {
+        let times: collections::HashMap<_, _> = plan
+            .files
+            .iter()
+            .map(|file| (file.path.as_path(), file.time))
+            .collect();
+        for (_, files) in &mut deletions {
+            order.sort(files, &times);
+        }
+    }
+    // Only once --grace and the caps settled which files are deleted
+    if let Some(algorithm) = args.record_checksum {
+        record_checksums(&mut plan, algorithm);
+    }
+    let scan_time = scan_started.elapsed();
+    let scanned = plan.files.len() + plan.skipped.len();
+    let skipped = plan.skipped.len();
+    let checksums: collections::BTreeMap<_, _> = plan
+        .files
+        .iter()
+        .filter_map(|file| Some((file.path.clone(), file.checksum.clone()?)))
+        .collect();
+    let (_to_keep, to_delete) = plan.split();
+
+    if !plan.skipped.is_empty() {
+        report.detail(format_args!("\nSkipped {} entries:", plan.skipped.len()));
+        for entry in &plan.skipped {
+            report.detail(format_args!(
+                "{}: {}",
+                report.shown_path(&entry.path).display(),
+                entry.reason
+            ));
+        }
+    }
+    report.info(format_args!(
+        "\nSummary: {} files to keep ({} bytes), {} files to be deleted ({} bytes)",
+        _to_keep.len(),
+        total_size(&_to_keep, args.dereference_size),
+        to_delete.len(),
+        total_size(&to_delete, args.dereference_size)
+    ));
+    if args.check_deletable {
+        let undeletable = undeletable_files(&to_delete);
+        if undeletable.is_empty() {
+            report.info(format_args!(
+                "\nAll {} files to be deleted can be deleted.",
+                to_delete.len()
+            ));
+        } else {
+            report.info(format_args!(
+                "\n{} of {} files to be deleted cannot be deleted:",
+                undeletable.len(),
+                to_delete.len()
+            ));
+            for (file, problem) in &undeletable {
+                report.info(format_args!("{}: {}", file.display(), problem));
+            }
+        }
+    }
+
+    let plan = SavedPlan {
+        keep: _to_keep,
+        delete: to_delete,
+        files: plan.files,
+        skipped: plan.skipped,
+    };
+    let shown_plan = report.shown_plan(&plan);
+    let shown = shown_plan.as_ref().unwrap_or(&plan);
+    match args.format {
+        OutputFormat::Human => {}
+        OutputFormat::Json => report.line(format_args!(
+            "{}",
+            serde_json::to_string_pretty(shown).expect("Plan is always serializable")
+        )),
+        OutputFormat::Csv => write_plan_csv(&mut report, shown),
+        OutputFormat::Markdown => write_plan_markdown(&mut report, shown),
+        OutputFormat::Jsonl => write_plan_jsonl(&mut report, shown),
+    }
+    if let Some(previous_plan) = &previous_plan {
+        print_plan_diff(&mut report, &compare_plans(previous_plan, &plan));
+    }
+    report.flush();
+    if let (Some(expected_plan), Some(plan_path)) = (&expected_plan, &args.assert_plan) {
+        let mismatches = plan_mismatches(expected_plan, &plan);
+        if !mismatches.is_empty() {
+            let files: collections::HashSet<_> =
+                mismatches.iter().map(|(_, file, _)| file).collect();
+            CliError::new(
+                "assert_plan",
+                format!(
+                    "The plan differs from {} in {} files (- expected, + planned). Nothing was deleted.",
+                    plan_path,
+                    files.len()
+                ),
+            )
+            .with_path(plan_path)
+            .with_mismatches(
+                mismatches
+                    .into_iter()
+                    .map(|(sign, path, action)| PlanMismatch { sign, action, path })
+                    .collect(),
+            )
+            .exit(error_format);
+        }
+    }
+    if let Some(list) = args.list {
+        let paths = match list {
+            ListChoice::Kept => &shown.keep,
+            ListChoice::Deleted => &shown.delete,
+        };
+        let terminator = if args.print0 { b'\0' } else { b'\n' };
+        let mut stdout = io::stdout().lock();
+        for path in paths {
+            stdout
+                .write_all(path.as_os_str().as_encoded_bytes())
+                .and_then(|_| stdout.write_all(&[terminator]))
+                .expect("Failed to write the paths");
+        }
+    }
+    let SavedPlan {
+        keep: _to_keep,
+        delete: to_delete,
+        ..
+    } = plan;
+
+    if let Some(max_files) = args.abort_if_over
+        && to_delete.len() > max_files
+    {
+        CliError::new(
+            "abort_if_over",
+            format!(
+                "{} files would be deleted, more than --abort-if-over {}. Nothing was deleted.",
+                to_delete.len(),
+                max_files
+            ),
+        )
+        .exit(error_format);
+    }
+    if let Some(max_bytes) = args.abort_if_over_bytes {
+        let bytes = total_size(&to_delete, args.dereference_size);
+        if bytes > max_bytes {
+            CliError::new(
+                "abort_if_over",
+                format!(
+                    "{} bytes would be deleted, more than --abort-if-over-bytes {}. Nothing was deleted.",
+                    bytes, max_bytes
+                ),
+            )
+            .exit(error_format);
+        }
+    }
+
+    if let Some(max_age) = args.require_preview {
+        let fingerprints: Vec<_> = targets
+            .iter()
+            .map(|path| plan_fingerprint(path, &options))
+            .collect();
+        if args.print_only {
+            for fingerprint in &fingerprints {
+                if let Err(err) = save_preview(fingerprint) {
+                    eprintln!("Warning: could not record the preview: {}", err);
+                }
+            }
+        } else if !to_delete.is_empty()
+            && fingerprints.iter().any(|fingerprint| {
+                preview_age(fingerprint)
+                    .is_none_or(|age| age > time::Duration::from_secs(max_age * 60))
+            })
+        {
+            CliError::new(
+                "no_preview",
+                format!(
+                    "No preview of this plan within the last {} minutes. Run the same command with --print-only first.",
+                    max_age
+                ),
+            )
+            .exit(error_format);
+        }
+    }
+
+    let auto_confirmed = args
+        .auto_confirm_under
+        .is_some_and(|limit| to_delete.len() < limit);
+    if !args.force
+        && !auto_confirmed
+        && !args.print_only
+        && !args.quiet
+        && (!to_delete.is_empty() || !expired_quarantines.is_empty())
+    {
+        // In recursive mode, the scope is every directory that loses all its files
+        let emptied = if args.recursive {
+            emptied_dirs(&_to_keep, &to_delete)
+        } else {
+            collections::BTreeMap::new()
+        };
+        let expected = if !emptied.is_empty() {
+            format!("DELETE ALL IN {} DIRECTORIES", emptied.len())
+        } else if _to_keep.is_empty() && !to_delete.is_empty() {
+            "DELETE ALL".to_string()
+        } else {
+            String::new()
+        };
+        let delete_all = !expected.is_empty();
+        if !emptied.is_empty() {
+            println!(
+                "WARNING! {} directories will be emptied, {} files will be deleted from them.",
+                emptied.len(),
+                emptied.values().sum::<usize>()
+            );
+            println!(
+                "\nType {} to proceed with deletion. There is no undo.",
+                expected
+            );
+        } else if delete_all {
+            println!("WARNING! No files will be kept, you want ALL files to be deleted.");
+            println!("\nType DELETE ALL to proceed with deletion. There is no undo.");
+        } else {
+            println!("\nDo you want to proceed with deletion? There is no undo. (yes/no)");
+        }
+        let mut confirmation = String::new();
+        io::stdin()
+            .read_line(&mut confirmation)
+            .expect("Failed to read line");
+        let confirmed = if delete_all {
+            confirmation.trim() == expected
+        } else {
+            confirmation.trim().to_lowercase() == "yes"
+        };
+        if !confirmed {
+            println!("Operation cancelled.");
+            return;
+        }
+    }
+
+    let mut deletion_time = None;
+    let mut failed = 0;
+    let mut freed_bytes = 0;
+    let mut errors: collections::HashMap<io::ErrorKind, usize> = collections::HashMap::new();
+    if !args.print_only {
+        for expired in &expired_quarantines {
+            match fs::remove_dir_all(expired) {
+                Ok(()) => verbosity.info(format_args!(
+                    "Purged the expired quarantine {}",
+                    expired.display()
+                )),
+                Err(err) => eprintln!(
+                    "Warning: could not purge the quarantine {}: {}",
+                    expired.display(),
+                    err
+                ),
+            }
+        }
+        if !to_delete.is_empty() {
+            if let Err(err) =
+                ctrlc::set_handler(|| INTERRUPTED.store(true, atomic::Ordering::SeqCst))
+            {
+                eprintln!("Warning: could not install the Ctrl-C handler: {}", err);
+            }
+            let delete_options = DeleteOptions {
+                retries: args.retries,
+                retry_delay: time::Duration::from_millis(args.retry_delay),
+                quiet_errors: args.quiet_errors,
+                journal: args.journal.as_ref().map(path::PathBuf::from),
+                recycle: args.recycle,
+                mark: args.mark.clone(),
+                on_conflict: args.on_conflict,
+                quarantine: args
+                    .quarantine_days
+                    .map(|_| chrono::Local::now().date_naive()),
+                verify: args.verify,
+                deadline: args.max_runtime.map(|max_runtime| started + max_runtime),
+                error_format,
+                dereference_size: args.dereference_size,
+            };
+            if let Some(journal) = &delete_options.journal
+                && let Err(err) = write_journal(journal, &to_delete)
+            {
+                CliError::new(
+                    "journal",
+                    format!("Could not write the journal {}: {}", journal.display(), err),
+                )
+                .with_path(journal)
+                .exit(error_format);
+            }
+            let delete_started = time::Instant::now();
+            let mut deleted = 0;
+            let mut interrupted = false;
+            let mut timed_out = false;
+            for (path, files) in deletions.iter().filter(|(_, files)| !files.is_empty()) {
+                if INTERRUPTED.load(atomic::Ordering::SeqCst) {
+                    interrupted = true; // Do not start on the next directory
+                    break;
+                }
+                let deletion = delete_files(verbosity, files, &delete_options, &INTERRUPTED)
+                    .unwrap_or_else(|err| {
+                        syslog.err(&format!("Deletion in {} failed: {}", path.display(), err));
+                        CliError::new("deletion", format!("Deletion failed: {}", err))
+                            .with_path(path)
+                            .print(error_format);
+                        DeletionReport::default()
+                    });
+                for (file, err) in &deletion.failures {
+                    syslog.err(&format!("Could not delete {}: {}", file.display(), err));
+                }
+                if deletion.conflicts > 0 {
+                    eprintln!(
+                        "Warning: {} files in {} were left in place, their marked name already exists (--on-conflict skip).",
+                        deletion.conflicts,
+                        path.display()
+                    );
+                }
+                deleted += deletion.deleted;
+                failed += deletion.failed;
+                freed_bytes += deletion.freed_bytes;
+                for (kind, count) in deletion.errors {
+                    *errors.entry(kind).or_default() += count;
+                }
+                if !args.no_history {
+                    let record = HistoryRecord {
+                        timestamp: chrono::Local::now().to_rfc3339(),
+                        path: fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+                        deleted: deletion.deleted,
+                        freed_bytes: deletion.freed_bytes,
+                        checksums: files
+                            .iter()
+                            .filter(|file| !file.exists())
+                            .filter_map(|file| Some((file.clone(), checksums.get(file)?.clone())))
+                            .collect(),
+                    };
+                    if let Err(err) = append_history(&record) {
+                        eprintln!("Warning: could not write the history file: {}", err);
+                    }
+                }
+                if deletion.interrupted {
+                    interrupted = true;
+                    break;
+                }
+                if deletion.timed_out {
+                    timed_out = true;
+                    break;
+                }
+            }
+            deletion_time = Some((deleted, delete_started.elapsed()));
+            if interrupted || timed_out {
+                syslog.warning(&format!(
+                    "{}, {} of {} files were deleted",
+                    if interrupted {
+                        "Interrupted"
+                    } else {
+                        "Time budget exceeded"
+                    },
+                    deleted,
+                    to_delete.len()
+                ));
+            } else {
+                let summary = format!(
+                    "Deleted {} of {} files, freed {} bytes, {} failed",
+                    deleted,
+                    to_delete.len(),
+                    freed_bytes,
+                    failed
+                );
+                match failed {
+                    0 => syslog.info(&summary),
+                    _ => syslog.warning(&summary),
+                }
+            }
+            let resume_hint = if args.journal.is_some() {
+                " Run again with --resume to delete the rest."
+            } else {
+                ""
+            };
+            if interrupted {
+                CliError::new(
+                    "interrupted",
+                    format!(
+                        "Interrupted! {} of {} files were deleted, the rest were left untouched.{}",
+                        deleted,
+                        to_delete.len(),
+                        resume_hint
+                    ),
+                )
+                .exit_with(EXIT_INTERRUPTED, error_format);
+            }
+            if timed_out {
+                CliError::new(
+                    "time_budget",
+                    format!(
+                        "Time budget exceeded! {} of {} files were deleted, {} remain.{}",
+                        deleted,
+                        to_delete.len(),
+                        to_delete.len() - deleted - failed,
+                        resume_hint
+                    ),
+                )
+                .exit_with(EXIT_TIME_BUDGET, error_format);
+            }
+            if let Some(journal) = &delete_options.journal
+                && failed == 0
+            {
+                let _ = fs::remove_file(journal); // Nothing left to resume
+            }
+        } else {
+            syslog.info("No files to delete");
+            verbosity.info(format_args!("No files to delete."));
+        }
+        // Directories with failed deletions are not modified, so they would be skipped next time
+        if let Some(cache) = &options.dir_cache
+            && failed == 0
+            && let Err(err) =
+                dir_mtimes_path().and_then(|file| save_dir_mtimes(&file, &cache.merged()))
+        {
+            eprintln!("Warning: could not record the directory times: {}", err);
+        }
+        if let Some(hook) = &args.post_hook {
+            let deleted = deletion_time.map_or(0, |(deleted, _)| deleted);
+            match run_post_hook(hook, deleted, freed_bytes, failed) {
+                Ok(status) if status.success() => {}
+                Ok(status) => eprintln!("Warning: the post-delete hook failed: {}", status),
+                Err(err) => eprintln!("Warning: could not run the post-delete hook: {}", err),
+            }
+        }
+    } else {
+        syslog.info(&format!(
+            "Print-only, {} files would be deleted",
+            to_delete.len()
+        ));
+        verbosity.info(format_args!("\nPrint-only enabled, no files were deleted."));
+    }
+
+    if args.report_kept {
+        for (dir, (kept, deleted)) in tally_by_dir(&_to_keep, &to_delete) {
+            println!(
+                "{}: {} files kept, {} files deleted",
+                dir.display(),
+                kept,
+                deleted
+            );
+        }
+    }
+
+    if args.timing || args.verbose {
+        // Kept out of machine-readable output
+        let to_stderr = args.list.is_some()
+            || args.json_summary
+            || (args.format != OutputFormat::Human && args.output_file.is_none());
+        let timing = |line: fmt::Arguments| {
+            if to_stderr {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
+            }
+        };
+        timing(format_args!(
+            "\nScanning: {} entries in {:.3} s ({:.0} entries/second)",
+            scanned,
+            scan_time.as_secs_f64(),
+            per_second(scanned, scan_time)
+        ));
+        if let Some((deleted, delete_time)) = deletion_time {
+            timing(format_args!(
+                "Deleting: {} files in {:.3} s ({:.0} files/second)",
+                deleted,
+                delete_time.as_secs_f64(),
+                per_second(deleted, delete_time)
+            ));
+        }
+    }
+
+    if args.json_summary {
+        let summary = RunSummary {
+            kept: _to_keep.len(),
+            kept_bytes: total_size(&_to_keep, args.dereference_size),
+            to_delete: to_delete.len(),
+            to_delete_bytes: total_size(&to_delete, args.dereference_size),
+            deleted: deletion_time.map_or(0, |(deleted, _)| deleted),
+            freed_bytes,
+            failed,
+            skipped,
+            print_only: args.print_only,
+            duration_seconds: started.elapsed().as_secs_f64(),
+        };
+        println!(
+            "{}",
+            serde_json::to_string(&summary).expect("Summary is always serializable")
+        );
+    }
+
+    if failed > 0 {
+        // The errors of each file were not printed, so their kinds are summed up instead
+        let kinds = if args.quiet_errors {
+            format!(
+                " ({})",
+                errors
+                    .iter()
+                    .sorted_by_key(|(_, count)| cmp::Reverse(**count))
+                    .map(|(kind, count)| format!("{}: {}", kind, count))
+                    .join(", ")
+            )
+        } else {
+            String::new()
+        };
+        CliError::new(
+            "partial_failure",
+            format!(
+                "{} of {} files could not be deleted{}.",
+                failed,
+                to_delete.len(),
+                kinds
+            ),
+        )
+        .exit_with(EXIT_PARTIAL_FAILURE, error_format);
+    }
+    if args.exit_nonzero_if_deletions && !to_delete.is_empty() {
+        process::exit(EXIT_WOULD_DELETE);
+    }
+}
+
+/// Formats an age like "3 days ago", in its largest whole unit
+fn relative_time(age: time::Duration) -> String {
+    let secs = age.as_secs();
+    let (count, unit) = match secs {
+        0..60 => (secs, "second"),
+        60..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    if count == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", count, unit)
+    }
+}
+
+/// Deletes the files left in the journal of an interrupted run, then exits
+fn resume(args: &Args, verbosity: Verbosity) {
+    let journal = path::PathBuf::from(
+        args.journal
+            .as_deref()
+            .expect("--resume requires --journal"),
+    );
+    let remaining = read_journal(&journal).unwrap_or_else(|err| {
+        CliError::new(
+            "journal",
+            format!("Could not read the journal {}: {}", journal.display(), err),
+        )
+        .with_path(&journal)
+        .exit(args.error_format)
+    });
+    verbosity.info(format_args!(
+        "Resuming from {}: {} files left to delete.",
+        journal.display(),
+        remaining.len()
+    ));
+    if args.print_only {
+        for file in &remaining {
+            verbosity.listing(format_args!("{}", file.display()));
+        }
+        return;
+    }
+    if let Err(err) = ctrlc::set_handler(|| INTERRUPTED.store(true, atomic::Ordering::SeqCst)) {
+        eprintln!("Warning: could not install the Ctrl-C handler: {}", err);
+    }
+    let delete_options = DeleteOptions {
+        retries: args.retries,
+        retry_delay: time::Duration::from_millis(args.retry_delay),
+        quiet_errors: args.quiet_errors,
+        journal: Some(journal.clone()),
+        recycle: args.recycle,
+        mark: args.mark.clone(),
+        on_conflict: args.on_conflict,
+        quarantine: args
+            .quarantine_days
+            .map(|_| chrono::Local::now().date_naive()),
+        verify: args.verify,
+        deadline: args
+            .max_runtime
+            .map(|max_runtime| time::Instant::now() + max_runtime),
+        error_format: args.error_format,
+        dereference_size: args.dereference_size,
+    };
+    let deletion = delete_files(verbosity, &remaining, &delete_options, &INTERRUPTED)
+        .unwrap_or_else(|err| {
+            CliError::new("deletion", format!("Deletion failed: {}", err)).exit(args.error_format)
+        });
+    if deletion.interrupted {
+        CliError::new(
+            "interrupted",
+            format!(
+                "Interrupted! {} of {} files were deleted, run again with --resume to delete the rest.",
+                deletion.deleted,
+                remaining.len()
+            ),
+        )
+        .exit_with(EXIT_INTERRUPTED, args.error_format);
+    }
+    if deletion.timed_out {
+        CliError::new(
+            "time_budget",
+            format!(
+                "Time budget exceeded! {} of {} files were deleted, run again with --resume to delete the rest.",
+                deletion.deleted,
+                remaining.len()
+            ),
+        )
+        .exit_with(EXIT_TIME_BUDGET, args.error_format);
+    }
+    if deletion.failed > 0 {
+        CliError::new(
+            "partial_failure",
+            format!(
+                "{} of {} files could not be deleted.",
+                deletion.failed,
+                remaining.len()
+            ),
+        )
+        .exit_with(EXIT_PARTIAL_FAILURE, args.error_format);
+    }
+    let _ = fs::remove_file(&journal);
+}
+
+/// Throughput of a phase, 0 if it took no measurable time
+fn per_second(count: usize, elapsed: time::Duration) -> f64 {
+    if elapsed.is_zero() {
+        0.0
+    } else {
+        count as f64 / elapsed.as_secs_f64()
+    }
+}
+
+/// Parses a size like 1024, 500K, 10MB or 2GiB. K, M, G and T are binary units, KB, MB, GB and TB decimal ones.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", size))?;
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(format!("invalid size unit '{}'", unit)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", size))
+}
+
+/// Parses a duration like 90, 30s, 15m, 12h, 7d or 2w. Plain numbers are seconds.
+fn parse_duration(duration: &str) -> Result<time::Duration, String> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", duration))?;
+    let seconds: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return Err(format!("invalid duration unit '{}'", unit)),
+    };
+    number
+        .checked_mul(seconds)
+        .map(time::Duration::from_secs)
+        .ok_or_else(|| format!("duration '{}' is too long", duration))
+}
+
+fn parse_extension(extension: &str) -> Result<String, String> {
+    let extension = extension.trim().trim_start_matches('.').to_string();
+    if extension.is_empty() {
+        return Err("empty extension".to_string());
+    }
+    Ok(extension)
+}
+
+fn parse_template(template: &str) -> Result<Template, String> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unclosed placeholder '{{{}'", name)),
+                    }
+                }
+                let part = match name.as_str() {
+                    "path" => TemplatePart::Path,
+                    "time" => TemplatePart::Time,
+                    "bucket" => TemplatePart::Bucket,
+                    "action" => TemplatePart::Action,
+                    "size" => TemplatePart::Size,
+                    "owner" => TemplatePart::Owner,
+                    _ => return Err(format!("unknown placeholder '{{{}}}'", name)),
+                };
+                if !text.is_empty() {
+                    parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(part);
+            }
+            '}' => return Err("unmatched '}', write '}}' for a literal brace".to_string()),
+            '\\' => match chars.next() {
+                Some('t') => text.push('\t'),
+                Some('n') => text.push('\n'),
+                Some(other) => {
+                    text.push('\\');
+                    text.push(other);
+                }
+                None => text.push('\\'),
+            },
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(TemplatePart::Text(text));
+    }
+    Ok(Template(parts))
+}
+
+fn parse_bucket_boundaries(boundaries: &str) -> Result<BucketBoundaries, String> {
+    let boundaries: Vec<u64> = boundaries
+        .split(',')
+        .map(|boundary| {
+            boundary
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid boundary '{}'", boundary))
+        })
+        .collect::<Result<_, _>>()?;
+    if boundaries.first() == Some(&0) || !boundaries.is_sorted_by(|a, b| a < b) {
+        return Err("boundaries must be positive and increasing".to_string());
+    }
+    Ok(BucketBoundaries(boundaries))
+}
+
+/// Parses the BOUNDARY,KEEP lines of a policy file, ending with a *,KEEP line for the oldest time segment
+fn parse_policy(content: &str) -> Result<RetentionPolicy, String> {
+    let mut boundaries = Vec::new();
+    let mut keep = Vec::new();
+    let mut oldest = None;
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let number = number + 1;
+        if oldest.is_some() {
+            return Err(format!("line {}: the * line must be the last one", number));
+        }
+        let (boundary, count) = line
+            .split_once(',')
+            .ok_or_else(|| format!("line {}: expected BOUNDARY,KEEP, got '{}'", number, line))?;
+        let count: u32 = count.trim().parse().map_err(|_| {
+            format!(
+                "line {}: invalid number of files '{}'",
+                number,
+                count.trim()
+            )
+        })?;
+        match boundary.trim() {
+            "*" => oldest = Some(count),
+            boundary => {
+                let boundary: u64 = boundary
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid boundary '{}'", number, boundary))?;
+                if boundary == 0 || boundaries.last().is_some_and(|last| *last >= boundary) {
+                    return Err(format!(
+                        "line {}: boundaries must be positive and increasing",
+                        number
+                    ));
+                }
+                boundaries.push(boundary);
+                keep.push(count);
+            }
+        }
+    }
+    let oldest =
+        oldest.ok_or("missing the *,KEEP line for the files older than the last boundary")?;
+    keep.push(oldest);
+    Ok(RetentionPolicy {
+        boundaries: BucketBoundaries(boundaries),
+        keep,
+    })
+}
+
+fn read_policy_file(policy_file: &path::Path) -> io::Result<RetentionPolicy> {
+    parse_policy(&fs::read_to_string(policy_file)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn parse_keep_override(keep_override: &str) -> Result<KeepOverride, String> {
+    let (dir, keep) = keep_override
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected DIR=N, got '{}'", keep_override))?;
+    if dir.is_empty() {
+        return Err(format!("missing directory in '{}'", keep_override));
+    }
+    let keep = keep
+        .parse()
+        .map_err(|_| format!("invalid number of files '{}'", keep))?;
+    Ok(KeepOverride {
+        dir: path::PathBuf::from(dir),
+        keep,
+    })
+}
+
+fn get_time_type(meta: &fs::Metadata, sort_type: &SortType) -> time::SystemTime {
+    match sort_type {
+        SortType::MTime => meta.modified().unwrap_or(time::UNIX_EPOCH),
+        SortType::ATime => meta.accessed().unwrap_or(time::UNIX_EPOCH),
+        SortType::CTime => meta.created().unwrap_or(time::UNIX_EPOCH),
+    }
+}
+
+/// Rounds a time down to a multiple of the resolution
+fn round_time(file_time: time::SystemTime, resolution: time::Duration) -> time::SystemTime {
+    if resolution.is_zero() {
+        return file_time;
+    }
+    let Ok(since_epoch) = file_time.duration_since(time::UNIX_EPOCH) else {
+        return file_time;
+    };
+    let rounded = since_epoch.as_nanos() / resolution.as_nanos() * resolution.as_nanos();
+    time::UNIX_EPOCH + time::Duration::from_nanos(rounded as u64)
+}
+
+/// Finds how precisely the filesystem of the directory stores modification times: zero if to the
+/// nanosecond, 1 s if without fractions, 2 s on FAT. None if the directory is not writable.
+/// The times of the directory are restored after the probe, so --skip-unchanged-dirs still skips it.
+fn timestamp_resolution(dir: &path::Path) -> Option<time::Duration> {
+    let dir_meta = fs::metadata(dir).ok()?;
+    let probe = tempfile::NamedTempFile::new_in(dir).ok()?;
+    let odd = time::UNIX_EPOCH + time::Duration::new(1_000_000_001, 500_000_000);
+    let stored = filetime::set_file_mtime(probe.path(), filetime::FileTime::from_system_time(odd))
+        .and_then(|_| fs::metadata(probe.path())?.modified());
+    drop(probe);
+    let _ = filetime::set_file_times(
+        dir,
+        filetime::FileTime::from_last_access_time(&dir_meta),
+        filetime::FileTime::from_last_modification_time(&dir_meta),
+    );
+    let stored = stored.ok()?;
+    let since_epoch = stored.duration_since(time::UNIX_EPOCH).ok()?;
+    Some(if stored == odd {
+        time::Duration::ZERO
+    } else if since_epoch.as_secs() == 1_000_000_001 {
+        time::Duration::from_secs(1)
+    } else {
+        time::Duration::from_secs(2)
+    })
+}
+
+/// Date embedded in the file name, at local midnight
+fn date_from_name(file: &path::Path, pattern: &regex::Regex) -> Option<time::SystemTime> {
+    let name = file.file_name()?.to_string_lossy();
+    let captures = pattern.captures(&name)?;
+    let date = captures.get(1).or_else(|| captures.get(0))?.as_str();
+    let date = ["%Y-%m-%d", "%Y%m%d", "%Y_%m_%d", "%Y.%m.%d"]
+        .iter()
+        .find_map(|format| chrono::NaiveDate::parse_from_str(date, format).ok())?;
+    let datetime = date
+        .and_hms_opt(0, 0, 0)?
+        .and_local_timezone(chrono::Local)
+        .earliest()?;
+    Some(datetime.into())
+}
+
+/// Time segment of a file with the given age: 1, 2, 4, 8, ... units
+fn bucket_for_age(age: u64) -> u64 {
+    if age == 0 {
+        1
+    } else {
+        1 << (age.checked_ilog2().unwrap() + if age.is_power_of_two() { 0 } else { 1 })
+    }
+}
+
+fn bucket_label(bucket: u64, age_unit: AgeUnit) -> String {
+    format!(
+        "Younger than {} {} but older than {} {}",
+        bucket,
+        age_unit.name(),
+        bucket / 2,
+        age_unit.name()
+    )
+}
+
+fn group_files_by_bucket(
+    path: &path::Path,
+    options: &PlanOptions,
+) -> io::Result<(Buckets, Vec<SkippedEntry>)> {
+    let mut groups: Buckets = collections::BTreeMap::new();
+    let mut skipped = Vec::new();
+    let mut files = Vec::new();
+
+    let mut entries = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+    if options.inode_sort {
+        entries.sort_by_key(entry_inode);
+    }
+    let metas = stat_entries(&entries, options.stat_jobs);
+    for (entry, meta) in entries.into_iter().zip(metas) {
+        let meta = match meta {
+            Ok(meta) => meta,
+            Err(err) if options.stat_errors_fatal => {
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!(
+                        "Could not read the metadata of {}: {}",
+                        entry.path().display(),
+                        err
+                    ),
+                ));
+            }
+            Err(err) => {
+                eprintln!(
+                    "Warning: skipping {}: could not read metadata: {}",
+                    entry.path().display(),
+                    err
+                );
+                skipped.push(SkippedEntry::new(
+                    entry.path(),
+                    SkipReason::StatError(err.to_string()),
+                ));
+                continue;
+            }
+        };
+        if entry.file_name() == LOCK_FILE_NAME {
+            skipped.push(SkippedEntry::new(entry.path(), SkipReason::LockFile));
+            continue;
+        }
+        let empty_dir = options.include_empty_dirs && meta.is_dir() && is_empty_dir(&entry.path());
+        if !meta.is_file() && !empty_dir {
+            let reason = if meta.is_dir() {
+                SkipReason::Directory
+            } else if meta.file_type().is_symlink() {
+                SkipReason::Symlink
+            } else {
+                SkipReason::NotRegularFile
+            };
+            skipped.push(SkippedEntry::new(entry.path(), reason));
+            continue; // Skip directories and other non-file entries
+        }
+        if options.skip_hidden && is_hidden(&entry.file_name()) {
+            skipped.push(SkippedEntry::new(entry.path(), SkipReason::HiddenFile));
+            continue;
+        }
+        if !empty_dir
+            && !has_extension(
+                &entry.path(),
+                &options.extensions,
+                options.case_sensitive_ext,
+            )
+        {
+            skipped.push(SkippedEntry::new(entry.path(), SkipReason::OtherExtension));
+            continue;
+        }
+        if let Some(suffix) = &options.mark
+            && entry
+                .file_name()
+                .to_string_lossy()
+                .ends_with(suffix.as_str())
+        {
+            skipped.push(SkippedEntry::new(entry.path(), SkipReason::Marked));
+            continue;
+        }
+        let file_time = options
+            .date_from_name
+            .as_ref()
+            .and_then(|pattern| date_from_name(&entry.path(), pattern))
+            .unwrap_or_else(|| get_time_type(&meta, &options.sort_type));
+        let file_time = match options.time_resolution {
+            Some(resolution) => round_time(file_time, resolution),
+            None => file_time,
+        };
+        files.push((entry.path(), file_time));
+    }
+    let reference = match options.age_reference {
+        AgeReference::Now => time::SystemTime::now(),
+        AgeReference::Newest => files
+            .iter()
+            .map(|(_, file_time)| *file_time)
+            .max()
+            .unwrap_or_else(time::SystemTime::now),
+    };
+    for (file, file_time) in files {
+        match reference.duration_since(file_time) {
+            Ok(age) => {
+                let bucket = options.bucket_for(age.as_secs() / options.age_unit.seconds());
+                groups.entry(bucket).or_default().push((file, file_time));
+            }
+            Err(_) => skipped.push(SkippedEntry::new(file, SkipReason::FutureTime)),
+        }
+    }
+    // The directory order depends on the filesystem and on the history of the directory,
+    // so the entries are listed by path to get the same plan on every machine
+    for files in groups.values_mut() {
+        files.sort();
+    }
+    skipped.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok((groups, skipped))
+}
+
+/// Whether a directory has no entries, false if it cannot be read
+fn is_empty_dir(dir: &path::Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+/// Whether the file has one of the lowercase extensions, or any if there are none
+fn has_extension(file: &path::Path, extensions: &[String], case_sensitive: bool) -> bool {
+    extensions.is_empty()
+        || file.extension().is_some_and(|extension| {
+            extensions.contains(&normalize_extension(
+                &extension.to_string_lossy(),
+                case_sensitive,
+            ))
+        })
+}
+
+/// Extension as compared by --ext, --group-by-extension and --protect, lowercase unless `case_sensitive`
+fn normalize_extension(extension: &str, case_sensitive: bool) -> String {
+    if case_sensitive {
+        extension.to_string()
+    } else {
+        extension.to_lowercase()
+    }
+}
+
+/// File name or pattern with its extension normalized, e.g. Photo.jpg for Photo.JPG
+fn normalize_name(name: &str, case_sensitive: bool) -> String {
+    let name = path::Path::new(name);
+    match name.extension() {
+        Some(extension) if !case_sensitive => name
+            .with_extension(normalize_extension(&extension.to_string_lossy(), false))
+            .to_string_lossy()
+            .into_owned(),
+        _ => name.to_string_lossy().into_owned(),
+    }
+}
+
+/// Inode of a directory entry, known without reading its metadata on Unix
+#[cfg(unix)]
+fn entry_inode(entry: &fs::DirEntry) -> u64 {
+    use std::os::unix::fs::DirEntryExt;
+    entry.ino()
+}
+
+#[cfg(not(unix))]
+fn entry_inode(_entry: &fs::DirEntry) -> u64 {
+    0
+}
+
+/// Owner and permission bits of a file, e.g. "alice 0644", the owner being the uid if it has no user name
+#[cfg(unix)]
+fn file_owner(file: &path::Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = fs::metadata(file).ok()?;
+    let uid = meta.uid();
+    Some(format!(
+        "{} {:04o}",
+        user_name(uid).unwrap_or_else(|| uid.to_string()),
+        meta.mode() & 0o7777
+    ))
+}
+
+/// Name of the user, from any source of the system such as LDAP, looked up once per uid
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    type Names = sync::Mutex<collections::HashMap<u32, Option<String>>>;
+    static NAMES: sync::OnceLock<Names> = sync::OnceLock::new();
+    let names = NAMES.get_or_init(Default::default);
+    if let Some(name) = names.lock().expect("User names poisoned").get(&uid) {
+        return name.clone();
+    }
+    let name = lookup_user_name(uid);
+    names
+        .lock()
+        .expect("User names poisoned")
+        .insert(uid, name.clone());
+    name
+}
+
+#[cfg(unix)]
+fn lookup_user_name(uid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    let mut passwd = mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut result = std::ptr::null_mut();
+    loop {
+        // SAFETY: the buffers outlive the call, and their lengths are those given
+        let err = unsafe {
+            libc::getpwuid_r(
+                uid,
+                passwd.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        if err == libc::ERANGE && buffer.len() < 1 << 20 {
+            buffer.resize(buffer.len() * 2, 0); // Entry larger than the buffer
+            continue;
+        }
+        if err != 0 || result.is_null() {
+            return None;
+        }
+        // SAFETY: result points to passwd, filled with strings stored in buffer
+        let name = unsafe { ffi::CStr::from_ptr((*result).pw_name) };
+        return Some(name.to_string_lossy().into_owned());
+    }
+}
+
+#[cfg(not(unix))]
+fn file_owner(_file: &path::Path) -> Option<String> {
+    None
+}
+
+/// Line describing a file in the plan, with its owner if --show-owner is set
+fn plan_line(
+    report: &Report,
+    options: &PlanOptions,
+    file: &path::Path,
+    time: time::SystemTime,
+) -> String {
+    let line = format!(
+        "{} | {}",
+        report.shown_path(file).display(),
+        report.timestamp(time)
+    );
+    match options.show_owner.then(|| file_owner(file)).flatten() {
+        Some(owner) => format!("{} | {}", line, owner),
+        None => line,
+    }
+}
+
+/// Lists a file of the plan, rendered with --template if given, `action` being keep, protected, delete
+/// or duplicate
+fn list_file(
+    report: &mut Report,
+    options: &PlanOptions,
+    file: &path::Path,
+    time: time::SystemTime,
+    bucket: u64,
+    action: &str,
+) {
+    let (color, marker) = match action {
+        "protected" => (GREEN, Some((YELLOW, "<-- protected"))),
+        "delete" => (RED, Some((BOLD_RED, "<-- to be deleted"))),
+        "duplicate" => (
+            RED,
+            Some((BOLD_RED, "<-- copy of a newer file, to be deleted")),
+        ),
+        _ => (GREEN, None),
+    };
+    let line = match &options.template {
+        Some(template) => {
+            let line = template.render(
+                file,
+                report.shown_path(file),
+                &report.timestamp(time),
+                bucket,
+                action,
+                options.dereference_size,
+            );
+            return report.listing(format_args!("{}", report.paint(color, &line)));
+        }
+        None => plan_line(report, options, file, time),
+    };
+    match marker {
+        Some((marker_color, marker)) => report.listing(format_args!(
+            "{} {}",
+            report.paint(color, &line),
+            report.paint(marker_color, marker)
+        )),
+        None => report.listing(format_args!("{}", report.paint(color, &line))),
+    }
+}
+
+/// Metadata of the directory entries, in their order, read on up to `stat_jobs` threads
+fn stat_entries(entries: &[fs::DirEntry], stat_jobs: u16) -> Vec<io::Result<fs::Metadata>> {
+    let jobs = (stat_jobs as usize).clamp(1, entries.len().max(1));
+    if jobs == 1 {
+        return entries.iter().map(fs::DirEntry::metadata).collect();
+    }
+    thread::scope(|scope| {
+        let workers: Vec<_> = entries
+            .chunks(entries.len().div_ceil(jobs))
+            .map(|chunk| {
+                scope.spawn(move || chunk.iter().map(fs::DirEntry::metadata).collect::<Vec<_>>())
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Reading the metadata panicked"))
+            .collect()
+    })
+}
+
+/// Groups the files of each directory, in the order of `dirs`, on up to `dir_jobs` threads
+fn scan_dirs(
+    dirs: &[path::PathBuf],
+    options: &PlanOptions,
+) -> Vec<io::Result<(Buckets, Vec<SkippedEntry>)>> {
+    let jobs = (options.dir_jobs as usize).clamp(1, dirs.len().max(1));
+    if jobs == 1 {
+        return dirs
+            .iter()
+            .map(|dir| group_files_by_bucket(dir, options))
+            .collect();
+    }
+    let next = atomic::AtomicUsize::new(0);
+    let mut scans: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut scans = Vec::new();
+                    while let Some(dir) = dirs.get(next.fetch_add(1, atomic::Ordering::Relaxed)) {
+                        scans.push((dir, group_files_by_bucket(dir, options)));
+                    }
+                    scans
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Directory scan panicked"))
+            .collect()
+    });
+    let order: collections::HashMap<_, _> =
+        dirs.iter().enumerate().map(|(i, dir)| (dir, i)).collect();
+    scans.sort_by_key(|(dir, _)| order[dir]);
+    scans.into_iter().map(|(_, scan)| scan).collect()
+}
+
+/// Lines drawing the directories under `root` holding planned files as a tree, with the number of
+/// files kept and to be deleted in each of them, the root being shown as `label`
+fn tree_lines(root: &path::Path, label: &str, files: &[PlannedFile]) -> Vec<String> {
+    let mut counts: collections::BTreeMap<path::PathBuf, (usize, usize)> =
+        collections::BTreeMap::from([(path::PathBuf::new(), (0, 0))]);
+    for file in files {
+        let Some(dir) = file
+            .path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(root).ok())
+        else {
+            continue;
+        };
+        for ancestor in dir.ancestors() {
+            counts.entry(ancestor.to_path_buf()).or_default();
+        }
+        let (kept, deleted) = counts.entry(dir.to_path_buf()).or_default();
+        match file.action {
+            Action::Keep => *kept += 1,
+            Action::Delete => *deleted += 1,
+        }
+    }
+    let mut children: collections::BTreeMap<&path::Path, Vec<&path::Path>> =
+        collections::BTreeMap::new();
+    for dir in counts.keys().filter(|dir| !dir.as_os_str().is_empty()) {
+        children
+            .entry(dir.parent().unwrap_or(path::Path::new("")))
+            .or_default()
+            .push(dir);
+    }
+    let node = |name: &str, dir: &path::Path| {
+        let (kept, deleted) = counts[dir];
+        format!("{} ({} kept, {} to delete)", name, kept, deleted)
+    };
+    let mut lines = vec![node(label, path::Path::new(""))];
+    // Directories still to draw, with the prefix of their line and of their children's lines
+    let mut stack: Vec<(path::PathBuf, String, String)> = Vec::new();
+    let push_children =
+        |stack: &mut Vec<(path::PathBuf, String, String)>, dir: &path::Path, prefix: &str| {
+            let dirs = children.get(dir).map(Vec::as_slice).unwrap_or_default();
+            for (i, child) in dirs.iter().enumerate().rev() {
+                let (branch, indent) = if i + 1 == dirs.len() {
+                    ("└── ", "    ")
+                } else {
+                    ("├── ", "│   ")
+                };
+                stack.push((
+                    child.to_path_buf(),
+                    format!("{}{}", prefix, branch),
+                    format!("{}{}", prefix, indent),
+                ));
+            }
+        };
+    push_children(&mut stack, path::Path::new(""), "");
+    while let Some((dir, line_prefix, child_prefix)) = stack.pop() {
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        lines.push(format!("{}{}", line_prefix, node(&name, &dir)));
+        push_children(&mut stack, &dir, &child_prefix);
+    }
+    lines
+}
+
+/// Groups the files of the target directory, which must contain at least one file
+fn group_target_files(
+    path: &path::Path,
+    options: &PlanOptions,
+) -> io::Result<(Buckets, Vec<SkippedEntry>)> {
+    let (groups, skipped) = group_files_by_bucket(path, options)?;
+    if groups.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No files found in the directory. Remember that the program only works with files, not directories.",
+        ));
+    }
+    Ok((groups, skipped))
+}
+
+fn is_hidden(name: &ffi::OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
+/// Device of the filesystem holding the entry, where the platform tells
+#[cfg(unix)]
+fn device_id(meta: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_meta: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Percentage of the space in use, out of the space available to unprivileged users like df reports it
+fn usage_percent(used: u64, available: u64) -> f64 {
+    match used + available {
+        0 => 0.0,
+        total => used as f64 * 100.0 / total as f64,
+    }
+}
+
+/// Statistics of the filesystem holding the path
+#[cfg(unix)]
+fn statvfs(path: &path::Path) -> io::Result<libc::statvfs> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: c_path is NUL-terminated and stat is only read once statvfs filled it
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { stat.assume_init() })
+}
+
+/// Percentage of the filesystem holding the path in use
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // The statvfs field types differ between platforms
+fn disk_usage_percent(path: &path::Path) -> io::Result<f64> {
+    let stat = statvfs(path)?;
+    let block_size = stat.f_frsize as u64;
+    Ok(usage_percent(
+        (stat.f_blocks as u64 - stat.f_bfree as u64) * block_size,
+        stat.f_bavail as u64 * block_size,
+    ))
+}
+
+#[cfg(windows)]
+fn disk_usage_percent(path: &path::Path) -> io::Result<f64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+    let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let (mut available, mut total, mut free) = (0u64, 0u64, 0u64);
+    // SAFETY: wide_path is NUL-terminated and the out pointers are valid
+    if unsafe { GetDiskFreeSpaceExW(wide_path.as_ptr(), &mut available, &mut total, &mut free) }
+        == 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(usage_percent(total - free, available))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn disk_usage_percent(_path: &path::Path) -> io::Result<f64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "disk usage is not available on this platform",
+    ))
+}
+
+/// Percentage of the inodes of the filesystem holding the path still free for unprivileged users,
+/// 100 for filesystems without a fixed number of inodes
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // The statvfs field types differ between platforms
+fn free_inodes_percent(path: &path::Path) -> io::Result<f64> {
+    let stat = statvfs(path)?;
+    Ok(match stat.f_files as u64 {
+        0 => 100.0,
+        total => stat.f_favail as u64 as f64 * 100.0 / total as f64,
+    })
+}
+
+#[cfg(not(unix))]
+fn free_inodes_percent(_path: &path::Path) -> io::Result<f64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "inode counts are not available on this platform",
+    ))
+}
+
+/// Keeps the targets whose filesystem has less than `threshold` percent of its inodes free, reporting the others
+fn targets_inodes_below(
+    report: &mut Report,
+    targets: Vec<path::PathBuf>,
+    threshold: u32,
+    free_inodes: impl Fn(&path::Path) -> io::Result<f64>,
+) -> Result<Vec<path::PathBuf>, (path::PathBuf, io::Error)> {
+    let mut below = Vec::new();
+    for path in targets {
+        let percent = match free_inodes(&path) {
+            Ok(percent) => percent,
+            Err(err) => return Err((path, err)),
+        };
+        if percent < threshold as f64 {
+            below.push(path);
+        } else {
+            report.info(format_args!(
+                "{}: {:.1}% of the inodes are free, not below {}%, skipped.",
+                path.display(),
+                percent,
+                threshold
+            ));
+        }
+    }
+    Ok(below)
+}
+
+/// Keeps the targets whose filesystem is more than `threshold` percent full, reporting the others
+fn targets_above(
+    report: &mut Report,
+    targets: Vec<path::PathBuf>,
+    threshold: u32,
+    usage: impl Fn(&path::Path) -> io::Result<f64>,
+) -> Result<Vec<path::PathBuf>, (path::PathBuf, io::Error)> {
+    let mut above = Vec::new();
+    for path in targets {
+        let percent = match usage(&path) {
+            Ok(percent) => percent,
+            Err(err) => return Err((path, err)),
+        };
+        if percent > threshold as f64 {
+            above.push(path);
+        } else {
+            report.info(format_args!(
+                "{}: disk usage {:.1}% is below {}%, skipped.",
+                path.display(),
+                percent,
+                threshold
+            ));
+        }
+    }
+    Ok(above)
+}
+
+/// Whether a directory is on another filesystem than the root, e.g. a mount point
+fn crosses_filesystem(root_device: Option<u64>, dir_device: Option<u64>) -> bool {
+    matches!((root_device, dir_device), (Some(root), Some(dir)) if root != dir)
+}
+
+fn group_files_by_bucket_recursive(
+    verbosity: Verbosity,
+    root: &path::Path,
+    options: &PlanOptions,
+) -> io::Result<(
+    collections::BTreeMap<path::PathBuf, Buckets>,
+    Vec<SkippedEntry>,
+)> {
+    let mut all_groups = collections::BTreeMap::new();
+    let mut skipped = Vec::new();
+    let mut pruned = Vec::new();
+    let root_device = fs::metadata(root).ok().and_then(|meta| device_id(&meta));
+    let walker = WalkDir::new(root)
+        .sort_by_file_name() // Same order on every filesystem
+        .into_iter()
+        .filter_entry(|entry| {
+            if entry.depth() == 0 || !entry.file_type().is_dir() {
+                return true; // The root itself is always processed, even if hidden
+            }
+            let reason = if entry.file_name() == QUARANTINE_DIR_NAME {
+                Some(SkipReason::Quarantine)
+            } else if options.skip_hidden && is_hidden(entry.file_name()) {
+                Some(SkipReason::Hidden)
+            } else if options.same_filesystem
+                && crosses_filesystem(
+                    root_device,
+                    entry.metadata().ok().and_then(|meta| device_id(&meta)),
+                )
+            {
+                Some(SkipReason::OtherFilesystem)
+            } else {
+                None
+            };
+            match reason {
+                Some(reason) => {
+                    pruned.push(SkippedEntry::new(entry.path().to_path_buf(), reason));
+                    false
+                }
+                None => true,
+            }
+        });
+    let mut dirs = Vec::new();
+    let mut unreadable = collections::HashSet::new();
+    for entry in walker {
+        match entry {
+            Ok(entry) if entry.file_type().is_dir() => dirs.push(entry.into_path()),
+            Ok(_) => {}
+            Err(err) => {
+                let dir = err.path().unwrap_or(root).to_path_buf();
+                let (kind, message) = match err.io_error() {
+                    Some(io_err) => (io_err.kind(), io_err.to_string()),
+                    None => (io::ErrorKind::Other, err.to_string()),
+                };
+                match options.walk_errors {
+                    WalkErrors::Fail => {
+                        return Err(io::Error::new(
+                            kind,
+                            format!("Could not read {}: {}", dir.display(), message),
+                        ));
+                    }
+                    WalkErrors::Warn => {
+                        eprintln!(
+                            "Warning: skipping {}: could not read directory: {}",
+                            dir.display(),
+                            message
+                        );
+                        skipped.push(SkippedEntry::new(
+                            dir.clone(),
+                            SkipReason::WalkError(message),
+                        ));
+                    }
+                    WalkErrors::Ignore => {}
+                }
+                unreadable.insert(dir);
+            }
+        }
+    }
+    let dirs: Vec<_> = dirs
+        .into_iter()
+        .filter(|dir| !unreadable.contains(dir))
+        .filter(|dir| {
+            let unchanged = options
+                .dir_cache
+                .as_ref()
+                .is_some_and(|cache| cache.unchanged(dir));
+            if unchanged {
+                verbosity.detail(format_args!(
+                    "Directory {} is unchanged since the last run. Skipping.",
+                    dir.display()
+                ));
+                skipped.push(SkippedEntry::new(dir.clone(), SkipReason::Unchanged));
+            }
+            !unchanged
+        })
+        .collect();
+    for (dir_path, scan) in dirs.iter().zip(scan_dirs(&dirs, options)) {
+        let (groups, dir_skipped) = scan?;
+        // Subdirectories are visited by the walk itself, so they are not skipped entries here
+        skipped.extend(
+            dir_skipped
+                .into_iter()
+                .filter(|entry| entry.reason != SkipReason::Directory),
+        );
+        let file_count: usize = groups.values().map(Vec::len).sum();
+        if groups.is_empty() {
+            verbosity.detail(format_args!(
+                "Directory {} is empty. Skipping.",
+                dir_path.display()
+            ));
+        } else if file_count < options.min_files {
+            verbosity.detail(format_args!(
+                "Directory {} has only {} files. Skipping.",
+                dir_path.display(),
+                file_count
+            ));
+            skipped.push(SkippedEntry::new(
+                dir_path.to_path_buf(),
+                SkipReason::TooFewFiles,
+            ));
+        } else {
+            all_groups.insert(dir_path.to_path_buf(), groups);
+        }
+    }
+
+    // Unchanged directories may hold files, they are only not scanned again
+    let unchanged = skipped
+        .iter()
+        .any(|entry| entry.reason == SkipReason::Unchanged);
+    if all_groups.is_empty() && !unchanged {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No files found in the directory or its subdirectories. Remember that the program only works with files, not directories.",
+        ));
+    }
+
+    skipped.extend(pruned);
+    Ok((all_groups, skipped))
+}
+
+/// Prints how many files are in each time segment, without planning any deletion.
+/// With a width, as a bar chart of that many columns.
+fn count_files(
+    verbosity: Verbosity,
+    path: &path::Path,
+    options: &PlanOptions,
+    histogram_width: Option<usize>,
+) -> io::Result<()> {
+    let (all_groups, skipped) = if options.recursive {
+        group_files_by_bucket_recursive(verbosity, path, options)?
+    } else {
+        let (groups, skipped) = group_target_files(path, options)?;
+        (
+            collections::BTreeMap::from([(path.to_path_buf(), groups)]),
+            skipped,
+        )
+    };
+    for entry in skipped {
+        verbosity.detail(format_args!(
+            "Skipping {}: {}",
+            entry.path.display(),
+            entry.reason
+        ));
+    }
+    for (dir, groups) in all_groups {
+        println!("\n{}:", dir.display());
+        if let Some(width) = histogram_width {
+            for row in histogram_rows(&groups, options, width) {
+                println!("{}", row);
+            }
+            continue;
+        }
+        for (bucket, files) in groups {
+            println!("{}: {} files", options.bucket_label(bucket), files.len());
+        }
+    }
+    Ok(())
+}
+
+/// Sorts the files of a directory into time segments and describes them, without planning any deletion
+fn dump_buckets(path: &path::Path, options: &PlanOptions) -> io::Result<Vec<DirectoryBuckets>> {
+    let all_groups = if options.recursive {
+        // Skipped entries are not reported, so the output stays valid JSON
+        group_files_by_bucket_recursive(Verbosity::Quiet, path, options)?.0
+    } else {
+        collections::BTreeMap::from([(path.to_path_buf(), group_target_files(path, options)?.0)])
+    };
+    Ok(all_groups
+        .into_iter()
+        .map(|(dir, groups)| DirectoryBuckets {
+            path: dir,
+            age_unit: options.age_unit.name(),
+            buckets: groups
+                .into_iter()
+                .map(|(bucket, files)| {
+                    let (youngest, oldest) = options.bucket_ages(bucket);
+                    let paths: Vec<_> = files
+                        .into_iter()
+                        .sorted_by(by_time)
+                        .map(|(file, _)| file)
+                        .collect();
+                    BucketDump {
+                        boundary: (bucket != OLDEST_BUCKET).then_some(bucket),
+                        label: options.bucket_label(bucket),
+                        youngest,
+                        oldest,
+                        files: paths.len(),
+                        bytes: total_size(&paths, options.dereference_size),
+                        paths,
+                    }
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+/// Times scanning a directory and sorting its files into time segments `iterations` times, without planning any deletion
+fn benchmark(
+    verbosity: Verbosity,
+    path: &path::Path,
+    options: &PlanOptions,
+    iterations: u32,
+) -> io::Result<()> {
+    println!("\nBenchmarking {}:", path.display());
+    let mut total_elapsed = time::Duration::ZERO;
+    let mut all_groups = collections::BTreeMap::new();
+    for iteration in 1..=iterations {
+        let started = time::Instant::now();
+        all_groups = if options.recursive {
+            group_files_by_bucket_recursive(verbosity, path, options)?.0
+        } else {
+            collections::BTreeMap::from([(
+                path.to_path_buf(),
+                group_target_files(path, options)?.0,
+            )])
+        };
+        let elapsed = started.elapsed();
+        total_elapsed += elapsed;
+        let files = all_groups
+            .values()
+            .flat_map(|groups| groups.values())
+            .flatten()
+            .count();
+        println!(
+            "Scan {}: {} files in {:.3}s ({:.0} files/s)",
+            iteration,
+            files,
+            elapsed.as_secs_f64(),
+            per_second(files, elapsed)
+        );
+    }
+    let files: Vec<_> = all_groups
+        .values()
+        .flat_map(|groups| groups.values())
+        .flatten()
+        .map(|(file, _)| file.clone())
+        .collect();
+    let buckets: usize = all_groups.values().map(|groups| groups.len()).sum();
+    println!(
+        "Average: {:.3}s per scan ({:.0} files/s), {} files in {} time segments of {} directories, {} bytes",
+        total_elapsed.as_secs_f64() / iterations as f64,
+        per_second(files.len() * iterations as usize, total_elapsed),
+        files.len(),
+        buckets,
+        all_groups.len(),
+        total_size(&files, options.dereference_size)
+    );
+    Ok(())
+}
+
+/// Width of the terminal on stdout, else from $COLUMNS, 80 if unknown
+fn terminal_width() -> usize {
+    window_columns(&io::stdout())
+        .or_else(|| {
+            env::var("COLUMNS")
+                .ok()
+                .and_then(|columns| columns.parse().ok())
+        })
+        .unwrap_or(80)
+}
+
+/// Columns of the terminal behind the descriptor, None when it is not a terminal
+#[cfg(unix)]
+fn window_columns(fd: &impl std::os::fd::AsRawFd) -> Option<usize> {
+    let mut size = std::mem::MaybeUninit::<libc::winsize>::uninit();
+    // SAFETY: TIOCGWINSZ only fills the winsize, which is only read once the call succeeded
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCGWINSZ, size.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let size = unsafe { size.assume_init() };
+    (size.ws_col > 0).then_some(usize::from(size.ws_col))
+}
+
+#[cfg(not(unix))]
+fn window_columns<T>(_fd: &T) -> Option<usize> {
+    None
+}
+
+/// One bar per time segment, scaled so the longest row fits in `width` columns
+fn histogram_rows(groups: &Buckets, options: &PlanOptions, width: usize) -> Vec<String> {
+    let rows: Vec<_> = groups
+        .iter()
+        .map(|(bucket, files)| {
+            let bytes: u64 = files
+                .iter()
+                .map(|(file, _)| file_size(file, options.dereference_size))
+                .sum();
+            (
+                options.bucket_label(*bucket),
+                files.len(),
+                format!("{} files ({} bytes)", files.len(), bytes),
+            )
+        })
+        .collect();
+    let label_width = rows
+        .iter()
+        .map(|(label, _, _)| label.len())
+        .max()
+        .unwrap_or(0);
+    let totals_width = rows
+        .iter()
+        .map(|(_, _, totals)| totals.len())
+        .max()
+        .unwrap_or(0);
+    let bar_width = width.saturating_sub(label_width + totals_width + 4).max(10);
+    let most = rows.iter().map(|(_, count, _)| *count).max().unwrap_or(1);
+    rows.into_iter()
+        .map(|(label, count, totals)| {
+            let bar = "#".repeat((count * bar_width / most).max(1));
+            format!("{:<label_width$} | {:<bar_width$} {}", label, bar, totals)
+        })
+        .collect()
+}
+
+fn exp_sort_and_list_to_del(
+    report: &mut Report,
+    path: &path::Path,
+    options: &PlanOptions,
+) -> io::Result<Plan> {
+    let verbosity = report.verbosity;
+    let mut plan = Plan::default();
+    let (mut all_groups, skipped) = if options.recursive {
+        group_files_by_bucket_recursive(verbosity, path, options)?
+    } else {
+        let (groups, skipped) = group_target_files(path, options)?;
+        (
+            collections::BTreeMap::from([(path.to_path_buf(), groups)]),
+            skipped,
+        )
+    };
+    // Files younger than the first boundary, whose segment --protect-first-bucket keeps even
+    // once --merge-below merged it into an older one
+    let youngest: collections::HashSet<path::PathBuf> = if options.protect_first_bucket {
+        all_groups
+            .values()
+            .filter_map(|groups| groups.get(&options.bucket_for(0)))
+            .flatten()
+            .map(|(file, _)| file.clone())
+            .collect()
+    } else {
+        collections::HashSet::new()
+    };
+    for groups in all_groups.values_mut() {
+        *groups = merge_small_buckets(mem::take(groups), options.merge_below);
+    }
+    let duplicates = match options.dedupe_scope {
+        Some(scope) => take_duplicates(&mut all_groups, scope, options),
+        None => Vec::new(),
+    };
+    for (dir, groups) in all_groups {
+        process_dir(report, &mut plan, groups, options, &dir, &youngest);
+    }
+    plan.skipped = skipped;
+    if !duplicates.is_empty() {
+        report.info(format_args!("\nCopies of newer files:"));
+        for (file, time, bucket) in duplicates {
+            list_file(report, options, &file, time, bucket, "duplicate");
+            plan.files.push(PlannedFile {
+                path: file,
+                time,
+                bucket,
+                action: Action::Delete,
+                checksum: None,
+            });
+        }
+    }
+    if options.keep_linked {
+        let linked = keep_linked(&mut plan);
+        if !linked.is_empty() {
+            report.info(format_args!("\nKept because other files link to them:"));
+            for file in linked {
+                report.listing(format_args!(
+                    "{} <-- linked",
+                    report.shown_path(&file).display()
+                ));
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// Merges each time segment with fewer than `min_files` files into the next older ones until
+/// they hold enough files. The merged segment is the oldest one, and a small tail is merged into
+/// the segment before it.
+fn merge_small_buckets(groups: Buckets, min_files: usize) -> Buckets {
+    if min_files <= 1 {
+        return groups;
+    }
+    let mut merged = Buckets::new();
+    let mut pending = Vec::new();
+    let mut last_bucket = None;
+    for (bucket, files) in groups {
+        pending.extend(files);
+        last_bucket = Some(bucket);
+        if pending.len() >= min_files {
+            merged.insert(bucket, mem::take(&mut pending));
+        }
+    }
+    if let Some(last_bucket) = last_bucket
+        && !pending.is_empty()
+    {
+        if let Some((_, mut files)) = merged.pop_last() {
+            files.append(&mut pending);
+            pending = files;
+        }
+        merged.insert(last_bucket, pending);
+    }
+    merged
+}
+
+/// Takes the older copies of files with the same contents out of the time segments, leaving the
+/// newest one. Files are only hashed when another one in the scope has the same size.
+fn take_duplicates(
+    all_groups: &mut collections::BTreeMap<path::PathBuf, Buckets>,
+    scope: DedupeScope,
+    options: &PlanOptions,
+) -> Vec<(path::PathBuf, time::SystemTime, u64)> {
+    let mut by_size: collections::BTreeMap<_, Vec<_>> = collections::BTreeMap::new();
+    for (dir, groups) in all_groups.iter() {
+        for (bucket, files) in groups {
+            for (file, time) in files {
+                if matches_name(file, &options.protect, options.case_sensitive_ext) {
+                    continue;
+                }
+                // Empty directories of --include-empty-dirs have no contents to compare
+                let Some(meta) = fs::metadata(file).ok().filter(fs::Metadata::is_file) else {
+                    continue;
+                };
+                let key = match scope {
+                    DedupeScope::Bucket => (Some(dir), Some(*bucket)),
+                    DedupeScope::Directory => (Some(dir), None),
+                    DedupeScope::Global => (None, None),
+                };
+                by_size
+                    .entry((key, meta.len()))
+                    .or_default()
+                    .push((file.clone(), *time, *bucket));
+            }
+        }
+    }
+    let mut duplicates = Vec::new();
+    for (_, candidates) in by_size.into_iter().filter(|(_, files)| files.len() > 1) {
+        let mut by_digest: collections::BTreeMap<_, Vec<_>> = collections::BTreeMap::new();
+        for (file, time, bucket) in candidates {
+            match ChecksumAlgorithm::Sha256.digest_file(&file) {
+                Ok(digest) => by_digest
+                    .entry(digest)
+                    .or_default()
+                    .push((file, time, bucket)),
+                Err(err) => eprintln!("Warning: could not hash {}: {}", file.display(), err),
+            }
+        }
+        for (_, mut copies) in by_digest {
+            copies.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))); // Newest first
+            duplicates.extend(copies.into_iter().skip(1));
+        }
+    }
+    duplicates.sort();
+    let taken: collections::HashSet<_> = duplicates.iter().map(|(file, _, _)| file).collect();
+    for groups in all_groups.values_mut() {
+        for files in groups.values_mut() {
+            files.retain(|(file, _)| !taken.contains(file));
+        }
+        groups.retain(|_, files| !files.is_empty());
+    }
+    duplicates
+}
+
+/// Identity of the file contents, shared by hard links
+#[cfg(unix)]
+fn inode(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Moves files to be deleted which are the target of a scanned symlink, or a hard link of a kept file,
+/// to the kept ones. Returns the moved files.
+fn keep_linked(plan: &mut Plan) -> Vec<path::PathBuf> {
+    let symlink_targets: collections::HashSet<_> = plan
+        .skipped
+        .iter()
+        .filter(|entry| entry.reason == SkipReason::Symlink)
+        .filter_map(|entry| fs::canonicalize(&entry.path).ok())
+        .collect();
+    let kept_inodes: collections::HashSet<_> = plan
+        .files
+        .iter()
+        .filter(|file| file.action == Action::Keep)
+        .filter_map(|file| inode(&fs::metadata(&file.path).ok()?))
+        .collect();
+    let mut linked = Vec::new();
+    for file in plan
+        .files
+        .iter_mut()
+        .filter(|file| file.action == Action::Delete)
+    {
+        let is_symlink_target =
+            fs::canonicalize(&file.path).is_ok_and(|target| symlink_targets.contains(&target));
+        let is_hard_link = fs::metadata(&file.path)
+            .ok()
+            .and_then(|meta| inode(&meta))
+            .is_some_and(|inode| kept_inodes.contains(&inode));
+        if is_symlink_target || is_hard_link {
+            file.action = Action::Keep;
+            linked.push(file.path.clone());
+        }
+    }
+    linked
+}
+
+/// Splits the time segments of a directory into separate ones for each file extension
+fn split_by_extension(
+    groups: Buckets,
+    case_sensitive: bool,
+) -> collections::BTreeMap<String, Buckets> {
+    let mut by_extension: collections::BTreeMap<String, Buckets> = collections::BTreeMap::new();
+    for (bucket, files) in groups {
+        for (file, time) in files {
+            let extension = file
+                .extension()
+                .map(|ext| normalize_extension(&ext.to_string_lossy(), case_sensitive))
+                .unwrap_or_default();
+            by_extension
+                .entry(extension)
+                .or_default()
+                .entry(bucket)
+                .or_default()
+                .push((file, time));
+        }
+    }
+    by_extension
+}
+
+fn process_dir(
+    report: &mut Report,
+    plan: &mut Plan,
+    groups: Buckets,
+    options: &PlanOptions,
+    dir: &path::Path,
+    youngest: &collections::HashSet<path::PathBuf>,
+) {
+    if report.condense {
+        // Plan the directory silently first, to see whether anything would be deleted
+        let verbosity = report.verbosity;
+        report.verbosity = Verbosity::Quiet;
+        let mut dir_plan = Plan::default();
+        report.condense = false;
+        process_dir(
+            report,
+            &mut dir_plan,
+            groups.clone(),
+            options,
+            dir,
+            youngest,
+        );
+        (report.verbosity, report.condense) = (verbosity, true);
+        if dir_plan
+            .files
+            .iter()
+            .all(|file| file.action == Action::Keep)
+        {
+            report.info(format_args!(
+                "\n{}: {} files, all kept",
+                dir.display(),
+                dir_plan.files.len()
+            ));
+            plan.files.extend(dir_plan.files);
+            return;
+        }
+    }
+    if !options.group_by_extension {
+        return process_groups(report, plan, &groups, options, dir, None, youngest);
+    }
+    for (extension, groups) in split_by_extension(groups, options.case_sensitive_ext) {
+        process_groups(
+            report,
+            plan,
+            &groups,
+            options,
+            dir,
+            Some(&extension),
+            youngest,
+        );
+    }
+}
+
+/// Directory for files kept between runs: $XDG_STATE_HOME/expdel, by default ~/.local/state/expdel
+fn state_dir() -> Option<path::PathBuf> {
+    let state_home = match env::var_os("XDG_STATE_HOME") {
+        Some(state_home) if !state_home.is_empty() => path::PathBuf::from(state_home),
+        _ => match env::var_os("HOME") {
+            Some(home) => path::Path::new(&home).join(".local").join("state"),
+            None => path::PathBuf::from(env::var_os("LOCALAPPDATA")?), // Windows
+        },
+    };
+    Some(state_home.join("expdel"))
+}
+
+fn append_history(record: &HistoryRecord) -> io::Result<()> {
+    let dir = state_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No state directory found"))?;
+    fs::create_dir_all(&dir)?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("history.jsonl"))?;
+    let line = serde_json::to_string(record).map_err(io::Error::other)?;
+    writeln!(file, "{}", line)
+}
+
+/// Runs the --post-hook command in the shell, with the outcome of the deletion in its environment
+fn run_post_hook(
+    hook: &str,
+    deleted: usize,
+    freed_bytes: u64,
+    failed: usize,
+) -> io::Result<process::ExitStatus> {
+    let mut command = if cfg!(windows) {
+        let mut command = process::Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = process::Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    command
+        .arg(hook)
+        .env("EXPDEL_DELETED_COUNT", deleted.to_string())
+        .env("EXPDEL_FREED_BYTES", freed_bytes.to_string())
+        .env("EXPDEL_FAILED_COUNT", failed.to_string())
+        .status()
+}
+
+/// Takes an exclusive advisory lock, failing at once if another process holds it
+fn acquire_lock(lock_path: &path::Path) -> io::Result<fs::File> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(fs::TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "another ExpDel instance is already running on this directory",
+        )),
+        Err(fs::TryLockError::Error(err)) => Err(err),
+    }
+}
+
+/// Reads the directories listed in the file, skipping empty lines and # comments
+fn read_paths_file(paths_file: &path::Path) -> io::Result<Vec<path::PathBuf>> {
+    Ok(fs::read_to_string(paths_file)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(path::PathBuf::from)
+        .collect())
+}
+
+/// The settings of `PlanOptions` deciding which files are deleted, for the plan fingerprint.
+/// How the plan is shown and how fast it is made are left out, so they may differ from the preview.
+#[derive(Serialize)]
+struct PlanSettings<'a> {
+    sort_type: &'a SortType,
+    date_from_name: Option<&'a str>,
+    age_unit: AgeUnit,
+    age_reference: AgeReference,
+    boundaries: &'a Option<BucketBoundaries>,
+    merge_below: usize,
+    dedupe_scope: Option<DedupeScope>,
+    time_resolution: Option<time::Duration>,
+    keep: u32,
+    keep_for: &'a [KeepOverride],
+    policy: &'a Option<RetentionPolicy>,
+    keep_total: Option<usize>,
+    keep_percent: Option<u32>,
+    bucket_keep_min: u32,
+    protect_first_bucket: bool,
+    calendar: Option<CalendarRetention>,
+    target_size: Option<u64>,
+    dereference_size: bool,
+    group_by_extension: bool,
+    case_sensitive_ext: bool,
+    protect: Vec<&'a str>,
+    prefer_keep: Vec<&'a str>,
+    extensions: &'a [String],
+    keep_linked: bool,
+    recursive: bool,
+    include_empty_dirs: bool,
+    skip_hidden: bool,
+    same_filesystem: bool,
+    min_files: usize,
+    mark: Option<&'a str>,
+}
+
+impl<'a> PlanSettings<'a> {
+    fn new(options: &'a PlanOptions) -> Self {
+        let patterns =
+            |patterns: &'a [glob::Pattern]| patterns.iter().map(glob::Pattern::as_str).collect();
+        PlanSettings {
+            sort_type: &options.sort_type,
+            date_from_name: options.date_from_name.as_ref().map(regex::Regex::as_str),
+            age_unit: options.age_unit,
+            age_reference: options.age_reference,
+            boundaries: &options.boundaries,
+            merge_below: options.merge_below,
+            dedupe_scope: options.dedupe_scope,
+            time_resolution: options.time_resolution,
+            keep: options.keep,
+            keep_for: &options.keep_for,
+            policy: &options.policy,
+            keep_total: options.keep_total,
+            keep_percent: options.keep_percent,
+            bucket_keep_min: options.bucket_keep_min,
+            protect_first_bucket: options.protect_first_bucket,
+            calendar: options.calendar,
+            target_size: options.target_size,
+            dereference_size: options.dereference_size,
+            group_by_extension: options.group_by_extension,
+            case_sensitive_ext: options.case_sensitive_ext,
+            protect: patterns(&options.protect),
+            prefer_keep: patterns(&options.prefer_keep),
+            extensions: &options.extensions,
+            keep_linked: options.keep_linked,
+            recursive: options.recursive,
+            include_empty_dirs: options.include_empty_dirs,
+            skip_hidden: options.skip_hidden,
+            same_filesystem: options.same_filesystem,
+            min_files: options.min_files,
+            mark: options.mark.as_deref(),
+        }
+    }
+}
+
+/// Identifies the target directory and the settings the plan was made with
+fn plan_fingerprint(path: &path::Path, options: &PlanOptions) -> String {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let settings = serde_json::to_string(&PlanSettings::new(options))
+        .expect("Settings are always serializable");
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(path.to_string_lossy().as_bytes());
+    hasher.update(settings.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn preview_path(fingerprint: &str) -> io::Result<path::PathBuf> {
+    let dir = state_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No state directory found"))?;
+    Ok(dir.join("previews").join(fingerprint))
+}
+
+fn save_preview(fingerprint: &str) -> io::Result<()> {
+    let preview = preview_path(fingerprint)?;
+    fs::create_dir_all(preview.parent().unwrap_or(&preview))?;
+    fs::write(preview, chrono::Local::now().to_rfc3339())
+}
+
+/// Time since the plan was last previewed, if it ever was
+fn preview_age(fingerprint: &str) -> Option<time::Duration> {
+    let content = fs::read_to_string(preview_path(fingerprint).ok()?).ok()?;
+    let previewed = chrono::DateTime::parse_from_rfc3339(content.trim()).ok()?;
+    (chrono::Local::now() - previewed.with_timezone(&chrono::Local))
+        .to_std()
+        .ok()
+}
+
+/// When each file was first scanned with --grace, in seconds since the Unix epoch
+type FirstSeen = collections::BTreeMap<path::PathBuf, u64>;
+
+/// Modification time of each directory scanned with --skip-unchanged-dirs
+type DirMtimes = collections::HashMap<path::PathBuf, time::SystemTime>;
+
+fn first_seen_path() -> io::Result<path::PathBuf> {
+    let dir = state_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No state directory found"))?;
+    Ok(dir.join("first_seen.json"))
+}
+
+/// The recorded first sightings, none if the file is missing or unreadable
+fn load_first_seen(file: &path::Path) -> FirstSeen {
+    fs::read_to_string(file)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_first_seen(file: &path::Path, first_seen: &FirstSeen) -> io::Result<()> {
+    fs::create_dir_all(file.parent().unwrap_or(file))?;
+    fs::write(
+        file,
+        serde_json::to_string(first_seen).map_err(io::Error::other)?,
+    )
+}
+
+fn dir_mtimes_path() -> io::Result<path::PathBuf> {
+    let dir = state_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No state directory found"))?;
+    Ok(dir.join("dir_mtimes.json"))
+}
+
+/// The recorded directory times, none if the file is missing or unreadable
+fn load_dir_mtimes(file: &path::Path) -> DirMtimes {
+    fs::read_to_string(file)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_dir_mtimes(file: &path::Path, dir_mtimes: &DirMtimes) -> io::Result<()> {
+    fs::create_dir_all(file.parent().unwrap_or(file))?;
+    fs::write(
+        file,
+        serde_json::to_string(dir_mtimes).map_err(io::Error::other)?,
+    )
+}
+
+fn load_plan(plan_path: &path::Path) -> io::Result<SavedPlan> {
+    let content = fs::read_to_string(plan_path)?;
+    serde_json::from_str(&content).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn compare_plans(previous: &SavedPlan, current: &SavedPlan) -> PlanDiff {
+    let previously_deleted: collections::HashSet<_> = previous.delete.iter().collect();
+    let currently_deleted: collections::HashSet<_> = current.delete.iter().collect();
+    PlanDiff {
+        newly_deleted: current
+            .delete
+            .iter()
+            .filter(|file| !previously_deleted.contains(file))
+            .cloned()
+            .collect(),
+        no_longer_deleted: previous
+            .delete
+            .iter()
+            .filter(|file| !currently_deleted.contains(file) && current.keep.contains(file))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Files with their action in the expected plan but not in the actual one (-), and the other way around (+),
+/// sorted by path
+fn plan_mismatches(
+    expected: &SavedPlan,
+    actual: &SavedPlan,
+) -> Vec<(char, path::PathBuf, &'static str)> {
+    let lines = |plan: &SavedPlan| -> collections::BTreeSet<(path::PathBuf, &'static str)> {
+        let keep = plan.keep.iter().map(|file| (file.clone(), "keep"));
+        let delete = plan.delete.iter().map(|file| (file.clone(), "delete"));
+        keep.chain(delete).collect()
+    };
+    let (expected, actual) = (lines(expected), lines(actual));
+    let missing = expected.difference(&actual).map(|line| ('-', line));
+    let unexpected = actual.difference(&expected).map(|line| ('+', line));
+    missing
+        .chain(unexpected)
+        .sorted_by(|(_, a), (_, b)| a.cmp(b))
+        .map(|(sign, (file, action))| (sign, file.clone(), *action))
+        .collect()
+}
+
+fn print_plan_diff(report: &mut Report, diff: &PlanDiff) {
+    report.line(format_args!(
+        "\nNewly scheduled for deletion ({} files):",
+        diff.newly_deleted.len()
+    ));
+    for file in &diff.newly_deleted {
+        report.line(format_args!("{}", file.display()));
+    }
+    report.line(format_args!(
+        "\nPreviously scheduled for deletion, now kept ({} files):",
+        diff.no_longer_deleted.len()
+    ));
+    for file in &diff.no_longer_deleted {
+        report.line(format_args!("{}", file.display()));
+    }
+}
+
+/// Quotes a CSV field if needed
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn write_plan_csv(report: &mut Report, plan: &SavedPlan) {
+    report.line(format_args!("path,action,reason"));
+    for (files, action) in [(&plan.keep, "keep"), (&plan.delete, "delete")] {
+        for file in files {
+            report.line(format_args!(
+                "{},{},",
+                csv_field(&file.to_string_lossy()),
+                action
+            ));
+        }
+    }
+    for entry in &plan.skipped {
+        report.line(format_args!(
+            "{},skipped,{}",
+            csv_field(&entry.path.to_string_lossy()),
+            csv_field(&entry.reason.to_string())
+        ));
+    }
+}
+
+/// One JSON line per planned file, written once --grace and the caps settled the actions
+fn write_plan_jsonl(report: &mut Report, plan: &SavedPlan) {
+    for (i, file) in plan.files.iter().enumerate() {
+        let line = serde_json::to_string(file).expect("Planned files are always serializable");
+        report.line(format_args!("{}", line));
+        if (i + 1).is_multiple_of(JSONL_FLUSH_INTERVAL) {
+            report.flush();
+        }
+    }
+}
+
+/// Markdown table cell, with the characters breaking the table escaped
+fn markdown_cell(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace(['\n', '\r'], " ")
+}
+
+fn write_plan_markdown(report: &mut Report, plan: &SavedPlan) {
+    report.line(format_args!("| Path | Time | Bucket | Action |"));
+    report.line(format_args!("| --- | --- | --- | --- |"));
+    for file in &plan.files {
+        let action = match file.action {
+            Action::Keep => "keep",
+            Action::Delete => "delete",
+        };
+        report.line(format_args!(
+            "| {} | {} | {} | {} |",
+            markdown_cell(&file.path.to_string_lossy()),
+            report.timestamp(file.time),
+            bucket_name(file.bucket),
+            action
+        ));
+    }
+    report.line(format_args!("\n## Summary\n"));
+    report.line(format_args!("- {} files to keep", plan.keep.len()));
+    report.line(format_args!("- {} files to be deleted", plan.delete.len()));
+    report.line(format_args!("- {} entries skipped", plan.skipped.len()));
+}
+
+/// Counts the kept and deleted files in each directory
+fn tally_by_dir(
+    to_keep: &[path::PathBuf],
+    to_delete: &[path::PathBuf],
+) -> collections::BTreeMap<path::PathBuf, (usize, usize)> {
+    let mut tally: collections::BTreeMap<path::PathBuf, (usize, usize)> =
+        collections::BTreeMap::new();
+    for file in to_keep {
+        let dir = file.parent().unwrap_or(file).to_path_buf();
+        tally.entry(dir).or_default().0 += 1;
+    }
+    for file in to_delete {
+        let dir = file.parent().unwrap_or(file).to_path_buf();
+        tally.entry(dir).or_default().1 += 1;
+    }
+    tally
+}
+
+/// Directories none of whose files are kept, with the number of files each of them loses
+fn emptied_dirs(
+    keep: &[path::PathBuf],
+    delete: &[path::PathBuf],
+) -> collections::BTreeMap<path::PathBuf, usize> {
+    let kept_dirs: collections::HashSet<_> = keep.iter().filter_map(|file| file.parent()).collect();
+    let mut emptied = collections::BTreeMap::new();
+    for dir in delete.iter().filter_map(|file| file.parent()) {
+        if !kept_dirs.contains(dir) {
+            *emptied.entry(dir.to_path_buf()).or_insert(0) += 1;
+        }
+    }
+    emptied
+}
+
+fn total_size(files: &[path::PathBuf], dereference_size: bool) -> u64 {
+    files
+        .iter()
+        .map(|file| file_size(file, dereference_size))
+        .sum()
+}
+
+/// Size of a file, a symlink counting as the link itself, which is all deleting it frees, or as
+/// its target with --dereference-size. 0 for a missing file.
+fn file_size(file: &path::Path, dereference_size: bool) -> u64 {
+    let meta = if dereference_size {
+        fs::metadata(file)
+    } else {
+        fs::symlink_metadata(file)
+    };
+    meta.map_or(0, |meta| meta.len())
+}
+
+/// Finds the files which could not be deleted, without deleting anything
+fn undeletable_files(files: &[path::PathBuf]) -> Vec<(path::PathBuf, String)> {
+    let mut writable_dirs: collections::HashMap<path::PathBuf, Result<(), String>> =
+        collections::HashMap::new();
+    files
+        .iter()
+        .filter_map(|file| {
+            let problem = match fs::symlink_metadata(file) {
+                Err(err) => Some(err.to_string()),
+                Ok(meta) if cfg!(windows) && meta.permissions().readonly() => {
+                    Some("read-only file".to_string())
+                }
+                Ok(_) => {
+                    let dir = file.parent().unwrap_or(path::Path::new("."));
+                    writable_dirs
+                        .entry(dir.to_path_buf())
+                        .or_insert_with(|| check_writable(dir))
+                        .clone()
+                        .err()
+                }
+            };
+            problem.map(|problem| (file.clone(), problem))
+        })
+        .collect()
+}
+
+/// Creating a file in the directory is the surest way to know whether entries can be removed from it
+fn check_writable(dir: &path::Path) -> Result<(), String> {
+    tempfile::NamedTempFile::new_in(dir)
+        .map(|_| ())
+        .map_err(|err| format!("directory {} is not writable: {}", dir.display(), err))
+}
+
+/// Errors worth retrying, as they might not happen again
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ResourceBusy
+    )
+}
+
+/// Runs the operation, retrying it up to `retries` times if it fails with a transient error
+fn with_retries<T>(
+    retries: u32,
+    delay: time::Duration,
+    mut operation: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                attempt += 1;
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn delete_files(
+    verbosity: Verbosity,
+    files: &[path::PathBuf],
+    options: &DeleteOptions,
+    interrupted: &atomic::AtomicBool,
+) -> io::Result<DeletionReport> {
+    let mut report = DeletionReport::default();
+    let mut journal = options
+        .journal
+        .as_ref()
+        .map(|journal| fs::OpenOptions::new().append(true).open(journal))
+        .transpose()?;
+    verbosity.info(format_args!("\nDeleting files..."));
+    let no_trash = options.recycle && !trash_available();
+    if no_trash {
+        eprintln!(
+            "Warning: there is no trash to move the files to, deleting them permanently instead."
+        );
+    }
+    for (i, file) in files.iter().enumerate() {
+        if options
+            .deadline
+            .is_some_and(|deadline| time::Instant::now() >= deadline)
+        {
+            report.timed_out = true;
+            break;
+        }
+        // Never follow a symlink, e.g. a file replaced by one since the scan or listed in a resumed
+        // journal: only the link itself is removed, its target may be outside the scanned directories
+        let link_meta = fs::symlink_metadata(file).ok();
+        let is_symlink = link_meta
+            .as_ref()
+            .is_some_and(|meta| meta.file_type().is_symlink());
+        // An empty directory of --include-empty-dirs, which may have been filled since the scan
+        let is_dir = link_meta.as_ref().is_some_and(fs::Metadata::is_dir);
+        let size = if is_dir {
+            0
+        } else {
+            file_size(file, options.dereference_size)
+        };
+        let remove = || {
+            with_retries(options.retries, options.retry_delay, || {
+                if is_dir {
+                    fs::remove_dir(file) // Fails if the directory is not empty
+                } else {
+                    fs::remove_file(file)
+                }
+            })
+        };
+        let result = if is_dir && !is_empty_dir(file) {
+            Err(io::Error::other("directory is not empty any more"))
+        } else if let Some(suffix) = &options.mark {
+            let Some(marked) = marked_path(file, suffix, options.on_conflict) else {
+                report.conflicts += 1;
+                verbosity.listing(format_args!(
+                    "File skipped, already marked: {}",
+                    file.display()
+                ));
+                continue;
+            };
+            with_retries(options.retries, options.retry_delay, || {
+                fs::rename(file, &marked)
+            })
+        } else if let Some(day) = options.quarantine {
+            let quarantined = quarantine_path(file, day);
+            let quarantined =
+                marked_path(&quarantined, "", OnConflict::Rename).unwrap_or(quarantined);
+            with_retries(options.retries, options.retry_delay, || {
+                fs::create_dir_all(quarantined.parent().unwrap_or(&quarantined))?;
+                fs::rename(file, &quarantined)
+            })
+        } else if options.recycle && !is_symlink && !no_trash {
+            match trash::delete(file) {
+                Ok(()) => Ok(()),
+                Err(err) if !file.exists() => {
+                    Err(io::Error::new(io::ErrorKind::NotFound, err.to_string()))
+                }
+                Err(err) => Err(io::Error::other(format!(
+                    "could not move it to the trash: {}",
+                    err
+                ))),
+            }
+        } else {
+            remove()
+        };
+        let result = match result {
+            Ok(()) if options.verify => verify_deleted(file),
+            result => result,
+        };
+        match result {
+            Ok(_) => {
+                report.deleted += 1;
+                if let Some(journal) = &mut journal {
+                    writeln!(journal, "{}{}", JOURNAL_DONE, escape_journal_path(file))?;
+                }
+                if options.mark.is_some() {
+                    verbosity.listing(format_args!("File marked: {}", file.display()))
+                } else if options.quarantine.is_some() {
+                    verbosity.listing(format_args!("File quarantined: {}", file.display()))
+                } else {
+                    report.freed_bytes += size; // Renaming does not free any space
+                    verbosity.listing(format_args!("File deleted: {}", file.display()))
+                }
+            }
+            Err(e) => {
+                report.failed += 1;
+                *report.errors.entry(e.kind()).or_default() += 1;
+                report.failures.push((file.clone(), e.to_string()));
+                if !options.quiet_errors {
+                    CliError::new(
+                        "delete_failed",
+                        format!("Could not delete {}: {}", file.display(), e),
+                    )
+                    .with_path(file)
+                    .print(options.error_format);
+                }
+            }
+        }
+        if interrupted.load(atomic::Ordering::SeqCst) && i + 1 < files.len() {
+            report.interrupted = true; // Stop cleanly after the current file
+            break;
+        }
+    }
+    Ok(report)
+}
+
+/// Whether there is a trash to move the files to with --recycle. The freedesktop home trash is
+/// found from $XDG_DATA_HOME or $HOME, which e.g. a system service may run without.
+#[cfg(all(
+    unix,
+    not(target_os = "macos"),
+    not(target_os = "ios"),
+    not(target_os = "android")
+))]
+fn trash_available() -> bool {
+    ["XDG_DATA_HOME", "HOME"]
+        .iter()
+        .any(|var| env::var_os(var).is_some_and(|value| !value.is_empty()))
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+fn trash_available() -> bool {
+    true
+}
+
+#[cfg(not(any(windows, all(unix, not(target_os = "ios"), not(target_os = "android")))))]
+fn trash_available() -> bool {
+    false
+}
+
+/// Fails if a file reported as deleted is still there, with --verify
+fn verify_deleted(file: &path::Path) -> io::Result<()> {
+    match fs::symlink_metadata(file) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+        Ok(_) => Err(io::Error::other("still exists after being deleted")),
+    }
+}
+
+/// Name of a file marked with the suffix, None if it is taken and `on_conflict` is skip
+fn marked_path(file: &path::Path, suffix: &str, on_conflict: OnConflict) -> Option<path::PathBuf> {
+    let with_suffix = |number: Option<u32>| {
+        let mut marked = file.as_os_str().to_os_string();
+        if let Some(number) = number {
+            marked.push(format!(".{}", number));
+        }
+        marked.push(suffix);
+        path::PathBuf::from(marked)
+    };
+    let marked = with_suffix(None);
+    if fs::symlink_metadata(&marked).is_err() {
+        return Some(marked);
+    }
+    match on_conflict {
+        OnConflict::Skip => None,
+        OnConflict::Overwrite => Some(marked),
+        OnConflict::Rename => (1..)
+            .map(|number| with_suffix(Some(number)))
+            .find(|marked| fs::symlink_metadata(marked).is_err()),
+    }
+}
+
+/// Where a file is moved to when quarantined on the given day
+fn quarantine_path(file: &path::Path, day: chrono::NaiveDate) -> path::PathBuf {
+    file.parent()
+        .unwrap_or(path::Path::new("."))
+        .join(QUARANTINE_DIR_NAME)
+        .join(day.format("%Y-%m-%d").to_string())
+        .join(file.file_name().unwrap_or_default())
+}
+
+/// Day folders of the quarantine of the directory, and of its subdirectories if `recursive`,
+/// from more than `days` days before `today`
+fn expired_quarantine(
+    root: &path::Path,
+    recursive: bool,
+    days: u32,
+    today: chrono::NaiveDate,
+) -> Vec<path::PathBuf> {
+    let quarantines: Vec<_> = if recursive {
+        WalkDir::new(root)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_dir() && entry.file_name() == QUARANTINE_DIR_NAME)
+            .map(walkdir::DirEntry::into_path)
+            .collect()
+    } else {
+        vec![root.join(QUARANTINE_DIR_NAME)]
+    };
+    quarantines
+        .iter()
+        .filter_map(|quarantine| fs::read_dir(quarantine).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            chrono::NaiveDate::parse_from_str(&entry.file_name().to_string_lossy(), "%Y-%m-%d")
+                .is_ok_and(|day| (today - day).num_days() > i64::from(days))
+        })
+        .map(|entry| entry.path())
+        .sorted()
+        .collect()
+}
+
+/// Prefix of the journal lines listing a file to delete
+const JOURNAL_PLANNED: &str = "- ";
+/// Prefix of the journal lines marking a file as deleted
+const JOURNAL_DONE: &str = "+ ";
+
+/// Starts a journal listing the files to delete
+fn write_journal(journal: &path::Path, files: &[path::PathBuf]) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(journal)?);
+    for file in files {
+        writeln!(out, "{}{}", JOURNAL_PLANNED, escape_journal_path(file))?;
+    }
+    out.flush()
+}
+
+/// The files listed in the journal which were not marked as deleted yet, in the planned order
+fn read_journal(journal: &path::Path) -> io::Result<Vec<path::PathBuf>> {
+    let content = fs::read_to_string(journal)?;
+    let done: collections::HashSet<&str> = content
+        .lines()
+        .filter_map(|line| line.strip_prefix(JOURNAL_DONE))
+        .collect();
+    content
+        .lines()
+        .filter_map(|line| line.strip_prefix(JOURNAL_PLANNED))
+        .filter(|file| !done.contains(file))
+        .map(|file| {
+            unescape_journal_path(file).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid journal line: {}{}", JOURNAL_PLANNED, file),
+                )
+            })
+        })
+        .collect()
+}
+
+/// The path on a single journal line, with its backslashes, line breaks and bytes that are not
+/// UTF-8 escaped so that any file name is read back unchanged
+fn escape_journal_path(file: &path::Path) -> String {
+    let mut line = String::new();
+    for chunk in file.as_os_str().as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => line.push_str("\\\\"),
+                '\n' => line.push_str("\\n"),
+                '\r' => line.push_str("\\r"),
+                c => line.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            line.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    line
+}
+
+/// The path written by `escape_journal_path`, None for an unknown escape
+fn unescape_journal_path(line: &str) -> Option<path::PathBuf> {
+    let mut bytes = Vec::with_capacity(line.len());
+    let mut rest = line.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let (&escape, tail) = rest.split_first()?;
+        rest = tail;
+        match escape {
+            b'\\' => bytes.push(b'\\'),
+            b'n' => bytes.push(b'\n'),
+            b'r' => bytes.push(b'\r'),
+            b'x' => {
+                let hex = std::str::from_utf8(rest.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &rest[2..];
+            }
+            _ => return None,
+        }
+    }
+    path_from_bytes(bytes)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<path::PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(ffi::OsString::from_vec(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<path::PathBuf> {
+    String::from_utf8(bytes).ok().map(path::PathBuf::from)
+}
+
+fn matches_name(file: &path::Path, patterns: &[glob::Pattern], case_sensitive_ext: bool) -> bool {
+    let name = file
+        .file_name()
+        .map(|name| normalize_name(&name.to_string_lossy(), case_sensitive_ext))
+        .unwrap_or_default();
+    patterns.iter().any(|pattern| pattern.matches(&name))
+}
+
+/// Number of files making up `percent` of `count`, rounded up
+fn percent_of(count: usize, percent: u32) -> usize {
+    (count * percent as usize).div_ceil(100)
+}
+
+/// Orders files oldest first, files with the same time by path so the plan does not depend on the directory order
+fn by_time(
+    a: &(path::PathBuf, time::SystemTime),
+    b: &(path::PathBuf, time::SystemTime),
+) -> cmp::Ordering {
+    a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0))
+}
+
+/// Picks the newest `keep_total` files across all time segments
+fn newest_files(groups: &Buckets, keep_total: usize) -> collections::HashSet<&path::PathBuf> {
+    groups
+        .values()
+        .flatten()
+        .sorted_by(|a, b| by_time(b, a))
+        .take(keep_total)
+        .map(|(file, _)| file)
+        .collect()
+}
+
+/// Picks the files left after deleting the oldest ones, oldest time segment first, until the rest fit in `target_size` bytes
+fn files_within_size(
+    groups: &Buckets,
+    target_size: u64,
+    dereference_size: bool,
+) -> collections::HashSet<&path::PathBuf> {
+    let sized: Vec<_> = groups
+        .values()
+        .rev()
+        .flat_map(|files| files.iter().sorted_by(|a, b| by_time(a, b)))
+        .map(|(file, _)| (file, file_size(file, dereference_size)))
+        .collect();
+    let mut remaining: u64 = sized.iter().map(|(_, size)| size).sum();
+    sized
+        .into_iter()
+        .skip_while(|(_, size)| {
+            let over = remaining > target_size;
+            if over {
+                remaining -= size;
+            }
+            over
+        })
+        .map(|(file, _)| file)
+        .collect()
+}
+
+/// Picks the first file of each of the last days, weeks and months having any files
+fn calendar_files<'a>(
+    groups: &'a Buckets,
+    retention: &CalendarRetention,
+) -> collections::HashSet<&'a path::PathBuf> {
+    let files: Vec<_> = groups
+        .values()
+        .flatten()
+        .sorted_by(|a, b| by_time(a, b))
+        .collect();
+    type PeriodOf = fn(&chrono::DateTime<chrono::Local>) -> (i32, u32);
+    let periods: [(u32, PeriodOf); 3] = [
+        (retention.daily, |dt| (dt.year(), dt.ordinal())),
+        (retention.weekly, |dt| {
+            (dt.iso_week().year(), dt.iso_week().week())
+        }),
+        (retention.monthly, |dt| (dt.year(), dt.month())),
+    ];
+    let mut selected = collections::HashSet::new();
+    for (count, period_of) in periods {
+        let mut first_of_period = collections::BTreeMap::new();
+        for (file, time) in &files {
+            let datetime: chrono::DateTime<chrono::Local> = (*time).into();
+            first_of_period.entry(period_of(&datetime)).or_insert(file);
+        }
+        selected.extend(first_of_period.into_values().rev().take(count as usize));
+    }
+    selected
+}
+
+/// Hashes every file to be deleted, so the history proves what was removed
+fn record_checksums(plan: &mut Plan, algorithm: ChecksumAlgorithm) {
+    for file in plan
+        .files
+        .iter_mut()
+        .filter(|file| file.action == Action::Delete && !file.path.is_dir())
+    {
+        match algorithm.digest_file(&file.path) {
+            Ok(digest) => file.checksum = Some(digest),
+            Err(err) => eprintln!("Warning: could not hash {}: {}", file.path.display(), err),
+        }
+    }
+}
+
+fn process_groups(
+    report: &mut Report,
+    plan: &mut Plan,
+    groups: &Buckets,
+    options: &PlanOptions,
+    dir: &path::Path,
+    extension: Option<&str>,
+    youngest: &collections::HashSet<path::PathBuf>,
+) {
+    let target = match extension {
+        Some("") => format!(
+            "{} (files without extension)",
+            report.shown_path(dir).display()
+        ),
+        Some(extension) => format!(
+            "{} (*.{} files)",
+            report.shown_path(dir).display(),
+            extension
+        ),
+        None => report.shown_path(dir).display().to_string(),
+    };
+    // Strategies other than --keep pick the kept files across all time segments up front
+    let (strategy, newest) = if let Some(retention) = options.calendar {
+        (
+            format!(
+                "keeping the first file of the last {} days, {} weeks and {} months",
+                retention.daily, retention.weekly, retention.monthly
+            ),
+            Some(calendar_files(groups, &retention)),
+        )
+    } else if let Some(target_size) = options.target_size {
+        (
+            format!("deleting the oldest files down to {} bytes", target_size),
+            Some(files_within_size(
+                groups,
+                target_size,
+                options.dereference_size,
+            )),
+        )
+    } else if let Some(keep_total) = options.keep_total {
+        (
+            format!("keeping {} newest files in total", keep_total),
+            Some(newest_files(groups, keep_total)),
+        )
+    } else if let Some(percent) = options.keep_percent {
+        (format!("keeping {}% of the files", percent), None)
+    } else {
+        match &options.policy {
+            Some(policy) => (
+                format!(
+                    "keeping {} files per time segment",
+                    policy.keep.iter().join("/")
+                ),
+                None,
+            ),
+            None => (format!("keeping {} files", options.keep_in(dir, 0)), None),
+        }
+    };
+    report.info(format_args!(
+        "\nOpening {}, sorting by {:?} and {}",
+        target, options.sort_type, strategy
+    ));
+    if options.explain {
+        for bucket in groups.keys() {
+            report.info(format_args!("{}", options.explain_bucket(*bucket)));
+        }
+    }
+    let mut buckets: Vec<_> = groups.iter().collect();
+    if options.reverse {
+        buckets.reverse();
+    }
+    for (bucket, files) in buckets {
+        report.info(format_args!("\n{}:", options.bucket_label(*bucket)));
+        let mut sorted: Vec<_> = files.iter().sorted_by(|a, b| by_time(a, b)).collect();
+        if !options.prefer_keep.is_empty() {
+            // Stable, so the preferred files and the others each stay oldest first
+            sorted.sort_by_key(|(file, _)| {
+                !matches_name(file, &options.prefer_keep, options.case_sensitive_ext)
+            });
+        }
+        let (mut keep, mut delete): (Vec<_>, Vec<_>) = match &newest {
+            Some(newest) => sorted.iter().partition(|(file, _)| newest.contains(file)),
+            None => {
+                let keep = match options.keep_percent {
+                    Some(percent) => percent_of(sorted.len(), percent),
+                    None => options.keep_in(dir, *bucket) as usize,
+                };
+                let split_idx = keep.min(sorted.len());
+                let (keep, delete) = sorted.split_at(split_idx);
+                (keep.iter().collect(), delete.iter().collect())
+            }
+        };
+        // --merge-below stores a merged segment under its oldest bucket, so look for its files
+        if options.protect_first_bucket && files.iter().any(|(file, _)| youngest.contains(file)) {
+            keep.append(&mut delete);
+        }
+        let missing = (options.bucket_keep_min as usize)
+            .saturating_sub(keep.len())
+            .min(delete.len());
+        keep.extend(delete.drain(..missing));
+        let (protected, delete): (Vec<&&(path::PathBuf, time::SystemTime)>, Vec<_>) =
+            delete.into_iter().partition(|(file, _)| {
+                matches_name(file, &options.protect, options.case_sensitive_ext)
+            });
+        if delete.is_empty() {
+            report.info(format_args!("No files to delete in this group."));
+        }
+        for (file, time) in keep {
+            list_file(report, options, file, *time, *bucket, "keep");
+            plan.files.push(PlannedFile {
+                path: file.clone(),
+                time: *time,
+                bucket: *bucket,
+                action: Action::Keep,
+                checksum: None,
+            });
+        }
+        for (file, time) in protected {
+            list_file(report, options, file, *time, *bucket, "protected");
+            plan.files.push(PlannedFile {
+                path: file.clone(),
+                time: *time,
+                bucket: *bucket,
+                action: Action::Keep,
+                checksum: None,
+            });
+        }
+        for (file, time) in delete {
+            list_file(report, options, file, *time, *bucket, "delete");
+            plan.files.push(PlannedFile {
+                path: file.clone(),
+                time: *time,
+                bucket: *bucket,
+                action: Action::Delete,
+                checksum: None,
+            });
+        }
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use filetime::{FileTime, set_file_times};
+    use gag::BufferRedirect;
+    use rand::Rng;
+    use std::io::Read;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn plan_options(sort_type: SortType, keep: u32, recursive: bool) -> PlanOptions {
+        PlanOptions {
+            sort_type,
+            keep,
+            recursive,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_get_time_type() {
+        println!("Testing get_time_type function");
+
+        let meta = fs::metadata("Cargo.toml").unwrap();
+        let mtime = get_time_type(&meta, &SortType::MTime);
+        let atime = get_time_type(&meta, &SortType::ATime);
+        let ctime = get_time_type(&meta, &SortType::CTime);
+
+        assert!(mtime > time::UNIX_EPOCH);
+        assert!(atime > time::UNIX_EPOCH);
+        assert!(ctime > time::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_sort_type_parsing() {
+        println!("Testing parsing of sort types and their aliases");
+
+        for name in ["mtime", "modified", "m", "MTime", "MODIFIED", "M"] {
+            assert!(matches!(name.parse::<SortType>(), Ok(SortType::MTime)));
+        }
+        for name in ["ctime", "created", "c", "CTIME", "Created"] {
+            assert!(matches!(name.parse::<SortType>(), Ok(SortType::CTime)));
+        }
+        for name in ["atime", "accessed", "a", "ATime", "ACCESSED"] {
+            assert!(matches!(name.parse::<SortType>(), Ok(SortType::ATime)));
+        }
+        for name in ["", "mtim", "time", "birth", "x"] {
+            let err = name.parse::<SortType>().unwrap_err();
+            assert!(err.contains("invalid sort type"));
+        }
+    }
+
+    #[test]
+    fn test_listing_simple() {
+        println!("Testing a normal directory structure");
+
+        let dir = tempdir().unwrap();
+        let mut rng = rand::rng();
+
+        for i in 0..500 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            let mut file = fs::File::create(&file_path).unwrap();
+            writeln!(file, "test {}", i).unwrap();
+
+            let now = time::SystemTime::now();
+            let offset_secs = rng.random_range(0..365 * 24 * 3600);
+            let random_time = FileTime::from_unix_time(
+                now.duration_since(time::UNIX_EPOCH).unwrap().as_secs() as i64 - offset_secs as i64,
+                0,
+            );
+
+            set_file_times(&file_path, random_time, random_time).unwrap();
+        } // Create some files with different times, max one-year-old
+
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, rng.random_range(1..5), false),
+        );
+        assert!(result.is_ok());
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::ATime, rng.random_range(1..5), false),
+        );
+        assert!(result.is_ok());
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::CTime, rng.random_range(1..5), false),
+        ); //Can't modify ctime in tests so always one bucket
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_files_to_delete_are_correct() {
+        println!("Testing that files to delete are correct");
+
+        let dir = tempdir().unwrap();
+        let file1 = dir.path().join("oldest.txt");
+        let file2 = dir.path().join("youngest.txt");
+        let file3 = dir.path().join("second_youngest.txt");
+        let file4 = dir.path().join("third_youngest.txt");
+        fs::File::create(&file1).unwrap();
+        fs::File::create(&file2).unwrap();
+        fs::File::create(&file3).unwrap();
+        fs::File::create(&file4).unwrap();
+
+        let now = time::SystemTime::now();
+        set_file_times(
+            &file1,
+            FileTime::from_system_time(now - time::Duration::from_secs(10000)),
+            FileTime::from_system_time(now - time::Duration::from_secs(10000)),
+        )
+        .unwrap();
+        set_file_times(
+            &file2,
+            FileTime::from_system_time(now),
+            FileTime::from_system_time(now),
+        )
+        .unwrap();
+        set_file_times(
+            &file3,
+            FileTime::from_system_time(now - time::Duration::from_secs(1)),
+            FileTime::from_system_time(now - time::Duration::from_secs(1)),
+        )
+        .unwrap();
+        set_file_times(
+            &file4,
+            FileTime::from_system_time(now - time::Duration::from_secs(500)),
+            FileTime::from_system_time(now - time::Duration::from_secs(500)),
+        )
+        .unwrap();
+
+        let (to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 1, false),
+        )
+        .unwrap()
+        .split();
+
+        assert!(to_keep.contains(&file1));
+        assert!(to_delete.contains(&file3));
+        assert!(to_delete.contains(&file4));
+        assert!(to_delete.contains(&file2));
+        assert_eq!(to_keep.len(), 1);
+        assert_eq!(to_delete.len(), 3);
+
+        let (to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::ATime, 1, false),
+        )
+        .unwrap()
+        .split();
+        assert!(to_keep.contains(&file1));
+        assert!(to_delete.contains(&file3));
+        assert!(to_delete.contains(&file4));
+        assert!(to_delete.contains(&file2));
+        assert_eq!(to_keep.len(), 1);
+        assert_eq!(to_delete.len(), 3);
+
+        //Ctime is tested separately since it cannot be easily modified in tests
+    }
+
+    #[test]
+    fn test_ctime() {
+        println!("Testing ctime sorting");
+
+        let dir = tempdir().unwrap();
+        let file1 = dir.path().join("file1.txt");
+        fs::File::create(&file1).unwrap();
+
+        thread::sleep(time::Duration::from_secs(2)); // Ensure a difference in ctime. That's why this test is slow.
+
+        let file2 = dir.path().join("file2.txt");
+        fs::File::create(&file2).unwrap();
+
+        thread::sleep(time::Duration::from_secs(2));
+
+        let file3 = dir.path().join("file3.txt");
+        fs::File::create(&file3).unwrap();
+
+        let (to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::CTime, 1, false),
+        )
+        .unwrap()
+        .split();
+
+        assert!(to_keep.contains(&file1));
+        assert!(to_delete.contains(&file2));
+        assert!(to_delete.contains(&file3));
+        assert_eq!(to_keep.len(), 1);
+        assert_eq!(to_delete.len(), 2);
+    }
+
+    #[test]
+    fn test_buckets_behavior() {
+        println!("Testing buckets behavior explicitly");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+
+        for i in 0..16 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let days = i * 86400;
+            set_file_times(
+                &file_path,
+                FileTime::from_system_time(now - time::Duration::from_secs(days)),
+                FileTime::from_system_time(now - time::Duration::from_secs(days)),
+            )
+            .unwrap();
+        }
+
+        let (to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 1, false),
+        )
+        .unwrap()
+        .split();
+
+        assert!(to_delete.contains(&dir.path().join("file0.txt"))); //Files asserted explicitly
+        assert!(to_keep.contains(&dir.path().join("file1.txt")));
+        assert!(to_keep.contains(&dir.path().join("file2.txt")));
+        assert!(to_delete.contains(&dir.path().join("file3.txt")));
+        assert!(to_keep.contains(&dir.path().join("file4.txt")));
+        assert!(to_delete.contains(&dir.path().join("file5.txt")));
+        assert!(to_delete.contains(&dir.path().join("file6.txt")));
+        assert!(to_delete.contains(&dir.path().join("file7.txt")));
+        assert!(to_keep.contains(&dir.path().join("file8.txt")));
+        assert!(to_delete.contains(&dir.path().join("file9.txt")));
+        assert!(to_delete.contains(&dir.path().join("file10.txt")));
+        assert!(to_delete.contains(&dir.path().join("file11.txt")));
+        assert!(to_delete.contains(&dir.path().join("file12.txt")));
+        assert!(to_delete.contains(&dir.path().join("file13.txt")));
+        assert!(to_delete.contains(&dir.path().join("file14.txt")));
+        assert!(to_keep.contains(&dir.path().join("file15.txt")));
+        assert_eq!(to_keep.len(), 5);
+        assert_eq!(to_delete.len(), 11);
+
+        let (to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::ATime, 1, false),
+        )
+        .unwrap()
+        .split();
+
+        assert!(to_delete.contains(&dir.path().join("file0.txt")));
+        assert!(to_keep.contains(&dir.path().join("file1.txt")));
+        assert!(to_keep.contains(&dir.path().join("file2.txt")));
+        assert!(to_delete.contains(&dir.path().join("file3.txt")));
+        assert!(to_keep.contains(&dir.path().join("file4.txt")));
+        assert!(to_delete.contains(&dir.path().join("file5.txt")));
+        assert!(to_delete.contains(&dir.path().join("file6.txt")));
+        assert!(to_delete.contains(&dir.path().join("file7.txt")));
+        assert!(to_keep.contains(&dir.path().join("file8.txt")));
+        assert!(to_delete.contains(&dir.path().join("file9.txt")));
+        assert!(to_delete.contains(&dir.path().join("file10.txt")));
+        assert!(to_delete.contains(&dir.path().join("file11.txt")));
+        assert!(to_delete.contains(&dir.path().join("file12.txt")));
+        assert!(to_delete.contains(&dir.path().join("file13.txt")));
+        assert!(to_delete.contains(&dir.path().join("file14.txt")));
+        assert!(to_keep.contains(&dir.path().join("file15.txt")));
+        assert_eq!(to_keep.len(), 5);
+        assert_eq!(to_delete.len(), 11);
+
+        // CTime is not tested here since it cannot be easily modified in tests
+    }
+
+    #[test]
+    fn test_keep_total() {
+        println!("Testing keeping a total number of the newest files");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+
+        for i in 0..100 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 3600));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // Files spread over a few days, so over several buckets
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            keep: 1, // Ignored when keep_total is set
+            keep_total: Some(50),
+            ..Default::default()
+        };
+        let (to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        delete_files(
+            Verbosity::Normal,
+            &to_delete,
+            &DeleteOptions::default(),
+            &atomic::AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert_eq!(to_keep.len(), 50);
+        assert_eq!(to_delete.len(), 50);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 50);
+        for i in 0..50 {
+            assert!(dir.path().join(format!("file{}.txt", i)).exists()); // The newest files survive
+        }
+    }
+
+    #[test]
+    fn test_plan_buckets() {
+        println!("Testing the time segment and action of each planned file");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for (name, hours) in [("new.txt", 1), ("old.txt", 84), ("older.txt", 252)] {
+            let file_path = dir.path().join(name);
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(hours * 3600));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+        fs::File::create(dir.path().join("oldest.txt")).unwrap();
+        let ft = FileTime::from_system_time(now - time::Duration::from_secs(300 * 3600));
+        set_file_times(dir.path().join("oldest.txt"), ft, ft).unwrap();
+
+        let plan = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 1, false),
+        )
+        .unwrap();
+        let buckets: Vec<_> = plan
+            .files
+            .iter()
+            .map(|file| {
+                (
+                    file.path.file_name().unwrap().to_str().unwrap(),
+                    file.bucket,
+                    file.action,
+                )
+            })
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![
+                ("new.txt", 1, Action::Keep),
+                ("old.txt", 4, Action::Keep),
+                ("oldest.txt", 16, Action::Keep), // The oldest file of a segment is kept
+                ("older.txt", 16, Action::Delete),
+            ]
+        );
+        assert_eq!(
+            plan.paths(Action::Delete),
+            vec![dir.path().join("older.txt")]
+        );
+    }
+
+    #[test]
+    fn test_reverse_buckets() {
+        println!("Testing that --reverse flips the order of the time segments only");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for (name, hours) in [("a.txt", 1), ("b.txt", 2), ("c.txt", 84), ("d.txt", 252)] {
+            let file_path = dir.path().join(name);
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(hours * 3600));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+
+        let plan = |reverse| {
+            exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Quiet),
+                dir.path(),
+                &PlanOptions {
+                    reverse,
+                    ..plan_options(SortType::MTime, 1, false)
+                },
+            )
+            .unwrap()
+        };
+        let forward = plan(false);
+        let reversed = plan(true);
+        let buckets = |plan: &Plan| {
+            plan.files
+                .iter()
+                .map(|file| file.bucket)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(buckets(&forward), vec![1, 1, 4, 16]);
+        assert_eq!(buckets(&reversed), vec![16, 4, 1, 1]);
+        assert_eq!(
+            forward.paths(Action::Delete),
+            reversed.paths(Action::Delete)
+        );
+    }
+
+    #[test]
+    fn test_prefer_keep() {
+        println!("Testing keeping the files matching --prefer-keep first");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for (name, minutes) in [("a.tar", 30), ("b-full.tar", 20), ("c.tar", 10)] {
+            let file_path = dir.path().join(name);
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(minutes * 60));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // All in the same time segment, a.tar is the oldest
+
+        let plan = |prefer_keep: &[&str], keep| {
+            exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Quiet),
+                dir.path(),
+                &PlanOptions {
+                    prefer_keep: prefer_keep
+                        .iter()
+                        .map(|pattern| glob::Pattern::new(pattern).unwrap())
+                        .collect(),
+                    ..plan_options(SortType::MTime, keep, false)
+                },
+            )
+            .unwrap()
+            .split()
+            .0
+        };
+        assert_eq!(plan(&[], 1), vec![dir.path().join("a.tar")]);
+        assert_eq!(plan(&["*full*"], 1), vec![dir.path().join("b-full.tar")]);
+        assert_eq!(
+            plan(&["*full*"], 2),
+            vec![dir.path().join("b-full.tar"), dir.path().join("a.tar")]
+        ); // Then by age as usual
+    }
+
+    #[test]
+    fn test_calendar_files() {
+        println!("Testing keeping the first file of each day, week and month");
+
+        let mut groups: Buckets = collections::BTreeMap::new();
+        let start = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        for day in 0..35 {
+            for hour in [8, 20] {
+                let datetime = (start + chrono::Days::new(day))
+                    .and_hms_opt(hour, 0, 0)
+                    .unwrap()
+                    .and_local_timezone(chrono::Local)
+                    .unwrap();
+                let file = path::PathBuf::from(datetime.format("%Y-%m-%d-%H.txt").to_string());
+                groups.entry(1).or_default().push((file, datetime.into()));
+            }
+        } // Two files a day from Monday 2024-01-01 to Sunday 2024-02-04
+
+        let selected = |daily, weekly, monthly| {
+            let retention = CalendarRetention {
+                daily,
+                weekly,
+                monthly,
+            };
+            calendar_files(&groups, &retention)
+                .into_iter()
+                .map(|file| file.to_string_lossy().into_owned())
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            selected(3, 0, 0),
+            [
+                "2024-02-02-08.txt",
+                "2024-02-03-08.txt",
+                "2024-02-04-08.txt"
+            ]
+        );
+        assert_eq!(
+            selected(0, 2, 0),
+            ["2024-01-22-08.txt", "2024-01-29-08.txt"]
+        );
+        assert_eq!(
+            selected(0, 0, 5),
+            ["2024-01-01-08.txt", "2024-02-01-08.txt"]
+        ); // Only two months have files
+        assert_eq!(selected(1, 1, 1).len(), 3);
+        assert!(selected(0, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn test_calendar_mode() {
+        println!("Testing calendar mode keeps one file per day");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..84 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 6 * 3600));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // Four files a day over three weeks
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            calendar: Some(CalendarRetention {
+                daily: 5,
+                weekly: 0,
+                monthly: 0,
+            }),
+            ..Default::default()
+        };
+        let (to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        assert_eq!(to_keep.len(), 5);
+        assert_eq!(to_delete.len(), 79);
+        let days: collections::HashSet<_> = to_keep
+            .iter()
+            .map(|file| {
+                let modified: chrono::DateTime<chrono::Local> =
+                    fs::metadata(file).unwrap().modified().unwrap().into();
+                modified.date_naive()
+            })
+            .collect();
+        assert_eq!(days.len(), 5); // One survivor per day
+    }
+
+    #[test]
+    fn test_parse_size() {
+        println!("Testing parsing of sizes with units");
+
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("500K"), Ok(500 * 1024));
+        assert_eq!(parse_size("10GiB"), Ok(10 << 30));
+        assert_eq!(parse_size("2mb"), Ok(2_000_000));
+        assert_eq!(parse_size("7 B"), Ok(7));
+        assert!(parse_size("10XB").is_err());
+        assert!(parse_size("GiB").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn test_grace_period() {
+        println!("Testing keeping files first seen within the grace period");
+
+        assert_eq!(parse_duration("90").unwrap(), time::Duration::from_secs(90));
+        assert_eq!(
+            parse_duration("12h").unwrap(),
+            time::Duration::from_secs(12 * 3600)
+        );
+        assert_eq!(
+            parse_duration("7D").unwrap(),
+            time::Duration::from_secs(7 * 86400)
+        );
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("d").is_err());
+
+        let dir = tempdir().unwrap();
+        for i in 0..3 {
+            fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+        }
+        let first_seen_file = dir.path().join("state").join("first_seen.json");
+        let grace = time::Duration::from_secs(7 * 86400);
+        let plan = || {
+            exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Normal),
+                dir.path(),
+                &PlanOptions::default(), // Keeps nothing
+            )
+            .unwrap()
+        };
+
+        let now = time::SystemTime::now();
+        let mut first_seen = load_first_seen(&first_seen_file);
+        assert!(first_seen.is_empty());
+        let mut first_plan = plan();
+        let recent = first_plan.keep_recently_seen(&mut first_seen, now, grace);
+        assert_eq!(recent.len(), 3); // Just appeared
+        assert!(first_plan.paths(Action::Delete).is_empty());
+        save_first_seen(&first_seen_file, &first_seen).unwrap();
+
+        let mut first_seen = load_first_seen(&first_seen_file);
+        assert_eq!(first_seen.len(), 3);
+        let mut later_plan = plan();
+        let later = now + grace + time::Duration::from_secs(1);
+        assert!(
+            later_plan
+                .keep_recently_seen(&mut first_seen, later, grace)
+                .is_empty()
+        ); // Seen long enough ago
+        assert_eq!(later_plan.paths(Action::Delete).len(), 3);
+    }
+
+    #[test]
+    fn test_target_size() {
+        println!("Testing deleting the oldest files down to a target size");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..10 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::write(&file_path, vec![0u8; 100]).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 86400));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // 1000 bytes over several time segments, file9 is the oldest
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            target_size: Some(450),
+            ..Default::default()
+        };
+        let (to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        assert_eq!(to_delete.len(), 6);
+        assert!(total_size(&to_keep, false) <= 450);
+        for i in 0..4 {
+            assert!(to_keep.contains(&dir.path().join(format!("file{}.txt", i)))); // The newest files survive
+        }
+
+        let options = PlanOptions {
+            target_size: Some(5000),
+            ..options
+        };
+        let (_to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        assert!(to_delete.is_empty()); // Already under the target
+    }
+
+    #[test]
+    fn test_keep_percent() {
+        println!("Testing keeping a percentage of the files per time segment");
+
+        assert_eq!(percent_of(8, 25), 2);
+        assert_eq!(percent_of(3, 50), 2); // Rounded up
+        assert_eq!(percent_of(1, 1), 1); // At least one file
+        assert_eq!(percent_of(10, 0), 0);
+        assert_eq!(percent_of(7, 100), 7);
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..16 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 86400 + 3600));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // 1, 1, 2, 4 and 8 files in the segments of 1, 2, 4, 8 and 16 days
+
+        let kept_per_bucket = |percent| {
+            let options = PlanOptions {
+                sort_type: SortType::MTime,
+                keep_percent: Some(percent),
+                ..Default::default()
+            };
+            let plan = exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Normal),
+                dir.path(),
+                &options,
+            )
+            .unwrap();
+            plan.files
+                .iter()
+                .filter(|file| file.action == Action::Keep)
+                .counts_by(|file| file.bucket)
+        };
+        let kept = kept_per_bucket(25);
+        assert_eq!(
+            [1, 2, 4, 8, 16].map(|bucket| kept[&bucket]),
+            [1, 1, 1, 1, 2]
+        );
+        let kept = kept_per_bucket(50);
+        assert_eq!(
+            [1, 2, 4, 8, 16].map(|bucket| kept[&bucket]),
+            [1, 1, 1, 2, 4]
+        );
+        assert!(kept_per_bucket(0).is_empty());
+    }
+
+    #[test]
+    fn test_dedupe_scope() {
+        println!("Testing deleting the older copies of identical files");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        let mut copies = Vec::new();
+        for (sub, days) in [("a", [0, 3, 9]), ("b", [0, 20, 40])] {
+            let sub_dir = dir.path().join(sub);
+            fs::create_dir(&sub_dir).unwrap();
+            for (i, day) in days.into_iter().enumerate() {
+                let file_path = sub_dir.join(format!("copy{}.txt", i));
+                fs::write(&file_path, b"same").unwrap();
+                let ft = FileTime::from_system_time(
+                    now - time::Duration::from_secs(day * 86400 + 3600 + i as u64),
+                );
+                set_file_times(&file_path, ft, ft).unwrap();
+                copies.push(file_path);
+            }
+            fs::write(sub_dir.join("other.txt"), format!("oth{}", sub)).unwrap(); // Same size, other contents
+        }
+
+        let kept_copies = |dedupe_scope| {
+            let plan = exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Quiet),
+                dir.path(),
+                &PlanOptions {
+                    dedupe_scope,
+                    ..plan_options(SortType::MTime, 10, true)
+                },
+            )
+            .unwrap();
+            assert_eq!(plan.files.len(), 8); // Copies are still part of the plan
+            let kept = plan.paths(Action::Keep);
+            assert!(kept.contains(&dir.path().join("a").join("other.txt")));
+            assert!(kept.contains(&dir.path().join("b").join("other.txt")));
+            copies.iter().filter(|file| kept.contains(file)).count()
+        };
+        assert_eq!(kept_copies(None), 6);
+        // Each copy of a directory is in its own time segment
+        assert_eq!(kept_copies(Some(DedupeScope::Bucket)), 6);
+        assert_eq!(kept_copies(Some(DedupeScope::Directory)), 2);
+        assert_eq!(kept_copies(Some(DedupeScope::Global)), 1);
+
+        let plan = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Quiet),
+            dir.path(),
+            &PlanOptions {
+                dedupe_scope: Some(DedupeScope::Global),
+                ..plan_options(SortType::MTime, 10, true)
+            },
+        )
+        .unwrap();
+        let kept = plan.paths(Action::Keep);
+        assert!(kept.contains(&copies[0])); // The newest copy
+    }
+
+    #[test]
+    fn test_protect_first_bucket() {
+        println!("Testing keeping every file of the youngest time segment");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..8 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let age = if i < 5 { 60 * i } else { 2 * 86400 + 3600 * i };
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(age));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // 5 files younger than a day, 3 in an older time segment
+
+        let kept = |protect_first_bucket| {
+            let plan = exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Quiet),
+                dir.path(),
+                &PlanOptions {
+                    protect_first_bucket,
+                    ..plan_options(SortType::MTime, 1, false)
+                },
+            )
+            .unwrap();
+            plan.paths(Action::Keep).len()
+        };
+        assert_eq!(kept(false), 2);
+        assert_eq!(kept(true), 6); // The older time segment still keeps only one
+
+        // The youngest segment merged with the next ones is still protected
+        let plan = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Quiet),
+            dir.path(),
+            &PlanOptions {
+                protect_first_bucket: true,
+                merge_below: 7,
+                ..plan_options(SortType::MTime, 1, false)
+            },
+        )
+        .unwrap();
+        assert_eq!(plan.paths(Action::Keep).len(), 8);
+
+        // Without files younger than a day, the youngest segment is thinned like the others
+        for i in 0..5 {
+            fs::remove_file(dir.path().join(format!("file{}.txt", i))).unwrap();
+        }
+        assert_eq!(kept(true), 1);
+        let plan = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Quiet),
+            dir.path(),
+            &PlanOptions {
+                protect_first_bucket: true,
+                merge_below: 7,
+                ..plan_options(SortType::MTime, 1, false)
+            },
+        )
+        .unwrap();
+        assert_eq!(plan.paths(Action::Keep).len(), 1);
+    }
+
+    #[test]
+    fn test_bucket_keep_min() {
+        println!("Testing the minimum number of files kept per time segment");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..16 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 86400 + 3600));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // 2, 1, 2, 4 and 7 files in the segments of 1, 2, 4, 8 and 16 days
+
+        let kept_per_bucket = |options: PlanOptions| {
+            let plan = exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Normal),
+                dir.path(),
+                &PlanOptions {
+                    sort_type: SortType::MTime,
+                    ..options
+                },
+            )
+            .unwrap();
+            let kept = plan
+                .files
+                .iter()
+                .filter(|file| file.action == Action::Keep)
+                .counts_by(|file| file.bucket);
+            [1, 2, 4, 8, 16].map(|bucket| kept.get(&bucket).copied().unwrap_or(0))
+        };
+        assert_eq!(
+            kept_per_bucket(PlanOptions {
+                keep_total: Some(1),
+                ..Default::default()
+            }),
+            [1, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            kept_per_bucket(PlanOptions {
+                keep_total: Some(1),
+                bucket_keep_min: 1,
+                ..Default::default()
+            }),
+            [1, 1, 1, 1, 1]
+        );
+        assert_eq!(
+            kept_per_bucket(PlanOptions {
+                keep_percent: Some(10),
+                bucket_keep_min: 2,
+                ..Default::default()
+            }),
+            [2, 1, 2, 2, 2]
+        );
+    }
+
+    #[test]
+    fn test_group_by_extension() {
+        println!("Testing grouping files by extension");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+
+        for extension in ["bak", "log", "tmp"] {
+            for i in 0..3 {
+                let file_path = dir.path().join(format!("file{}.{}", i, extension));
+                fs::File::create(&file_path).unwrap();
+                let ft = FileTime::from_system_time(now - time::Duration::from_secs(100 - i));
+                set_file_times(&file_path, ft, ft).unwrap();
+            }
+        } // All files land in the same bucket, file0 is the oldest of each type
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            keep: 1,
+            group_by_extension: true,
+            ..Default::default()
+        };
+        let (to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+
+        assert_eq!(to_keep.len(), 3);
+        assert_eq!(to_delete.len(), 6);
+        assert!(to_keep.contains(&dir.path().join("file0.bak")));
+        assert!(to_keep.contains(&dir.path().join("file0.log")));
+        assert!(to_keep.contains(&dir.path().join("file0.tmp")));
+
+        let (to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 1, false),
+        )
+        .unwrap()
+        .split(); // Without grouping only one file is kept
+        assert_eq!(to_keep.len(), 1);
+        assert_eq!(to_delete.len(), 8);
+    }
+
+    #[test]
+    fn test_extension_case() {
+        println!("Testing case-insensitive extensions");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for (i, name) in ["a.jpg", "b.JPG", "c.Jpg", "d.png", "e.PNG"]
+            .iter()
+            .enumerate()
+        {
+            let file_path = dir.path().join(name);
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(100 - i as u64));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // All files land in the same bucket, the first of each type is the oldest
+
+        let plan = |case_sensitive_ext| {
+            exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Quiet),
+                dir.path(),
+                &PlanOptions {
+                    sort_type: SortType::MTime,
+                    keep: 1,
+                    group_by_extension: true,
+                    case_sensitive_ext,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .split()
+        };
+        let (to_keep, to_delete) = plan(false);
+        assert_eq!(
+            to_keep,
+            vec![dir.path().join("a.jpg"), dir.path().join("d.png")]
+        );
+        assert_eq!(to_delete.len(), 3);
+        let (to_keep, _) = plan(true); // Every spelling is its own group
+        assert_eq!(to_keep.len(), 5);
+
+        let extensions = vec![normalize_extension("JPG", false)];
+        assert!(has_extension(path::Path::new("b.JPG"), &extensions, false));
+        assert!(has_extension(path::Path::new("a.jpg"), &extensions, false));
+        assert!(!has_extension(path::Path::new("d.png"), &extensions, false));
+        assert!(!has_extension(
+            path::Path::new("b.JPG"),
+            &["jpg".to_string()],
+            true
+        ));
+
+        let patterns = vec![glob::Pattern::new(&normalize_name("*.JPG", false)).unwrap()];
+        assert!(matches_name(path::Path::new("Photo.jpg"), &patterns, false));
+        assert!(matches_name(path::Path::new("Photo.Jpg"), &patterns, false));
+        assert!(!matches_name(
+            path::Path::new("Photo.png"),
+            &patterns,
+            false
+        ));
+        assert_eq!(normalize_name("important_*", false), "important_*");
+    }
+
+    #[test]
+    fn test_age_unit_hours() {
+        println!("Testing time segments in hours");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+
+        for (name, hours) in [("a.txt", 0), ("b.txt", 1), ("c.txt", 3), ("d.txt", 5)] {
+            let file_path = dir.path().join(name);
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(hours * 3600 + 60));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            age_unit: AgeUnit::Hours,
+            ..Default::default()
+        };
+        let groups = group_files_by_bucket(dir.path(), &options).unwrap().0;
+        assert_eq!(groups.keys().copied().collect::<Vec<_>>(), vec![1, 4, 8]);
+        assert_eq!(groups[&1].len(), 2);
+        assert_eq!(groups[&4][0].0, dir.path().join("c.txt"));
+        assert_eq!(groups[&8][0].0, dir.path().join("d.txt"));
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            ..Default::default()
+        };
+        let groups = group_files_by_bucket(dir.path(), &options).unwrap().0;
+        assert_eq!(groups.keys().copied().collect::<Vec<_>>(), vec![1]); // Same day
+
+        assert_eq!(
+            bucket_label(8, AgeUnit::Hours),
+            "Younger than 8 hours but older than 4 hours"
+        );
+        assert_eq!(
+            bucket_label(2, AgeUnit::Weeks),
+            "Younger than 2 weeks but older than 1 weeks"
+        );
+    }
+
+    #[test]
+    fn test_explain_buckets() {
+        println!("Testing the explanation of the time segments");
+
+        let options = PlanOptions::default();
+        let explained: Vec<_> = [1, 2, 4, 8]
+            .map(|bucket| options.explain_bucket(bucket))
+            .to_vec();
+        assert_eq!(
+            explained,
+            vec![
+                "Bucket 1: files aged 0-1 days, spanning 2 days",
+                "Bucket 2: files aged 2 days",
+                "Bucket 4: files aged 3-4 days, spanning 2 days",
+                "Bucket 8: files aged 5-8 days, spanning 4 days",
+            ]
+        );
+        for age in 0..100 {
+            let (youngest, oldest) = options.bucket_ages(bucket_for_age(age));
+            assert!(youngest <= age && age <= oldest.unwrap());
+        }
+
+        let options = PlanOptions {
+            boundaries: Some(BucketBoundaries(vec![1, 7, 30])),
+            age_unit: AgeUnit::Weeks,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.explain_bucket(7),
+            "Bucket 7: files aged 1-6 weeks, spanning 6 weeks"
+        );
+        assert_eq!(options.explain_bucket(1), "Bucket 1: files aged 0 weeks");
+        assert_eq!(
+            options.explain_bucket(OLDEST_BUCKET),
+            "Oldest bucket: files aged 30 weeks or more"
+        );
+        for age in 0..100 {
+            let (youngest, oldest) = options.bucket_ages(options.bucket_for(age));
+            assert!(youngest <= age && oldest.is_none_or(|oldest| age <= oldest));
+        }
+    }
+
+    #[test]
+    fn test_template() {
+        println!("Testing rendering files with a template");
+
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        fs::write(&file, "12345").unwrap();
+
+        let template = parse_template("{path}\\t{time}\\t{bucket}\\t{action} {{{size}}}").unwrap();
+        assert_eq!(
+            template.render(&file, &file, "2024-01-15 10:00:00", 8, "delete", false),
+            format!("{}\t2024-01-15 10:00:00\t8\tdelete {{5}}", file.display())
+        );
+        assert_eq!(
+            parse_template("{bucket}").unwrap().render(
+                &file,
+                &file,
+                "",
+                OLDEST_BUCKET,
+                "keep",
+                false
+            ),
+            "oldest"
+        );
+        assert_eq!(parse_template("").unwrap(), Template(Vec::new()));
+        assert!(parse_template("{name}").is_err());
+        assert!(parse_template("{path").is_err());
+        assert!(parse_template("path}").is_err());
+    }
+
+    #[test]
+    fn test_nanosecond_ties() {
+        println!("Testing files with times a nanosecond apart or equal");
+
+        let dir = tempdir().unwrap();
+        let time = time::SystemTime::now() - time::Duration::from_secs(3600);
+        let older = dir.path().join("b.txt");
+        let newer = dir.path().join("a.txt");
+        for (file, time) in [
+            (&older, time),
+            (&newer, time + time::Duration::from_nanos(1)),
+        ] {
+            fs::File::create(file).unwrap();
+            let ft = FileTime::from_system_time(time);
+            set_file_times(file, ft, ft).unwrap();
+        }
+        let options = plan_options(SortType::MTime, 1, false);
+        let plan = || {
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Quiet), dir.path(), &options)
+                .unwrap()
+                .split()
+        };
+
+        let modified = |file| get_time_type(&fs::metadata(file).unwrap(), &SortType::MTime);
+        if modified(&newer) != modified(&older) {
+            // The filesystem stores nanoseconds
+            assert_eq!(
+                modified(&newer).duration_since(modified(&older)).unwrap(),
+                time::Duration::from_nanos(1)
+            ); // Not rounded on the way
+            assert_eq!(plan(), (vec![older.clone()], vec![newer.clone()])); // The oldest is kept
+        }
+
+        let ft = FileTime::from_system_time(time);
+        set_file_times(&newer, ft, ft).unwrap();
+        for _ in 0..5 {
+            assert_eq!(plan(), (vec![newer.clone()], vec![older.clone()])); // Tied, a.txt comes first
+        }
+    }
+
+    #[test]
+    fn test_plan_reproducible() {
+        println!("Testing that the plan does not depend on the directory order");
+
+        let time =
+            FileTime::from_system_time(time::SystemTime::now() - time::Duration::from_secs(3600));
+        let names: Vec<_> = (0..20).map(|i| format!("file{:02}.txt", i)).collect();
+        let plan = |reversed: bool| {
+            let dir = tempdir().unwrap();
+            let mut order: Vec<_> = names.iter().collect();
+            if reversed {
+                order.reverse(); // Created in the other order, so usually listed in the other order
+            }
+            for name in order {
+                let file = dir.path().join(name);
+                fs::File::create(&file).unwrap();
+                set_file_times(&file, time, time).unwrap();
+                fs::create_dir(dir.path().join(format!("{}.d", name))).unwrap();
+            }
+            let plan = exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Quiet),
+                dir.path(),
+                &plan_options(SortType::MTime, 3, false),
+            )
+            .unwrap();
+            let name = |file: &path::Path| file.strip_prefix(dir.path()).unwrap().to_path_buf();
+            let files: Vec<_> = plan
+                .files
+                .iter()
+                .map(|file| (name(&file.path), file.action))
+                .collect();
+            let skipped: Vec<_> = plan.skipped.iter().map(|entry| name(&entry.path)).collect();
+            (files, skipped)
+        };
+        let (files, skipped) = plan(false);
+        assert_eq!(
+            files
+                .iter()
+                .filter(|(_, action)| *action == Action::Keep)
+                .map(|(file, _)| file.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                path::PathBuf::from("file00.txt"),
+                path::PathBuf::from("file01.txt"),
+                path::PathBuf::from("file02.txt")
+            ]
+        );
+        assert!(skipped.is_sorted());
+        assert_eq!(plan(true), (files, skipped));
+    }
+
+    #[test]
+    fn test_bucket_boundaries() {
+        println!("Testing custom time segment boundaries");
+
+        let boundaries = parse_bucket_boundaries("1, 7,30,90,365").unwrap();
+        assert_eq!(boundaries, BucketBoundaries(vec![1, 7, 30, 90, 365]));
+        assert!(parse_bucket_boundaries("7,1").is_err());
+        assert!(parse_bucket_boundaries("0,7").is_err());
+        assert!(parse_bucket_boundaries("1,,7").is_err());
+
+        assert_eq!(boundaries.bucket_for(0), 1);
+        assert_eq!(boundaries.bucket_for(6), 7);
+        assert_eq!(boundaries.bucket_for(7), 30);
+        assert_eq!(boundaries.bucket_for(365), OLDEST_BUCKET);
+        assert_eq!(
+            boundaries.label(30, AgeUnit::Days),
+            "Younger than 30 days but older than 7 days"
+        );
+        assert_eq!(
+            boundaries.label(OLDEST_BUCKET, AgeUnit::Days),
+            "Older than 365 days"
+        );
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for (i, hours) in [12, 72, 120, 480, 2400, 9600, 12000].iter().enumerate() {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(hours * 3600));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            boundaries: Some(boundaries),
+            keep: 1,
+            ..Default::default()
+        };
+        let plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap();
+        let buckets: Vec<_> = plan
+            .files
+            .iter()
+            .map(|file| (file.bucket, file.action))
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (1, Action::Keep),
+                (7, Action::Keep),
+                (7, Action::Delete),
+                (30, Action::Keep),
+                (365, Action::Keep),
+                (OLDEST_BUCKET, Action::Keep),
+                (OLDEST_BUCKET, Action::Delete),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_age_relative_to_newest() {
+        println!("Testing file ages relative to the newest file");
+
+        let dir = tempdir().unwrap();
+        let year_ago = time::SystemTime::now() - time::Duration::from_secs(365 * 86400);
+        for days in 0..10 {
+            let file_path = dir.path().join(format!("file{}.txt", days));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(year_ago - time::Duration::from_secs(days * 86400));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+
+        let buckets = |age_reference| {
+            let options = PlanOptions {
+                sort_type: SortType::MTime,
+                age_reference,
+                ..Default::default()
+            };
+            let (groups, _) = group_files_by_bucket(dir.path(), &options).unwrap();
+            groups
+                .iter()
+                .map(|(bucket, files)| (*bucket, files.len()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(buckets(AgeReference::Now), vec![(512, 10)]);
+        assert_eq!(
+            buckets(AgeReference::Newest),
+            vec![(1, 2), (2, 1), (4, 2), (8, 4), (16, 1)]
+        );
+    }
+
+    #[test]
+    fn test_merge_small_buckets() {
+        println!("Testing merging small time segments");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..10 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 86400));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // 2 files in bucket 1, 1 in 2, 2 in 4, 4 in 8, 1 in 16
+
+        let options = |merge_below| PlanOptions {
+            sort_type: SortType::MTime,
+            keep: 1,
+            merge_below,
+            ..Default::default()
+        };
+        let (groups, _) = group_files_by_bucket(dir.path(), &options(0)).unwrap();
+        let sizes = |groups: &Buckets| {
+            groups
+                .iter()
+                .map(|(bucket, files)| (*bucket, files.len()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sizes(&merge_small_buckets(groups.clone(), 1)),
+            sizes(&groups)
+        );
+        assert_eq!(
+            sizes(&merge_small_buckets(groups.clone(), 3)),
+            vec![(2, 3), (16, 7)] // The lone file of bucket 16 joins the files of 4 and 8
+        );
+        assert_eq!(sizes(&merge_small_buckets(groups, 20)), vec![(16, 10)]);
+
+        let kept = |merge_below| {
+            exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Normal),
+                dir.path(),
+                &options(merge_below),
+            )
+            .unwrap()
+            .files
+            .iter()
+            .filter(|file| file.action == Action::Keep)
+            .count()
+        };
+        assert_eq!(kept(0), 5);
+        assert_eq!(kept(3), 2);
+    }
+
+    #[test]
+    fn test_bucket_for_age() {
+        println!("Testing the time segment of a given age");
+
+        let expected = [1, 1, 2, 4, 4, 8, 8, 8, 8, 16, 16];
+        for (age, bucket) in expected.iter().enumerate() {
+            assert_eq!(bucket_for_age(age as u64), *bucket);
+        }
+        assert_eq!(bucket_for_age(1000), 1024);
+    }
+
+    #[test]
+    fn test_protect_pattern() {
+        println!("Testing that protected files are never deleted");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+
+        for name in ["a.txt", "b.txt", "important_c.txt", "d.keep", "e.txt"] {
+            let file_path = dir.path().join(name);
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(100));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            keep: 0, // Everything in the bucket would be deleted
+            protect: vec![
+                glob::Pattern::new("*.keep").unwrap(),
+                glob::Pattern::new("important_*").unwrap(),
+            ],
+            ..Default::default()
+        };
+        let (to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+
+        assert_eq!(to_keep.len(), 2);
+        assert!(to_keep.contains(&dir.path().join("important_c.txt")));
+        assert!(to_keep.contains(&dir.path().join("d.keep")));
+        assert_eq!(to_delete.len(), 3);
+    }
+
+    #[test]
+    fn test_date_from_name() {
+        println!("Testing taking file dates from their names");
+
+        let dir = tempdir().unwrap();
+        let today = chrono::Local::now().date_naive();
+        let ft = FileTime::from_system_time(time::SystemTime::now());
+
+        let mut names = Vec::new();
+        for days in [0, 3, 5, 20] {
+            let date = today - chrono::Days::new(days);
+            names.push(format!("backup-{}.tar.gz", date.format("%Y-%m-%d")));
+        }
+        names.push("backup-latest.tar.gz".to_string()); // No date, falls back to mtime
+        for name in &names {
+            let file_path = dir.path().join(name);
+            fs::File::create(&file_path).unwrap();
+            set_file_times(&file_path, ft, ft).unwrap(); // Identical times, as if copied together
+        }
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            date_from_name: Some(regex::Regex::new(r"backup-(\d{4}-\d{2}-\d{2})").unwrap()),
+            ..Default::default()
+        };
+        let groups = group_files_by_bucket(dir.path(), &options).unwrap().0;
+        let bucket_of = |name: &str| {
+            *groups
+                .iter()
+                .find(|(_, files)| files.iter().any(|(file, _)| file.ends_with(name)))
+                .unwrap()
+                .0
+        };
+
+        assert_eq!(bucket_of(&names[0]), 1);
+        assert_eq!(bucket_of(&names[1]), 4);
+        assert_eq!(bucket_of(&names[2]), 8);
+        assert_eq!(bucket_of(&names[3]), 32);
+        assert_eq!(bucket_of(&names[4]), 1);
+
+        let pattern = regex::Regex::new(r"\d{8}").unwrap();
+        assert!(date_from_name(path::Path::new("log_20240115.txt"), &pattern).is_some());
+        assert!(date_from_name(path::Path::new("log_99999999.txt"), &pattern).is_none());
+    }
+
+    #[test]
+    fn test_identical_times() {
+        println!("Testing with files having identical modification times");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        let ft = FileTime::from_system_time(now);
+
+        let file1 = dir.path().join("file1.txt");
+        let file2 = dir.path().join("file2.txt");
+        let file3 = dir.path().join("file3.txt");
+        let file4 = dir.path().join("file4.txt");
+        fs::File::create(&file1).unwrap();
+        fs::File::create(&file2).unwrap();
+        fs::File::create(&file3).unwrap();
+        fs::File::create(&file4).unwrap();
+        set_file_times(&file1, ft, ft).unwrap();
+        set_file_times(&file2, ft, ft).unwrap();
+        set_file_times(&file3, ft, ft).unwrap();
+        set_file_times(&file4, ft, ft).unwrap();
+
+        let (to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 2, false),
+        )
+        .unwrap()
+        .split(); // Ties are resolved by path
+
+        assert_eq!(to_keep, vec![file1, file2]);
+        assert_eq!(to_delete, vec![file3, file4]);
+    }
+
+    #[test]
+    fn test_zero_files_to_keep() {
+        println!("Testing with zero files to keep");
+
+        let dir = tempdir().unwrap();
+        let mut rng = rand::rng();
+
+        for i in 0..5 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            let mut file = fs::File::create(&file_path).unwrap();
             writeln!(file, "test {}", i).unwrap();
 
-            let now = time::SystemTime::now();
-            let offset_secs = rng.random_range(0..365 * 24 * 3600);
-            let random_time = FileTime::from_unix_time(
-                now.duration_since(time::UNIX_EPOCH).unwrap().as_secs() as i64 - offset_secs as i64,
-                0,
-            );
+            let now = time::SystemTime::now();
+            let offset_secs = rng.random_range(0..30 * 24 * 3600);
+            let random_time = FileTime::from_unix_time(
+                now.duration_since(time::UNIX_EPOCH).unwrap().as_secs() as i64 - offset_secs as i64,
+                0,
+            );
+
+            set_file_times(&file_path, random_time, random_time).unwrap();
+        }
+
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 0, false),
+        );
+        assert!(result.is_ok());
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::ATime, 0, false),
+        );
+        assert!(result.is_ok());
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::CTime, 0, false),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_empty_directory() {
+        println!("Testing with an empty directory");
 
-            set_file_times(&file_path, random_time, random_time).unwrap();
-        } // Create some files with different times, max one-year-old
+        let dir = tempdir().unwrap();
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 2, false),
+        );
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_invalid_path() {
+        println!("Testing with an invalid path");
+
+        let invalid_path = path::Path::new("/invalid/path");
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            invalid_path,
+            &plan_options(SortType::MTime, 2, false),
+        );
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_file_instead_of_directory() {
+        println!("Testing with a file instead of a directory");
+
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test_file.txt");
+        fs::File::create(&file_path).unwrap();
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            &file_path,
+            &plan_options(SortType::MTime, 2, false),
+        );
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotADirectory);
+    }
+
+    #[test]
+    fn test_same_times() {
+        println!("Testing with files having the same modification time");
+
+        //For now the program deletes randomly
+        //Maybe different implementation in the future
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        let ft = FileTime::from_system_time(now);
+        for i in 0..3 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
 
         let result = exp_sort_and_list_to_del(
-            false,
+            &mut Report::stdout(Verbosity::Normal),
             dir.path(),
-            &SortType::MTime,
-            rng.random_range(1..5),
-            false,
+            &plan_options(SortType::MTime, 1, false),
         );
         assert!(result.is_ok());
         let result = exp_sort_and_list_to_del(
-            false,
+            &mut Report::stdout(Verbosity::Normal),
             dir.path(),
-            &SortType::ATime,
-            rng.random_range(1..5),
-            false,
+            &plan_options(SortType::ATime, 1, false),
         );
         assert!(result.is_ok());
         let result = exp_sort_and_list_to_del(
-            false,
+            &mut Report::stdout(Verbosity::Normal),
             dir.path(),
-            &SortType::CTime,
-            rng.random_range(1..5),
-            false,
-        ); //Can't modify ctime in tests so always one bucket
+            &plan_options(SortType::CTime, 1, false),
+        );
         assert!(result.is_ok());
     }
 
     #[test]
-    fn test_files_to_delete_are_correct() {
-        println!("Testing that files to delete are correct");
+    fn test_compare_plans() {
+        println!("Testing comparing a previous plan with the current one");
+
+        let previous = SavedPlan {
+            keep: vec!["a".into(), "b".into()],
+            delete: vec!["c".into(), "d".into(), "e".into()],
+            ..Default::default()
+        };
+        let current = SavedPlan {
+            keep: vec!["c".into()],
+            delete: vec!["a".into(), "d".into(), "f".into()],
+            ..Default::default()
+        }; // "e" is gone entirely, so it is neither newly deleted nor kept
+
+        let diff = compare_plans(&previous, &current);
+        assert_eq!(
+            diff.newly_deleted,
+            vec![path::PathBuf::from("a"), path::PathBuf::from("f")]
+        );
+        assert_eq!(diff.no_longer_deleted, vec![path::PathBuf::from("c")]);
+
+        let diff = compare_plans(&current, &current);
+        assert!(diff.newly_deleted.is_empty());
+        assert!(diff.no_longer_deleted.is_empty());
+    }
+
+    #[test]
+    fn test_load_plan() {
+        println!("Testing loading a plan saved as JSON");
 
         let dir = tempdir().unwrap();
-        let file1 = dir.path().join("oldest.txt");
-        let file2 = dir.path().join("youngest.txt");
-        let file3 = dir.path().join("second_youngest.txt");
-        let file4 = dir.path().join("third_youngest.txt");
-        fs::File::create(&file1).unwrap();
-        fs::File::create(&file2).unwrap();
-        fs::File::create(&file3).unwrap();
-        fs::File::create(&file4).unwrap();
+        let plan_path = dir.path().join("plan.json");
+        fs::write(&plan_path, r#"{"keep": ["/a"], "delete": ["/b", "/c"]}"#).unwrap();
+        let plan = load_plan(&plan_path).unwrap();
+        assert_eq!(plan.keep, vec![path::PathBuf::from("/a")]);
+        assert_eq!(plan.delete.len(), 2);
+
+        fs::write(&plan_path, "not a plan").unwrap();
+        let err = load_plan(&plan_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_record_checksums() {
+        println!("Testing recording checksums of the files to be deleted");
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("file1.txt"), b"abc").unwrap();
+        fs::write(dir.path().join("file2.txt"), b"abc").unwrap();
+        let ft =
+            FileTime::from_system_time(time::SystemTime::now() - time::Duration::from_secs(60));
+        set_file_times(dir.path().join("file1.txt"), ft, ft).unwrap(); // file1 is kept
+
+        let mut plan = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 1, false),
+        )
+        .unwrap();
+        record_checksums(&mut plan, ChecksumAlgorithm::Sha256);
+        let checksums: Vec<_> = plan
+            .files
+            .iter()
+            .map(|file| (file.action, file.checksum.as_deref()))
+            .collect();
+        assert_eq!(
+            checksums,
+            vec![
+                (Action::Keep, None), // Kept files are not hashed
+                (
+                    Action::Delete,
+                    Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+                ),
+            ]
+        );
+        assert!(
+            ChecksumAlgorithm::Sha512
+                .digest_file(&dir.path().join("file2.txt"))
+                .unwrap()
+                .starts_with("ddaf35a193617aba")
+        );
+        assert!(
+            ChecksumAlgorithm::Sha256
+                .digest_file(&dir.path().join("missing.txt"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_post_hook() {
+        println!("Testing running the post-delete hook");
+
+        let dir = tempdir().unwrap();
+        let sentinel = dir.path().join("sentinel.txt");
+        let hook = format!(
+            "echo \"$EXPDEL_DELETED_COUNT $EXPDEL_FREED_BYTES $EXPDEL_FAILED_COUNT\" > '{}'",
+            sentinel.display()
+        );
+        let status = run_post_hook(&hook, 3, 1024, 1).unwrap();
+        assert!(status.success());
+        assert_eq!(fs::read_to_string(&sentinel).unwrap(), "3 1024 1\n");
+
+        let status = run_post_hook("exit 3", 0, 0, 0).unwrap();
+        assert_eq!(status.code(), Some(3)); // Reported as a failed hook
+    }
+
+    #[test]
+    fn test_acquire_lock() {
+        println!("Testing the lock fails while another holder has it");
+
+        let dir = tempdir().unwrap();
+        let lock_path = dir.path().join(LOCK_FILE_NAME);
+        let lock = acquire_lock(&lock_path).unwrap();
+        let err = acquire_lock(&lock_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        drop(lock);
+        assert!(acquire_lock(&lock_path).is_ok()); // Released when the holder is gone
+
+        fs::File::create(dir.path().join("file.txt")).unwrap();
+        let plan = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 0, false),
+        )
+        .unwrap();
+        assert_eq!(plan.files.len(), 1); // The lock file is never planned for deletion
+        assert_eq!(plan.skipped[0].reason, SkipReason::LockFile);
+    }
+
+    #[test]
+    fn test_read_paths_file() {
+        println!("Testing reading directories from a paths file");
+
+        let dir = tempdir().unwrap();
+        let paths_file = dir.path().join("paths.txt");
+        fs::write(
+            &paths_file,
+            "# Backups\n/srv/backups\n\n  /var/log/app  \n#/tmp\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_paths_file(&paths_file).unwrap(),
+            vec![
+                path::PathBuf::from("/srv/backups"),
+                path::PathBuf::from("/var/log/app")
+            ]
+        );
+        assert!(read_paths_file(&dir.path().join("missing.txt")).is_err());
+    }
+
+    #[test]
+    fn test_policy_file() {
+        println!("Testing a retention policy loaded from a file");
+
+        let dir = tempdir().unwrap();
+        let policy_file = dir.path().join("policy.csv");
+        fs::write(&policy_file, "# days,keep\n1, 2\n7,1\n\n*,0\n").unwrap();
+        let policy = read_policy_file(&policy_file).unwrap();
+        assert_eq!(policy.boundaries, BucketBoundaries(vec![1, 7]));
+        assert_eq!(policy.keep, vec![2, 1, 0]);
+
+        assert!(parse_policy("1,2\n").is_err()); // No * line
+        assert!(parse_policy("7,1\n1,2\n*,0\n").is_err());
+        assert!(parse_policy("*,0\n7,1\n").is_err());
+        assert!(parse_policy("7;1\n*,0\n").is_err());
+        assert!(parse_policy("7,-1\n*,0\n").is_err());
+        assert!(read_policy_file(&dir.path().join("missing.csv")).is_err());
 
         let now = time::SystemTime::now();
-        set_file_times(
-            &file1,
-            FileTime::from_system_time(now - time::Duration::from_secs(10000)),
-            FileTime::from_system_time(now - time::Duration::from_secs(10000)),
+        for (i, hours) in [1, 2, 3, 30, 50, 400, 500].iter().enumerate() {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(hours * 3600));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+        fs::remove_file(&policy_file).unwrap();
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            boundaries: Some(policy.boundaries.clone()),
+            policy: Some(policy),
+            ..Default::default()
+        };
+        let plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap();
+        let buckets: Vec<_> = plan
+            .files
+            .iter()
+            .map(|file| (file.bucket, file.action))
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (1, Action::Keep),
+                (1, Action::Keep),
+                (1, Action::Delete),
+                (7, Action::Keep),
+                (7, Action::Delete),
+                (OLDEST_BUCKET, Action::Delete),
+                (OLDEST_BUCKET, Action::Delete),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_fingerprint() {
+        println!("Testing fingerprints of the plan settings");
+
+        let dir = tempdir().unwrap();
+        let other_dir = tempdir().unwrap();
+        let options = plan_options(SortType::MTime, 2, false);
+        let fingerprint = plan_fingerprint(dir.path(), &options);
+        assert_eq!(fingerprint, plan_fingerprint(dir.path(), &options));
+        assert_eq!(fingerprint.len(), 64);
+        assert_ne!(fingerprint, plan_fingerprint(other_dir.path(), &options));
+        assert_ne!(
+            fingerprint,
+            plan_fingerprint(dir.path(), &plan_options(SortType::MTime, 3, false))
+        );
+        assert_ne!(
+            fingerprint,
+            plan_fingerprint(dir.path(), &plan_options(SortType::MTime, 2, true))
+        );
+        let shown_differently = PlanOptions {
+            show_owner: true,
+            template: Some(parse_template("{path}").unwrap()),
+            dir_jobs: 4,
+            ..plan_options(SortType::MTime, 2, false)
+        };
+        assert_eq!(
+            fingerprint,
+            plan_fingerprint(dir.path(), &shown_differently)
+        ); // Same files deleted
+    }
+
+    #[test]
+    fn test_cli_error() {
+        println!("Testing the human and JSON error output");
+
+        let error = CliError::new(
+            "not_a_directory",
+            "The provided path is a file, not a directory: /a/b",
+        )
+        .with_path("/a/b");
+        assert_eq!(
+            error.to_line(ErrorFormat::Human),
+            "Error: The provided path is a file, not a directory: /a/b"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&error.to_line(ErrorFormat::Json)).unwrap();
+        assert_eq!(json["code"], "not_a_directory");
+        assert_eq!(json["path"], "/a/b");
+
+        let json = CliError::new("no_directories", "No directories to process.")
+            .to_line(ErrorFormat::Json);
+        assert!(!json.contains("path")); // No context to give
+    }
+
+    #[test]
+    fn test_color_choice() {
+        println!("Testing when colors are enabled");
+
+        assert!(ColorChoice::Auto.enabled(true, false));
+        assert!(!ColorChoice::Auto.enabled(false, false)); // Not a terminal
+        assert!(!ColorChoice::Auto.enabled(true, true)); // NO_COLOR is set
+        assert!(ColorChoice::Always.enabled(false, true));
+        assert!(!ColorChoice::Never.enabled(true, false));
+
+        let mut report = Report::stdout(Verbosity::Normal);
+        assert_eq!(report.paint(RED, "file.txt"), "file.txt");
+        report.color = true;
+        assert_eq!(report.paint(RED, "file.txt"), "\x1b[31mfile.txt\x1b[0m");
+    }
+
+    #[test]
+    fn test_relative_time() {
+        println!("Testing relative file times");
+
+        let now = time::SystemTime::now();
+        let three_days_ago = now - time::Duration::from_secs(3 * 86400);
+        let report = Report {
+            relative_to: Some(now),
+            ..Report::stdout(Verbosity::Normal)
+        };
+        assert_eq!(report.timestamp(three_days_ago), "3 days ago");
+        assert_eq!(
+            report.timestamp(now + time::Duration::from_secs(60)),
+            "0 seconds ago"
+        ); // Future times
+
+        assert_eq!(
+            relative_time(time::Duration::from_secs(59)),
+            "59 seconds ago"
+        );
+        assert_eq!(relative_time(time::Duration::from_secs(60)), "1 minute ago");
+        assert_eq!(
+            relative_time(time::Duration::from_secs(2 * 3600 + 59)),
+            "2 hours ago"
+        );
+        assert_eq!(relative_time(time::Duration::from_secs(86400)), "1 day ago");
+
+        let absolute = Report::stdout(Verbosity::Normal).timestamp(three_days_ago);
+        assert!(!absolute.ends_with(" ago")); // Absolute time stays the default
+    }
+
+    #[test]
+    fn test_histogram_rows() {
+        println!("Testing the time segment histogram");
+
+        let dir = tempdir().unwrap();
+        let mut groups: Buckets = collections::BTreeMap::new();
+        for (bucket, count) in [(1, 3), (4, 1), (16, 6)] {
+            for i in 0..count {
+                let file = dir.path().join(format!("{}-{}.txt", bucket, i));
+                fs::write(&file, b"12").unwrap();
+                groups
+                    .entry(bucket)
+                    .or_default()
+                    .push((file, time::SystemTime::now()));
+            }
+        }
+
+        let rows = histogram_rows(&groups, &PlanOptions::default(), 100);
+        assert_eq!(rows.len(), 3); // A row per non-empty time segment
+        assert!(rows[0].starts_with("Younger than 1 days but older than 0 days "));
+        assert!(rows[0].ends_with(" 3 files (6 bytes)"));
+        assert!(rows[1].ends_with(" 1 files (2 bytes)"));
+        assert!(rows[2].ends_with(" 6 files (12 bytes)"));
+        assert!(rows.iter().all(|row| row.len() <= 100));
+
+        let bars: Vec<_> = rows.iter().map(|row| row.matches('#').count()).collect();
+        assert_eq!(bars[0] * 2, bars[2]); // Scaled to the largest segment
+        assert!(bars[1] >= 1);
+
+        let file = fs::File::open(dir.path().join("1-0.txt")).unwrap();
+        assert_eq!(window_columns(&file), None); // Not a terminal
+    }
+
+    #[test]
+    fn test_per_second() {
+        println!("Testing throughput calculation");
+
+        assert_eq!(per_second(100, time::Duration::from_secs(4)), 25.0);
+        assert_eq!(per_second(100, time::Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn test_csv_field() {
+        println!("Testing quoting of CSV fields");
+
+        assert_eq!(csv_field("/tmp/file.txt"), "/tmp/file.txt");
+        assert_eq!(csv_field("/tmp/a,b.txt"), "\"/tmp/a,b.txt\"");
+        assert_eq!(
+            csv_field("/tmp/\"quoted\".txt"),
+            "\"/tmp/\"\"quoted\"\".txt\""
+        );
+    }
+
+    #[test]
+    fn delete_files_freed_bytes() {
+        println!("Testing that delete_files reports the freed bytes");
+
+        let dir = tempdir().unwrap();
+        let file1 = dir.path().join("file1.txt");
+        let file2 = dir.path().join("file2.txt");
+        fs::write(&file1, b"1234").unwrap();
+        fs::write(&file2, b"123456").unwrap();
+
+        let report = delete_files(
+            Verbosity::Normal,
+            &[file1, file2, dir.path().join("missing.txt")],
+            &DeleteOptions::default(),
+            &atomic::AtomicBool::new(false),
         )
         .unwrap();
-        set_file_times(
-            &file2,
-            FileTime::from_system_time(now),
-            FileTime::from_system_time(now),
+        assert_eq!(report.deleted, 2);
+        assert_eq!(report.freed_bytes, 10);
+        assert_eq!(report.failed, 1); // The missing file
+    }
+
+    #[test]
+    fn delete_files_quiet_errors() {
+        println!("Testing that delete_files counts the errors per kind");
+
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        fs::File::create(&file).unwrap();
+        let mut files: Vec<_> = (0..3)
+            .map(|i| dir.path().join(format!("missing{}.txt", i)))
+            .collect();
+        files.push(file.clone());
+
+        let options = DeleteOptions {
+            quiet_errors: true,
+            ..Default::default()
+        };
+        let report = delete_files(
+            Verbosity::Normal,
+            &files,
+            &options,
+            &atomic::AtomicBool::new(false),
         )
         .unwrap();
-        set_file_times(
-            &file3,
-            FileTime::from_system_time(now - time::Duration::from_secs(1)),
-            FileTime::from_system_time(now - time::Duration::from_secs(1)),
+        assert_eq!(report.deleted, 1);
+        assert_eq!(report.failed, 3);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[&io::ErrorKind::NotFound], 3);
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn test_cap_deletions() {
+        println!("Testing capping the number of files to delete");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..101 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 60));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            keep_total: Some(1),
+            ..Default::default()
+        };
+        let mut plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap();
+        assert_eq!(plan.cap_deletions(10), 100);
+
+        let (to_keep, to_delete) = plan.split();
+        assert_eq!(to_keep.len(), 91);
+        assert_eq!(to_delete.len(), 10);
+        for i in 91..101 {
+            assert!(to_delete.contains(&dir.path().join(format!("file{}.txt", i)))); // The oldest ones
+        }
+
+        assert_eq!(plan.cap_deletions(20), 10); // Under the cap, nothing changes
+        assert_eq!(plan.paths(Action::Delete).len(), 10);
+    }
+
+    #[test]
+    fn test_cap_freed() {
+        println!("Testing deleting only the files needed to free some space");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..11 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::write(&file_path, vec![0u8; 100]).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 60));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            keep_total: Some(1),
+            ..Default::default()
+        };
+        let mut plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap();
+        assert_eq!(plan.cap_freed(250, false), 10); // Three files of 100 bytes reach 250 bytes
+
+        let to_delete = plan.paths(Action::Delete);
+        assert_eq!(to_delete.len(), 3);
+        for i in 8..11 {
+            assert!(to_delete.contains(&dir.path().join(format!("file{}.txt", i)))); // The oldest ones
+        }
+
+        assert_eq!(plan.cap_freed(300, false), 3); // Exactly reached, nothing changes
+        assert_eq!(plan.paths(Action::Delete).len(), 3);
+        assert_eq!(plan.cap_freed(200, false), 3);
+        assert_eq!(plan.paths(Action::Delete).len(), 2);
+    }
+
+    #[test]
+    fn test_delete_order() {
+        println!("Testing deleting the oldest or the newest files first");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        let files: Vec<_> = [2, 3, 1]
+            .iter()
+            .map(|days| {
+                let file = dir.path().join(format!("{}days.txt", days));
+                fs::File::create(&file).unwrap();
+                let ft = FileTime::from_system_time(now - time::Duration::from_secs(days * 86400));
+                set_file_times(&file, ft, ft).unwrap();
+                file
+            })
+            .collect();
+        let plan = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &PlanOptions {
+                sort_type: SortType::MTime,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let times = plan
+            .files
+            .iter()
+            .map(|file| (file.path.as_path(), file.time))
+            .collect();
+
+        let mut newest_first = files.clone();
+        DeleteOrder::Newest.sort(&mut newest_first, &times);
+        assert_eq!(
+            newest_first,
+            vec![files[2].clone(), files[0].clone(), files[1].clone()]
+        );
+
+        let mut oldest_first = files.clone();
+        DeleteOrder::Oldest.sort(&mut oldest_first, &times);
+        assert_eq!(
+            oldest_first,
+            vec![files[1].clone(), files[0].clone(), files[2].clone()]
+        );
+
+        let interrupted = atomic::AtomicBool::new(true); // Ctrl-C during the first deletion
+        delete_files(
+            Verbosity::Normal,
+            &oldest_first,
+            &DeleteOptions::default(),
+            &interrupted,
+        )
+        .unwrap();
+        assert!(!files[1].exists()); // Only the oldest file is gone
+        assert!(files[0].exists());
+        assert!(files[2].exists());
+    }
+
+    #[test]
+    fn test_resume_from_journal() {
+        println!("Testing resuming an interrupted deletion from its journal");
+
+        let dir = tempdir().unwrap();
+        let files: Vec<_> = (0..4)
+            .map(|i| dir.path().join(format!("file{}.txt", i)))
+            .collect();
+        for file in &files {
+            fs::File::create(file).unwrap();
+        }
+        let journal = dir.path().join("journal.txt");
+        write_journal(&journal, &files).unwrap();
+
+        let options = DeleteOptions {
+            journal: Some(journal.clone()),
+            ..Default::default()
+        };
+        let interrupted = atomic::AtomicBool::new(true); // Ctrl-C during the first deletion
+        let report = delete_files(Verbosity::Normal, &files, &options, &interrupted).unwrap();
+        assert!(report.interrupted);
+        assert_eq!(read_journal(&journal).unwrap(), files[1..].to_vec());
+
+        fs::File::create(&files[0]).unwrap(); // Already deleted files are not deleted again
+        let remaining = read_journal(&journal).unwrap();
+        let report = delete_files(
+            Verbosity::Normal,
+            &remaining,
+            &options,
+            &atomic::AtomicBool::new(false),
         )
         .unwrap();
-        set_file_times(
-            &file4,
-            FileTime::from_system_time(now - time::Duration::from_secs(500)),
-            FileTime::from_system_time(now - time::Duration::from_secs(500)),
+        assert_eq!(report.deleted, 3);
+        assert!(files[0].exists());
+        assert!(files[1..].iter().all(|file| !file.exists()));
+        assert!(read_journal(&journal).unwrap().is_empty());
+
+        // Names with line breaks and backslashes come back unchanged
+        let odd = vec![
+            dir.path().join("two\nlines.txt"),
+            dir.path().join("back\\slash\\n.txt"),
+            dir.path().join("- planned.txt"),
+        ];
+        write_journal(&journal, &odd).unwrap();
+        assert_eq!(fs::read_to_string(&journal).unwrap().lines().count(), 3);
+        assert_eq!(read_journal(&journal).unwrap(), odd);
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let file = dir
+                .path()
+                .join(ffi::OsStr::from_bytes(b"not\xffutf8\\x41.txt"));
+            assert_eq!(
+                unescape_journal_path(&escape_journal_path(&file)),
+                Some(file)
+            );
+        }
+        fs::write(&journal, "- bad\\escape.txt\n").unwrap();
+        assert!(read_journal(&journal).is_err());
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", not(target_env = "musl")))]
+    fn delete_files_recycle() {
+        println!("Testing moving the files to the trash instead of deleting them");
+
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("recycled.txt");
+        fs::write(&file, b"restore me").unwrap();
+
+        let options = DeleteOptions {
+            recycle: true,
+            ..Default::default()
+        };
+        let report = delete_files(
+            Verbosity::Normal,
+            std::slice::from_ref(&file),
+            &options,
+            &atomic::AtomicBool::new(false),
         )
         .unwrap();
+        assert_eq!(report.deleted, 1);
+        assert!(!file.exists());
 
-        let (to_keep, to_delete) =
-            exp_sort_and_list_to_del(false, dir.path(), &SortType::MTime, 1, false).unwrap();
+        let parent = fs::canonicalize(dir.path()).unwrap();
+        let trashed: Vec<_> = trash::os_limited::list()
+            .unwrap()
+            .into_iter()
+            .filter(|item| item.original_path() == parent.join("recycled.txt"))
+            .collect();
+        assert_eq!(trashed.len(), 1); // Still restorable from the trash
+        trash::os_limited::purge_all(trashed).unwrap();
 
-        assert!(to_keep.contains(&file1));
-        assert!(to_delete.contains(&file3));
-        assert!(to_delete.contains(&file4));
-        assert!(to_delete.contains(&file2));
-        assert_eq!(to_keep.len(), 1);
-        assert_eq!(to_delete.len(), 3);
+        assert!(trash_available()); // The home trash is found from $HOME
+    }
 
-        let (to_keep, to_delete) =
-            exp_sort_and_list_to_del(false, dir.path(), &SortType::ATime, 1, false).unwrap();
-        assert!(to_keep.contains(&file1));
-        assert!(to_delete.contains(&file3));
-        assert!(to_delete.contains(&file4));
-        assert!(to_delete.contains(&file2));
-        assert_eq!(to_keep.len(), 1);
-        assert_eq!(to_delete.len(), 3);
+    #[test]
+    fn delete_files_quarantine() {
+        println!("Testing quarantining files and purging the expired quarantine");
 
-        //Ctime is tested separately since it cannot be easily modified in tests
+        let dir = tempdir().unwrap();
+        let files: Vec<_> = (0..3)
+            .map(|i| dir.path().join(format!("file{}.txt", i)))
+            .collect();
+        for file in &files {
+            fs::write(file, "x").unwrap();
+        }
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let day_folder = dir.path().join(QUARANTINE_DIR_NAME).join("2024-03-10");
+        fs::create_dir_all(&day_folder).unwrap();
+        fs::write(day_folder.join("file0.txt"), "earlier").unwrap(); // Quarantined earlier that day
+        let options = DeleteOptions {
+            quarantine: Some(today),
+            ..Default::default()
+        };
+        let report = delete_files(
+            Verbosity::Normal,
+            &files,
+            &options,
+            &atomic::AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(report.deleted, 3);
+        assert_eq!(report.freed_bytes, 0); // Moving does not free any space
+        assert!(files.iter().all(|file| !file.exists()));
+        assert_eq!(fs::read_dir(&day_folder).unwrap().count(), 4);
+        assert!(day_folder.join("file0.txt.1").exists());
+
+        for day in ["2024-02-01", "2024-03-03", "notes"] {
+            fs::create_dir_all(dir.path().join(QUARANTINE_DIR_NAME).join(day)).unwrap();
+        }
+        assert_eq!(
+            expired_quarantine(dir.path(), false, 7, today),
+            vec![dir.path().join(QUARANTINE_DIR_NAME).join("2024-02-01")]
+        );
+        assert!(expired_quarantine(dir.path(), false, 60, today).is_empty());
+
+        let sub = dir.path().join("sub");
+        fs::create_dir_all(sub.join(QUARANTINE_DIR_NAME).join("2024-01-01")).unwrap();
+        assert_eq!(expired_quarantine(dir.path(), false, 7, today).len(), 1);
+        assert_eq!(expired_quarantine(dir.path(), true, 7, today).len(), 2);
     }
 
     #[test]
-    fn test_ctime() {
-        println!("Testing ctime sorting");
+    fn delete_files_verify() {
+        println!("Testing checking that deleted files are gone");
 
         let dir = tempdir().unwrap();
-        let file1 = dir.path().join("file1.txt");
-        fs::File::create(&file1).unwrap();
+        let files: Vec<_> = (0..3)
+            .map(|i| dir.path().join(format!("file{}.txt", i)))
+            .collect();
+        for file in &files {
+            fs::File::create(file).unwrap();
+        }
+        let options = DeleteOptions {
+            verify: true,
+            ..Default::default()
+        };
+        let report = delete_files(
+            Verbosity::Normal,
+            &files,
+            &options,
+            &atomic::AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(report.deleted, 3);
+        assert_eq!(report.failed, 0);
+        assert!(verify_deleted(&files[0]).is_ok());
 
-        thread::sleep(time::Duration::from_secs(2)); // Ensure a difference in ctime. That's why this test is slow.
+        // As if the filesystem had reported success without removing the file
+        let lingering = dir.path().join("lingering.txt");
+        fs::File::create(&lingering).unwrap();
+        let err = verify_deleted(&lingering).unwrap_err();
+        assert_eq!(err.to_string(), "still exists after being deleted");
+    }
 
-        let file2 = dir.path().join("file2.txt");
-        fs::File::create(&file2).unwrap();
+    #[test]
+    fn delete_files_mark_conflict() {
+        println!("Testing each --on-conflict policy of --mark");
 
-        thread::sleep(time::Duration::from_secs(2));
+        for on_conflict in [OnConflict::Skip, OnConflict::Overwrite, OnConflict::Rename] {
+            let dir = tempdir().unwrap();
+            let file = dir.path().join("file.txt");
+            let marked = dir.path().join("file.txt.deleted");
+            fs::write(&file, b"new").unwrap();
+            fs::write(&marked, b"old").unwrap();
+            fs::write(dir.path().join("file.txt.1.deleted"), b"older").unwrap();
 
-        let file3 = dir.path().join("file3.txt");
-        fs::File::create(&file3).unwrap();
+            let options = DeleteOptions {
+                mark: Some(".deleted".to_string()),
+                on_conflict,
+                ..Default::default()
+            };
+            let report = delete_files(
+                Verbosity::Normal,
+                std::slice::from_ref(&file),
+                &options,
+                &atomic::AtomicBool::new(false),
+            )
+            .unwrap();
+            assert_eq!(report.failed, 0);
+            match on_conflict {
+                OnConflict::Skip => {
+                    assert_eq!(report.conflicts, 1);
+                    assert_eq!(fs::read(&file).unwrap(), b"new"); // Left in place
+                    assert_eq!(fs::read(&marked).unwrap(), b"old");
+                }
+                OnConflict::Overwrite => {
+                    assert_eq!(report.deleted, 1);
+                    assert!(!file.exists());
+                    assert_eq!(fs::read(&marked).unwrap(), b"new");
+                }
+                OnConflict::Rename => {
+                    assert_eq!(report.deleted, 1);
+                    assert!(!file.exists());
+                    assert_eq!(fs::read(&marked).unwrap(), b"old");
+                    assert_eq!(
+                        fs::read(dir.path().join("file.txt.1.deleted")).unwrap(),
+                        b"older"
+                    );
+                    assert_eq!(
+                        fs::read(dir.path().join("file.txt.2.deleted")).unwrap(),
+                        b"new"
+                    ); // The first free number
+                }
+            }
+        }
+    }
 
-        let (to_keep, to_delete) =
-            exp_sort_and_list_to_del(false, dir.path(), &SortType::CTime, 1, false).unwrap();
+    #[cfg(unix)]
+    #[test]
+    fn delete_files_symlink() {
+        println!("Testing that deleting a symlink never deletes its target");
 
-        assert!(to_keep.contains(&file1));
-        assert!(to_delete.contains(&file2));
-        assert!(to_delete.contains(&file3));
-        assert_eq!(to_keep.len(), 1);
-        assert_eq!(to_delete.len(), 2);
+        let outside = tempdir().unwrap();
+        let target = outside.path().join("target.txt");
+        fs::write(&target, b"keep me").unwrap();
+        let dir = tempdir().unwrap();
+        let link = dir.path().join("link.txt");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        for recycle in [false, true] {
+            if recycle {
+                std::os::unix::fs::symlink(&target, &link).unwrap();
+            }
+            let options = DeleteOptions {
+                recycle,
+                ..Default::default()
+            };
+            let report = delete_files(
+                Verbosity::Normal,
+                std::slice::from_ref(&link),
+                &options,
+                &atomic::AtomicBool::new(false),
+            )
+            .unwrap();
+            assert_eq!(report.deleted, 1);
+            assert!(fs::symlink_metadata(&link).is_err()); // The link is gone
+            assert_eq!(fs::read(&target).unwrap(), b"keep me"); // The target survives
+        }
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_buckets_behavior() {
-        println!("Testing buckets behavior explicitly");
+    fn test_dereference_size() {
+        println!("Testing the size of symlinks with and without --dereference-size");
 
+        let outside = tempdir().unwrap();
+        let target = outside.path().join("large.bin");
+        fs::write(&target, vec![0u8; 100_000]).unwrap();
         let dir = tempdir().unwrap();
-        let now = time::SystemTime::now();
+        let link = dir.path().join("link.bin");
+        let link_size = target.as_os_str().len() as u64; // A symlink stores the path of its target
 
-        for i in 0..16 {
-            let file_path = dir.path().join(format!("file{}.txt", i));
-            fs::File::create(&file_path).unwrap();
-            let days = i * 86400;
-            set_file_times(
-                &file_path,
-                FileTime::from_system_time(now - time::Duration::from_secs(days)),
-                FileTime::from_system_time(now - time::Duration::from_secs(days)),
+        for dereference_size in [false, true] {
+            std::os::unix::fs::symlink(&target, &link).unwrap();
+            let expected = if dereference_size { 100_000 } else { link_size };
+            let files = vec![link.clone()];
+            assert_eq!(total_size(&files, dereference_size), expected);
+            let options = DeleteOptions {
+                dereference_size,
+                ..Default::default()
+            };
+            let report = delete_files(
+                Verbosity::Quiet,
+                &files,
+                &options,
+                &atomic::AtomicBool::new(false),
             )
             .unwrap();
+            assert_eq!(report.freed_bytes, expected);
+            assert!(target.exists()); // Only the link is deleted either way
         }
+    }
 
-        let (to_keep, to_delete) =
-            exp_sort_and_list_to_del(false, dir.path(), &SortType::MTime, 1, false).unwrap();
-
-        assert!(to_delete.contains(&dir.path().join("file0.txt"))); //Files asserted explicitly
-        assert!(to_keep.contains(&dir.path().join("file1.txt")));
-        assert!(to_keep.contains(&dir.path().join("file2.txt")));
-        assert!(to_delete.contains(&dir.path().join("file3.txt")));
-        assert!(to_keep.contains(&dir.path().join("file4.txt")));
-        assert!(to_delete.contains(&dir.path().join("file5.txt")));
-        assert!(to_delete.contains(&dir.path().join("file6.txt")));
-        assert!(to_delete.contains(&dir.path().join("file7.txt")));
-        assert!(to_keep.contains(&dir.path().join("file8.txt")));
-        assert!(to_delete.contains(&dir.path().join("file9.txt")));
-        assert!(to_delete.contains(&dir.path().join("file10.txt")));
-        assert!(to_delete.contains(&dir.path().join("file11.txt")));
-        assert!(to_delete.contains(&dir.path().join("file12.txt")));
-        assert!(to_delete.contains(&dir.path().join("file13.txt")));
-        assert!(to_delete.contains(&dir.path().join("file14.txt")));
-        assert!(to_keep.contains(&dir.path().join("file15.txt")));
-        assert_eq!(to_keep.len(), 5);
-        assert_eq!(to_delete.len(), 11);
+    #[cfg(unix)]
+    #[test]
+    fn test_file_owner() {
+        use std::os::unix::fs::MetadataExt;
+        println!("Testing file_owner function");
 
-        let (to_keep, to_delete) =
-            exp_sort_and_list_to_del(false, dir.path(), &SortType::ATime, 1, false).unwrap();
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        fs::File::create(&file).unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
 
-        assert!(to_delete.contains(&dir.path().join("file0.txt")));
-        assert!(to_keep.contains(&dir.path().join("file1.txt")));
-        assert!(to_keep.contains(&dir.path().join("file2.txt")));
-        assert!(to_delete.contains(&dir.path().join("file3.txt")));
-        assert!(to_keep.contains(&dir.path().join("file4.txt")));
-        assert!(to_delete.contains(&dir.path().join("file5.txt")));
-        assert!(to_delete.contains(&dir.path().join("file6.txt")));
-        assert!(to_delete.contains(&dir.path().join("file7.txt")));
-        assert!(to_keep.contains(&dir.path().join("file8.txt")));
-        assert!(to_delete.contains(&dir.path().join("file9.txt")));
-        assert!(to_delete.contains(&dir.path().join("file10.txt")));
-        assert!(to_delete.contains(&dir.path().join("file11.txt")));
-        assert!(to_delete.contains(&dir.path().join("file12.txt")));
-        assert!(to_delete.contains(&dir.path().join("file13.txt")));
-        assert!(to_delete.contains(&dir.path().join("file14.txt")));
-        assert!(to_keep.contains(&dir.path().join("file15.txt")));
-        assert_eq!(to_keep.len(), 5);
-        assert_eq!(to_delete.len(), 11);
+        let owner = file_owner(&file).unwrap();
+        assert!(owner.ends_with(" 0640"));
+        let uid = fs::metadata(&file).unwrap().uid();
+        let user = process::Command::new("id").arg("-un").output().unwrap();
+        let user = String::from_utf8_lossy(&user.stdout).trim().to_string();
+        assert!(owner == format!("{} 0640", user) || owner == format!("{} 0640", uid));
+        assert_eq!(file_owner(&dir.path().join("missing.txt")), None);
 
-        // CTime is not tested here since it cannot be easily modified in tests
+        assert_eq!(user_name(0).as_deref(), Some("root"));
+        assert_eq!(user_name(0).as_deref(), Some("root")); // From the cache
+        assert_eq!(user_name(u32::MAX - 1), None); // Shown as the uid
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_identical_times() {
-        println!("Testing with files having identical modification times");
+    fn test_when_above() {
+        println!("Testing the disk usage threshold");
 
         let dir = tempdir().unwrap();
-        let now = time::SystemTime::now();
-        let ft = FileTime::from_system_time(now);
-
-        let file1 = dir.path().join("file1.txt");
-        let file2 = dir.path().join("file2.txt");
-        let file3 = dir.path().join("file3.txt");
-        let file4 = dir.path().join("file4.txt");
-        fs::File::create(&file1).unwrap();
-        fs::File::create(&file2).unwrap();
-        fs::File::create(&file3).unwrap();
-        fs::File::create(&file4).unwrap();
-        set_file_times(&file1, ft, ft).unwrap();
-        set_file_times(&file2, ft, ft).unwrap();
-        set_file_times(&file3, ft, ft).unwrap();
-        set_file_times(&file4, ft, ft).unwrap();
+        let percent = disk_usage_percent(dir.path()).unwrap();
+        assert!((0.0..=100.0).contains(&percent));
+        assert!(disk_usage_percent(&dir.path().join("missing")).is_err());
+        assert_eq!(usage_percent(0, 0), 0.0);
+        assert_eq!(usage_percent(75, 25), 75.0);
 
-        let (to_keep, to_delete) =
-            exp_sort_and_list_to_del(false, dir.path(), &SortType::MTime, 2, false).unwrap(); //Function deletes randomly. It is expected behavior for now. Maybe change in the future for asking the user.
+        let targets = vec![dir.path().to_path_buf()];
+        let check = |usage: f64| {
+            targets_above(
+                &mut Report::stdout(Verbosity::Quiet),
+                targets.clone(),
+                80,
+                |_| Ok(usage),
+            )
+            .unwrap()
+        };
+        assert_eq!(check(80.5), targets); // Above the threshold
+        assert!(check(80.0).is_empty());
+        assert!(check(12.0).is_empty());
 
-        assert_eq!(to_keep.len(), 2);
-        assert_eq!(to_delete.len(), 2);
-        assert_eq!(to_keep.len() + to_delete.len(), 4);
+        let err = targets_above(
+            &mut Report::stdout(Verbosity::Quiet),
+            targets.clone(),
+            80,
+            |_| Err(io::Error::other("no statvfs")),
+        )
+        .unwrap_err();
+        assert_eq!(err.0, dir.path());
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_zero_files_to_keep() {
-        println!("Testing with zero files to keep");
+    fn test_when_inodes_below() {
+        println!("Testing the free inodes threshold");
 
         let dir = tempdir().unwrap();
-        let mut rng = rand::rng();
+        let percent = free_inodes_percent(dir.path()).unwrap();
+        assert!((0.0..=100.0).contains(&percent));
+        assert!(free_inodes_percent(&dir.path().join("missing")).is_err());
 
-        for i in 0..5 {
-            let file_path = dir.path().join(format!("file{}.txt", i));
-            let mut file = fs::File::create(&file_path).unwrap();
-            writeln!(file, "test {}", i).unwrap();
+        let targets = vec![dir.path().to_path_buf()];
+        let check = |free: f64| {
+            targets_inodes_below(
+                &mut Report::stdout(Verbosity::Quiet),
+                targets.clone(),
+                10,
+                |_| Ok(free),
+            )
+            .unwrap()
+        };
+        assert_eq!(check(9.5), targets); // Inodes are scarce
+        assert!(check(10.0).is_empty());
+        assert!(check(64.0).is_empty());
 
-            let now = time::SystemTime::now();
-            let offset_secs = rng.random_range(0..30 * 24 * 3600);
-            let random_time = FileTime::from_unix_time(
-                now.duration_since(time::UNIX_EPOCH).unwrap().as_secs() as i64 - offset_secs as i64,
-                0,
-            );
+        let err = targets_inodes_below(
+            &mut Report::stdout(Verbosity::Quiet),
+            targets.clone(),
+            10,
+            |_| Err(io::Error::other("no statvfs")),
+        )
+        .unwrap_err();
+        assert_eq!(err.0, dir.path());
+    }
 
-            set_file_times(&file_path, random_time, random_time).unwrap();
+    #[test]
+    fn delete_files_max_runtime() {
+        println!("Testing that delete_files stops at the deadline");
+
+        let dir = tempdir().unwrap();
+        let files: Vec<_> = (0..100)
+            .map(|i| dir.path().join(format!("file{}.txt", i)))
+            .collect();
+        for file in &files {
+            fs::File::create(file).unwrap();
         }
 
-        let result = exp_sort_and_list_to_del(false, dir.path(), &SortType::MTime, 0, false);
-        assert!(result.is_ok());
-        let result = exp_sort_and_list_to_del(false, dir.path(), &SortType::ATime, 0, false);
-        assert!(result.is_ok());
-        let result = exp_sort_and_list_to_del(false, dir.path(), &SortType::CTime, 0, false);
-        assert!(result.is_ok());
+        let options = DeleteOptions {
+            deadline: Some(time::Instant::now()), // A budget already used up
+            ..Default::default()
+        };
+        let report = delete_files(
+            Verbosity::Normal,
+            &files,
+            &options,
+            &atomic::AtomicBool::new(false),
+        )
+        .unwrap();
+        assert!(report.timed_out);
+        assert_eq!(report.deleted, 0);
+        assert!(files.iter().all(|file| file.exists()));
+
+        let options = DeleteOptions {
+            deadline: Some(time::Instant::now() + time::Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let report = delete_files(
+            Verbosity::Normal,
+            &files,
+            &options,
+            &atomic::AtomicBool::new(false),
+        )
+        .unwrap();
+        assert!(!report.timed_out);
+        assert_eq!(report.deleted, 100);
     }
 
     #[test]
-    fn test_empty_directory() {
-        println!("Testing with an empty directory");
+    fn test_tally_by_dir() {
+        println!("Testing counting kept and deleted files per directory");
 
-        let dir = tempdir().unwrap();
-        let result = exp_sort_and_list_to_del(false, dir.path(), &SortType::MTime, 2, false);
-        assert!(result.is_err());
-        let err = result.unwrap_err();
-        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let to_keep = vec![
+            path::PathBuf::from("/a/1"),
+            path::PathBuf::from("/a/2"),
+            path::PathBuf::from("/b/3"),
+        ];
+        let to_delete = vec![path::PathBuf::from("/a/4"), path::PathBuf::from("/c/5")];
+        let tally = tally_by_dir(&to_keep, &to_delete);
+
+        assert_eq!(tally.len(), 3);
+        assert_eq!(tally[path::Path::new("/a")], (2, 1));
+        assert_eq!(tally[path::Path::new("/b")], (1, 0));
+        assert_eq!(tally[path::Path::new("/c")], (0, 1));
     }
 
     #[test]
-    fn test_invalid_path() {
-        println!("Testing with an invalid path");
+    fn test_total_size() {
+        println!("Testing total_size function");
 
-        let invalid_path = path::Path::new("/invalid/path");
-        let result = exp_sort_and_list_to_del(false, invalid_path, &SortType::MTime, 2, false);
-        assert!(result.is_err());
-        let err = result.unwrap_err();
-        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let dir = tempdir().unwrap();
+        let file1 = dir.path().join("file1.txt");
+        let file2 = dir.path().join("file2.txt");
+        fs::write(&file1, b"12345").unwrap();
+        fs::write(&file2, b"123").unwrap();
+        let missing = dir.path().join("missing.txt"); // Missing files are not counted
+
+        assert_eq!(total_size(&[file1, file2, missing], false), 8);
+        assert_eq!(total_size(&[], false), 0);
     }
 
     #[test]
-    fn test_file_instead_of_directory() {
-        println!("Testing with a file instead of a directory");
+    fn delete_files_test() {
+        println!("Testing delete_files function");
 
         let dir = tempdir().unwrap();
-        let file_path = dir.path().join("test_file.txt");
-        fs::File::create(&file_path).unwrap();
-        let result = exp_sort_and_list_to_del(false, &file_path, &SortType::MTime, 2, false);
-        assert!(result.is_err());
-        let err = result.unwrap_err();
-        assert_eq!(err.kind(), io::ErrorKind::NotADirectory);
+        let file1 = dir.path().join("file1.txt");
+        let file2 = dir.path().join("file2.txt");
+        fs::File::create(&file1).unwrap();
+        fs::File::create(&file2).unwrap();
+
+        let files_to_delete = vec![file1.clone(), file2.clone()];
+        let result = delete_files(
+            Verbosity::Normal,
+            &files_to_delete,
+            &DeleteOptions::default(),
+            &atomic::AtomicBool::new(false),
+        );
+        assert!(result.is_ok());
+        assert!(!file1.exists());
+        assert!(!file2.exists());
     }
 
     #[test]
-    fn test_same_times() {
-        println!("Testing with files having the same modification time");
-
-        //For now the program deletes randomly
-        //Maybe different implementation in the future
+    fn delete_files_interrupted() {
+        println!("Testing delete_files function when interrupted with Ctrl-C");
 
         let dir = tempdir().unwrap();
-        let now = time::SystemTime::now();
-        let ft = FileTime::from_system_time(now);
-        for i in 0..3 {
-            let file_path = dir.path().join(format!("file{}.txt", i));
-            fs::File::create(&file_path).unwrap();
-            set_file_times(&file_path, ft, ft).unwrap();
+        let files: Vec<_> = (0..3)
+            .map(|i| dir.path().join(format!("file{}.txt", i)))
+            .collect();
+        for file in &files {
+            fs::File::create(file).unwrap();
         }
 
-        let result = exp_sort_and_list_to_del(false, dir.path(), &SortType::MTime, 1, false);
-        assert!(result.is_ok());
-        let result = exp_sort_and_list_to_del(false, dir.path(), &SortType::ATime, 1, false);
-        assert!(result.is_ok());
-        let result = exp_sort_and_list_to_del(false, dir.path(), &SortType::CTime, 1, false);
+        let interrupted = atomic::AtomicBool::new(true); // Simulate Ctrl-C pressed during the first deletion
+        let report = delete_files(
+            Verbosity::Normal,
+            &files,
+            &DeleteOptions::default(),
+            &interrupted,
+        )
+        .unwrap();
+
+        assert!(report.interrupted);
+        assert_eq!(report.deleted, 1);
+        assert!(!files[0].exists());
+        assert!(files[1].exists());
+        assert!(files[2].exists());
+    }
+
+    #[test]
+    fn test_with_retries() {
+        println!("Testing retrying operations failing with transient errors");
+
+        let delay = time::Duration::from_millis(1);
+
+        let mut calls = 0;
+        let result = with_retries(3, delay, || {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from(io::ErrorKind::Interrupted))
+            } else {
+                Ok(())
+            }
+        });
         assert!(result.is_ok());
+        assert_eq!(calls, 3); // Succeeded on the second retry
+
+        let mut calls = 0;
+        let result: io::Result<()> = with_retries(2, delay, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::TimedOut))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(calls, 3); // One attempt and two retries
+
+        let mut calls = 0;
+        let result: io::Result<()> = with_retries(5, delay, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(calls, 1); // Permission errors are not retried
     }
 
     #[test]
-    fn delete_files_test() {
-        println!("Testing delete_files function");
+    fn test_undeletable_files() {
+        println!("Testing checking whether files could be deleted");
+
+        let dir = tempdir().unwrap();
+        let file1 = dir.path().join("file1.txt");
+        fs::File::create(&file1).unwrap();
+        let missing = dir.path().join("missing.txt");
+
+        let undeletable = undeletable_files(&[file1.clone(), missing.clone()]);
+        assert_eq!(undeletable.len(), 1);
+        assert_eq!(undeletable[0].0, missing);
+
+        #[cfg(unix)]
+        {
+            let mut perms = fs::metadata(dir.path()).unwrap().permissions();
+            perms.set_mode(0o555);
+            fs::set_permissions(dir.path(), perms).unwrap();
+            if check_writable(dir.path()).is_ok() {
+                println!("The directory is still writable (running as root?), skipping the rest");
+                return;
+            }
 
-        let dir = tempdir().unwrap();
-        let file1 = dir.path().join("file1.txt");
-        let file2 = dir.path().join("file2.txt");
-        fs::File::create(&file1).unwrap();
-        fs::File::create(&file2).unwrap();
+            let undeletable = undeletable_files(std::slice::from_ref(&file1));
+            assert_eq!(undeletable.len(), 1);
+            assert!(undeletable[0].1.contains("is not writable"));
+            assert!(file1.exists()); // Nothing is deleted
 
-        let files_to_delete = vec![file1.clone(), file2.clone()];
-        let result = delete_files(false, &files_to_delete);
-        assert!(result.is_ok());
-        assert!(!file1.exists());
-        assert!(!file2.exists());
+            fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        }
     }
 
     #[test]
@@ -687,11 +8153,22 @@ mod tests {
             println!("Permission denied test is not implemented on Windows");
             return;
         }
+        if check_writable(dir.path()).is_ok() {
+            println!("Directory still writable (running as root?), skipping");
+            return;
+        }
 
         let files_to_delete = vec![file1.clone()];
-        let result = delete_files(false, &files_to_delete);
+        let report = delete_files(
+            Verbosity::Normal,
+            &files_to_delete,
+            &DeleteOptions::default(),
+            &atomic::AtomicBool::new(false),
+        )
+        .unwrap();
 
-        assert!(result.is_ok());
+        assert_eq!(report.deleted, 0);
+        assert_eq!(report.failed, 1); // Reported so that main exits with EXIT_PARTIAL_FAILURE
         assert!(file1.exists());
     }
 
@@ -710,9 +8187,20 @@ mod tests {
         let subfile_path = sub_dir_path.join("subfile.txt");
         fs::File::create(&subfile_path).unwrap();
 
-        let (_to_keep, to_delete) =
-            exp_sort_and_list_to_del(false, dir.path(), &SortType::MTime, 0, false).unwrap();
-        delete_files(false, &to_delete).unwrap();
+        let (_to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 0, false),
+        )
+        .unwrap()
+        .split();
+        delete_files(
+            Verbosity::Normal,
+            &to_delete,
+            &DeleteOptions::default(),
+            &atomic::AtomicBool::new(false),
+        )
+        .unwrap();
 
         assert!(dir.path().exists());
         for i in 0..5 {
@@ -738,9 +8226,20 @@ mod tests {
         let subfile_path = sub_dir_path.join("subfile.txt");
         fs::File::create(&subfile_path).unwrap();
 
-        let (_to_keep, to_delete) =
-            exp_sort_and_list_to_del(false, dir.path(), &SortType::MTime, 0, true).unwrap();
-        delete_files(false, &to_delete).unwrap();
+        let (_to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 0, true),
+        )
+        .unwrap()
+        .split();
+        delete_files(
+            Verbosity::Normal,
+            &to_delete,
+            &DeleteOptions::default(),
+            &atomic::AtomicBool::new(false),
+        )
+        .unwrap();
 
         assert!(dir.path().exists());
         for i in 0..5 {
@@ -751,6 +8250,797 @@ mod tests {
         assert!(!subfile_path.exists());
     }
 
+    #[test]
+    fn test_empty_subdirectories() {
+        println!("Testing empty subdirectories do not stop recursive mode");
+
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("empty1")).unwrap();
+        fs::create_dir_all(dir.path().join("nested").join("empty2")).unwrap();
+        let full_dir = dir.path().join("full");
+        fs::create_dir(&full_dir).unwrap();
+        for i in 0..3 {
+            fs::File::create(full_dir.join(format!("file{}.txt", i))).unwrap();
+        }
+
+        let (to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 1, true),
+        )
+        .unwrap()
+        .split(); // The root itself has no files either
+        assert_eq!(to_keep.len(), 1);
+        assert_eq!(to_delete.len(), 2);
+
+        let result = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &plan_options(SortType::MTime, 1, false),
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound); // No files in the target itself
+
+        let (groups, _skipped) = group_files_by_bucket(
+            &dir.path().join("empty1"),
+            &plan_options(SortType::MTime, 1, false),
+        )
+        .unwrap();
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_stat_errors() {
+        println!("Testing entries whose metadata cannot be read");
+
+        let dir = tempdir().unwrap();
+        for i in 0..3 {
+            fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+        }
+        std::os::unix::fs::symlink(dir.path().join("missing"), dir.path().join("broken")).unwrap();
+
+        let plan = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            dir.path(),
+            &PlanOptions::default(),
+        )
+        .unwrap(); // The broken symlink does not stop the scan
+        assert_eq!(plan.files.len(), 3);
+        assert_eq!(plan.skipped.len(), 1);
+
+        // Names can be listed but not stat'd in a directory without the execute permission
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::File::create(locked.join("file.txt")).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o444)).unwrap();
+        if fs::metadata(locked.join("file.txt")).is_ok() {
+            println!("Metadata still readable (running as root?), skipping the rest");
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+
+        let (groups, skipped) = group_files_by_bucket(&locked, &PlanOptions::default()).unwrap();
+        assert!(groups.is_empty());
+        assert!(matches!(skipped[0].reason, SkipReason::StatError(_)));
+
+        let options = PlanOptions {
+            stat_errors_fatal: true,
+            ..Default::default()
+        };
+        assert!(group_files_by_bucket(&locked, &options).is_err());
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_inode_sort() {
+        println!("Testing that reading the files in inode order does not change the plan");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..50 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i % 7 * 86400));
+            set_file_times(&file_path, ft, ft).unwrap(); // Many files with the same time
+        }
+        fs::create_dir(dir.path().join("subdir")).unwrap();
+
+        let plan = |inode_sort| {
+            let options = PlanOptions {
+                sort_type: SortType::MTime,
+                keep: 2,
+                inode_sort,
+                ..Default::default()
+            };
+            let plan = exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Normal),
+                dir.path(),
+                &options,
+            )
+            .unwrap();
+            let files: Vec<_> = plan
+                .files
+                .into_iter()
+                .map(|file| (file.path, file.bucket, file.action))
+                .collect();
+            let skipped: Vec<_> = plan.skipped.into_iter().map(|entry| entry.path).collect();
+            (files, skipped)
+        };
+        assert_eq!(plan(true), plan(false));
+    }
+
+    #[test]
+    fn test_round_times() {
+        println!("Testing rounding file times to a coarse timestamp resolution");
+
+        let dir = tempdir().unwrap();
+        let old =
+            FileTime::from_system_time(time::UNIX_EPOCH + time::Duration::from_secs(1_000_000));
+        set_file_times(dir.path(), old, old).unwrap();
+        assert!(timestamp_resolution(dir.path()).unwrap() < time::Duration::from_secs(2)); // Not FAT
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0); // The probe is removed
+        assert_eq!(
+            FileTime::from_last_modification_time(&fs::metadata(dir.path()).unwrap()),
+            old
+        ); // And the directory time restored
+
+        let at = |secs, nanos| time::UNIX_EPOCH + time::Duration::new(secs, nanos);
+        let two_seconds = time::Duration::from_secs(2);
+        let time = at(1_000_000_001, 999_000_000);
+        assert_eq!(round_time(time, two_seconds), at(1_000_000_000, 0));
+        assert_eq!(round_time(time, time::Duration::ZERO), time);
+
+        let base = time::SystemTime::now() - time::Duration::from_secs(3 * 86400 + 3600);
+        let base = round_time(base, two_seconds);
+        for (name, offset) in [("a.txt", 1), ("b.txt", 0)] {
+            let file_path = dir.path().join(name);
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(base + time::Duration::new(offset, 300_000_000));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // Within the same 2 seconds, as FAT would store them
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            time_resolution: Some(two_seconds), // As if probed on FAT
+            ..Default::default()
+        };
+        let (groups, _) = group_files_by_bucket(dir.path(), &options).unwrap();
+        let files: Vec<_> = groups.values().flatten().collect();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|(_, time)| *time == base)); // Same time and time segment
+        assert_eq!(groups.len(), 1);
+    }
+
+    #[test]
+    fn test_extensions() {
+        println!("Testing processing only files with the given extensions");
+
+        assert_eq!(parse_extension(".LOG").unwrap(), "LOG");
+        assert_eq!(parse_extension(" bak ").unwrap(), "bak");
+        assert!(parse_extension(".").is_err());
+
+        let dir = tempdir().unwrap();
+        for name in ["a.log", "b.LOG", "c.log.gz", "d.txt", "log"] {
+            fs::File::create(dir.path().join(name)).unwrap();
+        }
+
+        let options = PlanOptions {
+            extensions: vec![parse_extension(".log").unwrap()],
+            ..Default::default()
+        };
+        let plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap();
+        let planned: collections::BTreeSet<_> = plan
+            .files
+            .iter()
+            .map(|file| file.path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(planned, ["a.log", "b.LOG"].map(ffi::OsString::from).into());
+        assert_eq!(plan.skipped.len(), 3);
+        assert!(
+            plan.skipped
+                .iter()
+                .all(|entry| entry.reason == SkipReason::OtherExtension)
+        );
+    }
+
+    #[test]
+    fn test_skipped_entries() {
+        println!("Testing skipped entries are collected with their reasons");
+
+        let dir = tempdir().unwrap();
+        let sub_dir = dir.path().join("subdir");
+        let small_dir = sub_dir.join("small");
+        fs::create_dir_all(&small_dir).unwrap();
+        fs::create_dir(dir.path().join(".hidden")).unwrap();
+        fs::File::create(small_dir.join("lonely.txt")).unwrap();
+        for i in 0..3 {
+            fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+            fs::File::create(sub_dir.join(format!("file{}.txt", i))).unwrap();
+        }
+        let future_file = dir.path().join("future.txt");
+        fs::File::create(&future_file).unwrap();
+        let ft =
+            FileTime::from_system_time(time::SystemTime::now() + time::Duration::from_secs(3600));
+        set_file_times(&future_file, ft, ft).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path().join("missing.txt"), dir.path().join("link.txt"))
+            .unwrap();
+
+        let mut options = plan_options(SortType::MTime, 1, false);
+        let plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap();
+        let reason_of = |plan: &Plan, file: &path::Path| {
+            plan.skipped
+                .iter()
+                .find(|entry| entry.path == file)
+                .map(|entry| entry.reason.clone())
+        };
+        assert_eq!(reason_of(&plan, &sub_dir), Some(SkipReason::Directory));
+        assert_eq!(reason_of(&plan, &future_file), Some(SkipReason::FutureTime));
+        #[cfg(unix)]
+        assert_eq!(
+            reason_of(&plan, &dir.path().join("link.txt")),
+            Some(SkipReason::Symlink)
+        );
+        assert_eq!(plan.files.len(), 3);
+
+        options.recursive = true;
+        options.skip_hidden = true;
+        options.min_files = 2;
+        let plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap();
+        assert_eq!(
+            reason_of(&plan, &dir.path().join(".hidden")),
+            Some(SkipReason::Hidden)
+        );
+        assert_eq!(reason_of(&plan, &small_dir), Some(SkipReason::TooFewFiles));
+        assert_eq!(reason_of(&plan, &sub_dir), None); // Walked into, not skipped
+        assert_eq!(reason_of(&plan, &future_file), Some(SkipReason::FutureTime));
+        assert_eq!(
+            SkipReason::StatError("Permission denied".to_string()).to_string(),
+            "could not read metadata: Permission denied"
+        );
+    }
+
+    #[test]
+    fn test_keep_for() {
+        println!("Testing per-directory keep overrides in recursive mode");
+
+        assert_eq!(
+            parse_keep_override("app/logs=5"),
+            Ok(KeepOverride {
+                dir: path::PathBuf::from("app/logs"),
+                keep: 5
+            })
+        );
+        assert!(parse_keep_override("logs").is_err());
+        assert!(parse_keep_override("=5").is_err());
+        assert!(parse_keep_override("logs=many").is_err());
+
+        let dir = tempdir().unwrap();
+        for name in ["logs", "tmp", "other"] {
+            let sub_dir = dir.path().join(name);
+            fs::create_dir(&sub_dir).unwrap();
+            for i in 0..10 {
+                fs::File::create(sub_dir.join(format!("file{}.txt", i))).unwrap();
+            }
+        }
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            keep: 2,
+            keep_for: vec![
+                parse_keep_override("logs=5").unwrap(),
+                parse_keep_override("tmp=1").unwrap(),
+            ],
+            recursive: true,
+            ..Default::default()
+        };
+        let (to_keep, _to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        let kept_in = |name: &str| {
+            to_keep
+                .iter()
+                .filter(|file| file.parent() == Some(&dir.path().join(name)))
+                .count()
+        };
+        assert_eq!(kept_in("logs"), 5);
+        assert_eq!(kept_in("tmp"), 1);
+        assert_eq!(kept_in("other"), 2); // Falls back to --keep
+    }
+
+    #[test]
+    fn test_dir_jobs() {
+        println!("Testing scanning directories in parallel in recursive mode");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for d in 0..12 {
+            let sub_dir = dir.path().join(format!("dir{}", d)).join("nested");
+            fs::create_dir_all(&sub_dir).unwrap();
+            for (i, hours) in [1, 30, 90, 200, 500].iter().enumerate() {
+                for parent in [sub_dir.as_path(), sub_dir.parent().unwrap()] {
+                    let file_path = parent.join(format!("file{}.txt", i));
+                    fs::File::create(&file_path).unwrap();
+                    let ft =
+                        FileTime::from_system_time(now - time::Duration::from_secs(hours * 3600));
+                    set_file_times(&file_path, ft, ft).unwrap();
+                }
+            }
+        }
+        fs::create_dir(dir.path().join("empty")).unwrap();
+
+        let plan = |dir_jobs| {
+            let options = PlanOptions {
+                dir_jobs,
+                min_files: 2,
+                ..plan_options(SortType::MTime, 1, true)
+            };
+            let plan = exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Quiet),
+                dir.path(),
+                &options,
+            )
+            .unwrap();
+            let files: Vec<_> = plan
+                .files
+                .iter()
+                .map(|file| (file.path.clone(), file.bucket, file.action))
+                .collect();
+            let skipped: Vec<_> = plan
+                .skipped
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect();
+            (files, skipped)
+        };
+        let sequential = plan(1);
+        assert_eq!(sequential.0.len(), 120);
+        for dir_jobs in [2, 4, 16] {
+            assert_eq!(plan(dir_jobs), sequential);
+        }
+    }
+
+    #[test]
+    fn test_stat_jobs() {
+        println!("Testing reading the file metadata in parallel");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..50 {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 7 * 3600));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+        fs::create_dir(dir.path().join("sub")).unwrap();
+
+        let plan = |stat_jobs| {
+            let options = PlanOptions {
+                stat_jobs,
+                ..plan_options(SortType::MTime, 2, false)
+            };
+            let plan = exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Quiet),
+                dir.path(),
+                &options,
+            )
+            .unwrap();
+            let files: Vec<_> = plan
+                .files
+                .iter()
+                .map(|file| (file.path.clone(), file.bucket, file.action))
+                .collect();
+            let skipped: Vec<_> = plan
+                .skipped
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect();
+            (files, skipped)
+        };
+        let sequential = plan(1);
+        assert_eq!(sequential.0.len(), 50);
+        assert_eq!(sequential.1.len(), 1);
+        for stat_jobs in [2, 3, 8, 64] {
+            assert_eq!(plan(stat_jobs), sequential);
+        }
+    }
+
+    #[test]
+    fn test_tree_lines() {
+        println!("Testing drawing the recursive plan as a tree");
+
+        let root = path::Path::new("/srv/backups");
+        let file = |path: &str, action| PlannedFile {
+            path: root.join(path),
+            time: time::SystemTime::UNIX_EPOCH,
+            bucket: 1,
+            action,
+            checksum: None,
+        };
+        let files = [
+            file("root.txt", Action::Keep),
+            file("a/1.txt", Action::Keep),
+            file("a/2.txt", Action::Delete),
+            file("a/b/1.txt", Action::Delete),
+            file("a/b/2.txt", Action::Delete),
+            file("c/d/1.txt", Action::Keep),
+            file("e/1.txt", Action::Keep),
+        ];
+        assert_eq!(
+            tree_lines(root, "backups", &files),
+            vec![
+                "backups (1 kept, 0 to delete)",
+                "├── a (1 kept, 1 to delete)",
+                "│   └── b (0 kept, 2 to delete)",
+                "├── c (0 kept, 0 to delete)",
+                "│   └── d (1 kept, 0 to delete)",
+                "└── e (1 kept, 0 to delete)",
+            ]
+        );
+        assert_eq!(tree_lines(root, ".", &[]), vec![". (0 kept, 0 to delete)"]);
+    }
+
+    #[test]
+    fn test_include_empty_dirs() {
+        println!("Testing the empty directories as candidates");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for i in 0..16 {
+            let empty = dir.path().join(format!("empty{:02}", i));
+            fs::create_dir(&empty).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 86400 + 3600));
+            set_file_times(&empty, ft, ft).unwrap();
+        } // 2, 1, 2, 4 and 7 directories in the segments of 1, 2, 4, 8 and 16 days, the oldest of each kept
+        let full = dir.path().join("full");
+        fs::create_dir(&full).unwrap();
+        fs::File::create(full.join("file.txt")).unwrap();
+        let old = FileTime::from_system_time(now - time::Duration::from_secs(30 * 86400));
+        set_file_times(&full, old, old).unwrap();
+
+        let plan = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Quiet),
+            dir.path(),
+            &PlanOptions {
+                include_empty_dirs: true,
+                ..plan_options(SortType::MTime, 1, true)
+            },
+        )
+        .unwrap();
+        let to_keep = plan.paths(Action::Keep);
+        let to_delete = plan.paths(Action::Delete);
+        assert_eq!(
+            to_keep,
+            [1, 2, 4, 8, 15]
+                .map(|i| dir.path().join(format!("empty{:02}", i)))
+                .into_iter()
+                .chain([full.join("file.txt")])
+                .sorted()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(to_delete.len(), 11);
+        assert!(!to_keep.contains(&full) && !to_delete.contains(&full));
+
+        // A directory filled since the scan is left in place
+        fs::File::create(to_delete[0].join("new.txt")).unwrap();
+        let report = delete_files(
+            Verbosity::Quiet,
+            &to_delete,
+            &DeleteOptions::default(),
+            &atomic::AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(
+            (report.deleted, report.failed, report.freed_bytes),
+            (10, 1, 0)
+        );
+        assert!(to_delete[0].join("new.txt").exists());
+        assert!(to_delete[1..].iter().all(|dir| !dir.exists()));
+    }
+
+    #[test]
+    fn test_skip_unchanged_dirs() {
+        println!("Testing skipping the directories unchanged since the last run");
+
+        let dir = tempdir().unwrap();
+        for sub in ["a", "b"] {
+            let sub_dir = dir.path().join(sub);
+            fs::create_dir(&sub_dir).unwrap();
+            for i in 0..3 {
+                fs::File::create(sub_dir.join(format!("file{}.txt", i))).unwrap();
+            }
+        }
+
+        let run = |previous: DirMtimes| {
+            let options = PlanOptions {
+                dir_cache: Some(DirCache {
+                    previous,
+                    ..Default::default()
+                }),
+                ..plan_options(SortType::MTime, 1, true)
+            };
+            let plan = exp_sort_and_list_to_del(
+                &mut Report::stdout(Verbosity::Quiet),
+                dir.path(),
+                &options,
+            )
+            .unwrap();
+            let unchanged: Vec<_> = plan
+                .skipped
+                .iter()
+                .filter(|entry| entry.reason == SkipReason::Unchanged)
+                .map(|entry| entry.path.clone())
+                .sorted()
+                .collect();
+            (
+                plan.files.len(),
+                unchanged,
+                options.dir_cache.unwrap().merged(),
+            )
+        };
+        let (files, unchanged, recorded) = run(DirMtimes::new());
+        assert_eq!(files, 6);
+        assert!(unchanged.is_empty());
+        assert_eq!(recorded.len(), 3); // The root too
+
+        let (files, unchanged, recorded) = run(recorded);
+        assert_eq!(files, 0);
+        assert_eq!(
+            unchanged,
+            vec![
+                dir.path().to_path_buf(),
+                dir.path().join("a"),
+                dir.path().join("b")
+            ]
+        );
+
+        // A new file changes the time of its directory
+        let b = dir.path().join("b");
+        fs::File::create(b.join("new.txt")).unwrap();
+        let later =
+            FileTime::from_system_time(time::SystemTime::now() + time::Duration::from_secs(60));
+        set_file_times(&b, later, later).unwrap();
+        let (files, unchanged, _) = run(recorded);
+        assert_eq!(files, 4);
+        assert_eq!(
+            unchanged,
+            vec![dir.path().to_path_buf(), dir.path().join("a")]
+        );
+    }
+
+    #[test]
+    fn test_same_filesystem() {
+        println!("Testing staying within the starting filesystem in recursive mode");
+
+        assert!(!crosses_filesystem(Some(1), Some(1)));
+        assert!(crosses_filesystem(Some(1), Some(2)));
+        assert!(!crosses_filesystem(None, Some(2))); // Unknown devices never prune
+        assert!(!crosses_filesystem(Some(1), None));
+
+        let dir = tempdir().unwrap();
+        let sub_dir = dir.path().join("subdir");
+        fs::create_dir(&sub_dir).unwrap();
+        fs::File::create(dir.path().join("file.txt")).unwrap();
+        fs::File::create(sub_dir.join("subfile.txt")).unwrap();
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            recursive: true,
+            same_filesystem: true,
+            ..Default::default()
+        };
+        let plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap();
+        assert_eq!(plan.files.len(), 2); // A subdirectory on the same filesystem is processed
+        assert!(plan.skipped.is_empty());
+
+        #[cfg(unix)]
+        assert_eq!(
+            device_id(&fs::metadata(dir.path()).unwrap()),
+            device_id(&fs::metadata(&sub_dir).unwrap())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_errors() {
+        println!("Testing the subdirectories that cannot be read in recursive mode");
+
+        let dir = tempdir().unwrap();
+        fs::File::create(dir.path().join("file.txt")).unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::File::create(locked.join("hidden.txt")).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        if fs::read_dir(&locked).is_ok() {
+            println!("Directory still readable (running as root?), skipping the test");
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+
+        let scan = |walk_errors| {
+            group_files_by_bucket_recursive(
+                Verbosity::Quiet,
+                dir.path(),
+                &PlanOptions {
+                    walk_errors,
+                    ..plan_options(SortType::MTime, 1, true)
+                },
+            )
+        };
+        let (groups, skipped) = scan(WalkErrors::Warn).unwrap();
+        assert_eq!(groups.len(), 1); // The readable files are still planned
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].path, locked);
+        assert!(matches!(skipped[0].reason, SkipReason::WalkError(_)));
+
+        let (groups, skipped) = scan(WalkErrors::Ignore).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert!(skipped.is_empty());
+
+        let err = scan(WalkErrors::Fail).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("locked"));
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keep_linked() {
+        println!("Testing keeping symlink targets and hard links of kept files");
+
+        let dir = tempdir().unwrap();
+        let now = time::SystemTime::now();
+        for (name, secs) in [("old.txt", 300), ("mid.txt", 200), ("new.txt", 100)] {
+            let file_path = dir.path().join(name);
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(secs));
+            set_file_times(&file_path, ft, ft).unwrap();
+        } // All in one time segment, old.txt is kept with --keep 1
+        fs::hard_link(dir.path().join("old.txt"), dir.path().join("peer.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("new.txt"), dir.path().join("link.txt"))
+            .unwrap();
+
+        let mut options = plan_options(SortType::MTime, 1, false);
+        let (to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        assert_eq!(to_keep.len(), 1);
+        assert_eq!(to_delete.len(), 3);
+
+        options.keep_linked = true;
+        let (to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        assert_eq!(to_delete, vec![dir.path().join("mid.txt")]);
+        assert!(to_keep.contains(&dir.path().join("new.txt"))); // Target of link.txt
+        assert!(to_keep.contains(&dir.path().join("old.txt")));
+        assert!(to_keep.contains(&dir.path().join("peer.txt"))); // Same inode as old.txt
+    }
+
+    #[test]
+    fn test_min_files() {
+        println!("Testing skipping directories with too few files in recursive mode");
+
+        let dir = tempdir().unwrap();
+        let small_dir = dir.path().join("small");
+        let large_dir = dir.path().join("large");
+        fs::create_dir(&small_dir).unwrap();
+        fs::create_dir(&large_dir).unwrap();
+        fs::File::create(dir.path().join("root.txt")).unwrap();
+        for i in 0..2 {
+            fs::File::create(small_dir.join(format!("file{}.txt", i))).unwrap();
+        }
+        for i in 0..50 {
+            fs::File::create(large_dir.join(format!("file{}.txt", i))).unwrap();
+        }
+
+        let options = PlanOptions {
+            sort_type: SortType::MTime,
+            keep: 1,
+            recursive: true,
+            min_files: 10,
+            ..Default::default()
+        };
+        let (to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        assert_eq!(to_keep.len(), 1);
+        assert_eq!(to_delete.len(), 49);
+        assert!(
+            to_keep
+                .iter()
+                .chain(&to_delete)
+                .all(|file| file.starts_with(&large_dir))
+        );
+
+        let options = PlanOptions {
+            min_files: 100,
+            ..options
+        };
+        let result =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options);
+        assert!(result.is_err()); // Every directory is skipped
+    }
+
+    #[test]
+    fn test_skip_hidden_directories() {
+        println!("Testing skipping hidden directories in recursive mode");
+
+        let dir = tempdir().unwrap();
+        fs::File::create(dir.path().join("file.txt")).unwrap();
+        let hidden_dir = dir.path().join(".hidden");
+        let nested_dir = hidden_dir.join("nested");
+        fs::create_dir_all(&nested_dir).unwrap();
+        fs::File::create(hidden_dir.join("cached.txt")).unwrap();
+        fs::File::create(nested_dir.join("deep.txt")).unwrap();
+
+        let mut options = plan_options(SortType::MTime, 0, true);
+        let (_to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        assert_eq!(to_delete.len(), 3); // Hidden directories are processed by default
+
+        options.skip_hidden = true;
+        let (_to_keep, to_delete) =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Normal), dir.path(), &options)
+                .unwrap()
+                .split();
+        assert_eq!(to_delete, vec![dir.path().join("file.txt")]);
+
+        let (_to_keep, to_delete) = exp_sort_and_list_to_del(
+            &mut Report::stdout(Verbosity::Normal),
+            &hidden_dir,
+            &options,
+        )
+        .unwrap()
+        .split(); // A hidden root is still processed, but not its hidden children
+        assert_eq!(to_delete.len(), 2);
+    }
+
+    #[test]
+    fn test_skip_hidden_files() {
+        println!("Testing skipping hidden files in a flat directory");
+
+        let dir = tempdir().unwrap();
+        for name in ["file.txt", ".env", ".expdelignore"] {
+            fs::File::create(dir.path().join(name)).unwrap();
+        }
+
+        let mut options = plan_options(SortType::MTime, 0, false);
+        let plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Quiet), dir.path(), &options)
+                .unwrap();
+        assert_eq!(plan.paths(Action::Delete).len(), 3); // Hidden files are processed by default
+
+        options.skip_hidden = true;
+        let plan =
+            exp_sort_and_list_to_del(&mut Report::stdout(Verbosity::Quiet), dir.path(), &options)
+                .unwrap();
+        assert_eq!(
+            plan.paths(Action::Delete),
+            vec![dir.path().join("file.txt")]
+        );
+        assert_eq!(plan.skipped.len(), 2);
+        assert!(
+            plan.skipped
+                .iter()
+                .all(|entry| entry.reason == SkipReason::HiddenFile)
+        );
+    }
+
     #[test]
     fn test_quiet_mode() {
         println!("Testing quiet mode");
@@ -766,7 +9056,12 @@ mod tests {
         let mut redirect = BufferRedirect::stdout().unwrap();
 
         let files_to_delete = vec![file1.clone(), file2.clone()];
-        let result = delete_files(true, &files_to_delete);
+        let result = delete_files(
+            Verbosity::Quiet,
+            &files_to_delete,
+            &DeleteOptions::default(),
+            &atomic::AtomicBool::new(false),
+        );
 
         redirect.read_to_end(&mut buf).unwrap();
         assert!(
@@ -294,6 +294,9 @@ fn test_both_quiet_and_print_only() {
     println!("Running integration test for ExpDel with both --quiet and --print-only...");
 
     let dir = tempdir().unwrap();
+    for i in 0..6 {
+        fs::write(dir.path().join(format!("file{}.txt", i)), b"12").unwrap();
+    }
 
     let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
         .arg("--path")
@@ -304,16 +307,22 @@ fn test_both_quiet_and_print_only() {
         .arg("4")
         .arg("--print-only")
         .arg("--quiet")
+        .arg("--no-history")
         .output()
         .expect("Failed to execute process");
 
-    println!(
-        "Program output: {}",
-        String::from_utf8_lossy(&output.stdout)
-    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
     println!("{}", String::from_utf8_lossy(&output.stderr));
-    assert!(!output.status.success());
-    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used together"));
+    assert!(output.status.success());
+    assert_eq!(stdout.lines().count(), 1); // Only the summary
+    let summary: serde_json::Value = serde_json::from_str(stdout.trim()).unwrap();
+    assert_eq!(summary["kept"], 4);
+    assert_eq!(summary["to_delete"], 2);
+    assert_eq!(summary["to_delete_bytes"], 4);
+    assert_eq!(summary["deleted"], 0);
+    assert_eq!(summary["print_only"], true);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 6);
     dir.close().unwrap();
 }
 
@@ -388,30 +397,38 @@ fn test_with_zero_keep_and_confirmation() {
         set_file_times(&file_path, random_time, random_time).unwrap();
     } // Create some files with different times, max one-year-old
 
-    let mut child = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
-        .arg("--path")
-        .arg(dir.path())
-        .arg("--sort")
-        .arg("mtime")
-        .arg("--keep")
-        .arg("0")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("Failed to execute process");
-
-    {
-        let stdin = child.stdin.as_mut().expect("Failed to open stdin");
-        stdin.write_all(b"yes\n").expect("Failed to write to stdin");
-    }
-
-    let output = child.wait_with_output().expect("Failed to read stdout");
-
-    println!(
-        "Program output: {}",
-        String::from_utf8_lossy(&output.stdout)
-    );
-    assert!(output.status.success());
+    let run = |answer: &[u8]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--sort")
+            .arg("mtime")
+            .arg("--keep")
+            .arg("0")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute process");
+
+        {
+            let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+            stdin.write_all(answer).expect("Failed to write to stdin");
+        }
+
+        let output = child.wait_with_output().expect("Failed to read stdout");
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        println!("Program output: {}", stdout);
+        assert!(output.status.success());
+        stdout
+    };
+
+    // A plain yes is not enough to delete every file
+    let stdout = run(b"yes\n");
+    assert!(stdout.contains("Type DELETE ALL to proceed"));
+    assert!(stdout.contains("Operation cancelled."));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 500);
+
+    run(b"DELETE ALL\n");
 
     // Check that files are deleted
     let remaining_files = fs::read_dir(dir.path()).unwrap().count();
@@ -420,6 +437,63 @@ fn test_with_zero_keep_and_confirmation() {
     dir.close().unwrap();
 }
 
+#[test]
+fn test_with_recursive_zero_keep_confirmation() {
+    println!("Running integration test for ExpDel with --recursive, --keep 0 and no --force...");
+
+    let dir = tempdir().unwrap();
+    for sub in ["a", "b", "c/d"] {
+        let sub_dir = dir.path().join(sub);
+        fs::create_dir_all(&sub_dir).unwrap();
+        for i in 0..3 {
+            fs::write(sub_dir.join(format!("file{}.txt", i)), "x").unwrap();
+        }
+    }
+    fs::write(dir.path().join("keep.conf"), "x").unwrap(); // Protected, so the root is not emptied
+
+    let run = |answer: &[u8]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("0")
+            .arg("--recursive")
+            .arg("--protect")
+            .arg("*.conf")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute process");
+        child
+            .stdin
+            .as_mut()
+            .expect("Failed to open stdin")
+            .write_all(answer)
+            .expect("Failed to write to stdin");
+        let output = child.wait_with_output().expect("Failed to read stdout");
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        println!("Program output: {}", stdout);
+        assert!(output.status.success());
+        stdout
+    };
+
+    // The warning gives the scope, and the usual DELETE ALL is not enough
+    let stdout = run(b"DELETE ALL\n");
+    assert!(
+        stdout
+            .contains("WARNING! 3 directories will be emptied, 9 files will be deleted from them.")
+    );
+    assert!(stdout.contains("Type DELETE ALL IN 3 DIRECTORIES to proceed"));
+    assert!(stdout.contains("Operation cancelled."));
+    assert_eq!(fs::read_dir(dir.path().join("a")).unwrap().count(), 3);
+
+    run(b"DELETE ALL IN 3 DIRECTORIES\n");
+    for sub in ["a", "b", "c/d"] {
+        assert_eq!(fs::read_dir(dir.path().join(sub)).unwrap().count(), 0);
+    }
+    assert!(dir.path().join("keep.conf").exists());
+}
+
 #[test]
 fn test_with_recursive() {
     println!("Running integration test for ExpDel with --recursive...");
@@ -478,7 +552,10 @@ fn test_with_recursive() {
     );
     assert!(output.status.success());
     // Check that files are deleted
-    let remaining_files = fs::read_dir(dir.path()).unwrap().count();
+    let remaining_files = fs::read_dir(dir.path())
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().is_file())
+        .count(); // The subdirectory itself is not a file
     let remaining_sub_files = fs::read_dir(&sub_dir).unwrap().count();
     println!("\nRemaining files in main dir: {}", remaining_files);
     println!("Remaining files in sub dir: {}", remaining_sub_files);
@@ -486,3 +563,1852 @@ fn test_with_recursive() {
     assert!(remaining_sub_files <= 20); // 10 time segments per dir, max 2 files per segment
     dir.close().unwrap();
 }
+
+#[test]
+fn test_with_verbose() {
+    println!("Running integration test for ExpDel with --verbose...");
+
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("subdir")).unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--keep")
+        .arg("1")
+        .arg("--print-only")
+        .arg("--verbose")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("subdir: not a file")); // Skipped entries are reported only in verbose mode
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 4);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("1")
+        .arg("--quiet")
+        .arg("--verbose")
+        .output()
+        .expect("Failed to execute process");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used together"));
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_summary_only() {
+    println!("Running integration test for ExpDel with --summary-only...");
+
+    let dir = tempdir().unwrap();
+    let now = time::SystemTime::now();
+
+    for i in 0..10 {
+        let file_path = dir.path().join(format!("file{}.txt", i));
+        fs::write(&file_path, b"0123456789").unwrap();
+        let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 86400));
+        set_file_times(&file_path, ft, ft).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--keep")
+        .arg("1")
+        .arg("--summary-only")
+        .arg("--print-only")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("Younger than")); // Bucket headers are still printed
+    assert!(!stdout.contains(" | ")); // No per-file lines
+    assert!(!stdout.contains("file0.txt"));
+    assert!(
+        stdout.contains("Summary: 5 files to keep (50 bytes), 5 files to be deleted (50 bytes)")
+    );
+    assert!(stdout.contains("Print-only enabled"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 10);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_output_file() {
+    println!("Running integration test for ExpDel with --output-file...");
+
+    let dir = tempdir().unwrap();
+    let out_dir = tempdir().unwrap();
+    let now = time::SystemTime::now();
+
+    for i in 0..4 {
+        let file_path = dir.path().join(format!("file{}.txt", i));
+        fs::File::create(&file_path).unwrap();
+        let ft = FileTime::from_system_time(now - time::Duration::from_secs(100 - i));
+        set_file_times(&file_path, ft, ft).unwrap();
+    } // All in one bucket, file0 is the oldest
+
+    let plan_path = out_dir.path().join("plan.txt");
+    fs::write(&plan_path, "stale content").unwrap(); // Should be truncated
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--keep")
+        .arg("1")
+        .arg("--print-only")
+        .arg("--output-file")
+        .arg(&plan_path)
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let plan = fs::read_to_string(&plan_path).unwrap();
+    println!("Program output: {}", stdout);
+    println!("Plan: {}", plan);
+    assert!(output.status.success());
+    assert!(!plan.contains("stale content"));
+    assert!(plan.contains("file0.txt | "));
+    assert!(plan.contains("file3.txt | ") && plan.contains("<-- to be deleted"));
+    assert!(plan.contains("Summary: 1 files to keep"));
+    assert!(!stdout.contains("file0.txt")); // The plan is not printed on the terminal
+    assert!(stdout.contains("Print-only enabled"));
+
+    let csv_path = out_dir.path().join("plan.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--keep")
+        .arg("1")
+        .arg("--print-only")
+        .arg("--format")
+        .arg("csv")
+        .arg("--output-file")
+        .arg(&csv_path)
+        .output()
+        .expect("Failed to execute process");
+
+    assert!(output.status.success());
+    let csv = fs::read_to_string(&csv_path).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines[0], "path,action,reason");
+    assert_eq!(lines.len(), 5);
+    assert!(lines.contains(&format!("{},keep,", dir.path().join("file0.txt").display()).as_str()));
+
+    // The output file is checked before scanning
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("1")
+        .arg("--force")
+        .arg("--output-file")
+        .arg(out_dir.path().join("missing").join("plan.txt"))
+        .output()
+        .expect("Failed to execute process");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Could not create the output file"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 4);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_bom() {
+    println!("Running integration test for ExpDel with --bom...");
+
+    let dir = tempdir().unwrap();
+    let out_dir = tempdir().unwrap();
+    for name in ["café.txt", "plain.txt"] {
+        fs::File::create(dir.path().join(name)).unwrap();
+    }
+
+    let run = |csv_path: &std::path::Path, bom: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_ExpDel"));
+        command
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("1")
+            .arg("--print-only")
+            .arg("--format")
+            .arg("csv")
+            .arg("--output-file")
+            .arg(csv_path);
+        if bom {
+            command.arg("--bom");
+        }
+        let output = command.output().expect("Failed to execute process");
+        assert!(output.status.success());
+        fs::read(csv_path).unwrap()
+    };
+
+    let csv = run(&out_dir.path().join("bom.csv"), true);
+    assert_eq!(&csv[..3], b"\xEF\xBB\xBF");
+    assert!(csv[3..].starts_with(b"path,action,reason"));
+
+    let csv = run(&out_dir.path().join("plain.csv"), false);
+    assert!(csv.starts_with(b"path,action,reason"));
+}
+
+#[test]
+fn test_with_relative() {
+    println!("Running integration test for ExpDel with --relative...");
+
+    let dir = tempdir().unwrap();
+    let sub_dir = dir.path().join("sub");
+    fs::create_dir(&sub_dir).unwrap();
+    let now = time::SystemTime::now();
+    for (i, parent) in [dir.path(), &sub_dir].iter().enumerate() {
+        for j in 0..2 {
+            let file_path = parent.join(format!("file{}.txt", j));
+            fs::File::create(&file_path).unwrap();
+            let ft =
+                FileTime::from_system_time(now - time::Duration::from_secs(100 - j - i as u64));
+            set_file_times(&file_path, ft, ft).unwrap();
+        }
+    } // One bucket per directory, file0.txt is the oldest
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--sort")
+            .arg("mtime")
+            .arg("--keep")
+            .arg("1")
+            .arg("--recursive")
+            .arg("--relative")
+            .args(args)
+            .output()
+            .expect("Failed to execute process");
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        println!("Program output: {}", stdout);
+        assert!(output.status.success());
+        stdout
+    };
+
+    let csv = run(&["--print-only", "--format", "csv"]);
+    let mut lines: Vec<_> = csv.lines().skip(1).map(str::to_string).collect();
+    lines.sort();
+    let sub_file = std::path::Path::new("sub").join("file1.txt");
+    let mut expected = vec![
+        "file0.txt,keep,".to_string(),
+        "file1.txt,delete,".to_string(),
+        format!(
+            "{},keep,",
+            std::path::Path::new("sub").join("file0.txt").display()
+        ),
+        format!("{},delete,", sub_file.display()),
+    ];
+    expected.sort();
+    assert_eq!(lines, expected);
+    assert!(!csv.contains(&dir.path().display().to_string()));
+
+    let stdout = run(&["--force"]);
+    assert!(stdout.contains(&format!("\n{} | ", sub_file.display())));
+    assert!(!dir.path().join("file1.txt").exists());
+    assert!(!dir.path().join(&sub_file).exists());
+    assert!(dir.path().join("file0.txt").exists());
+    assert!(sub_dir.join("file0.txt").exists());
+}
+
+#[test]
+fn test_history_file() {
+    println!("Running integration test for ExpDel history file...");
+
+    let dir = tempdir().unwrap();
+    let state_dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::write(dir.path().join(format!("file{}.txt", i)), b"12345").unwrap();
+    }
+
+    let run = |extra_arg: Option<&str>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_ExpDel"));
+        command
+            .env("XDG_STATE_HOME", state_dir.path())
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--sort")
+            .arg("mtime")
+            .arg("--keep")
+            .arg("1")
+            .arg("--force");
+        if let Some(extra_arg) = extra_arg {
+            command.arg(extra_arg);
+        }
+        command.output().expect("Failed to execute process")
+    };
+
+    let history_path = state_dir.path().join("expdel").join("history.jsonl");
+    assert!(run(Some("--no-history")).status.success());
+    assert!(!history_path.exists());
+
+    fs::write(dir.path().join("file3.txt"), b"12345").unwrap();
+    assert!(run(None).status.success());
+    let history = fs::read_to_string(&history_path).unwrap();
+    let lines: Vec<_> = history.lines().collect();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("\"deleted\":1"));
+    assert!(lines[0].contains("\"freed_bytes\":5"));
+    assert!(lines[0].contains("\"timestamp\""));
+
+    fs::write(dir.path().join("file4.txt"), b"12345").unwrap();
+    assert!(run(None).status.success());
+    let history = fs::read_to_string(&history_path).unwrap();
+    assert_eq!(history.lines().count(), 2); // Appended, not overwritten
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_dump_buckets() {
+    println!("Running integration test for ExpDel with --dump-buckets...");
+
+    let dir = tempdir().unwrap();
+    let now = time::SystemTime::now();
+
+    for i in 0..10 {
+        let file_path = dir.path().join(format!("file{}.txt", i));
+        fs::write(&file_path, "x".repeat(i as usize)).unwrap();
+        let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 86400));
+        set_file_times(&file_path, ft, ft).unwrap();
+    } // Ages 0-9 days: 2 files in bucket 1, 1 in 2, 2 in 4, 4 in 8, 1 in 16
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--dump-buckets")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let dump: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let dirs = dump.as_array().unwrap();
+    assert_eq!(dirs.len(), 1);
+    assert_eq!(dirs[0]["age_unit"], "days");
+    let buckets = dirs[0]["buckets"].as_array().unwrap();
+    let counts: Vec<_> = buckets
+        .iter()
+        .map(|bucket| {
+            (
+                bucket["boundary"].as_u64().unwrap(),
+                bucket["files"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(counts, vec![(1, 2), (2, 1), (4, 2), (8, 4), (16, 1)]);
+    for bucket in buckets {
+        assert_eq!(
+            bucket["paths"].as_array().unwrap().len() as u64,
+            bucket["files"].as_u64().unwrap()
+        );
+    }
+    assert_eq!(buckets[2]["youngest"], 3);
+    assert_eq!(buckets[2]["oldest"], 4);
+    assert_eq!(buckets[2]["bytes"], 3 + 4); // file3.txt and file4.txt
+    assert!(
+        buckets[4]["paths"][0]
+            .as_str()
+            .unwrap()
+            .ends_with("file9.txt")
+    );
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 10); // Nothing deleted
+}
+
+#[test]
+fn test_with_assert_plan() {
+    println!("Running integration test for ExpDel with --assert-plan...");
+
+    let dir = tempdir().unwrap();
+    let files_dir = dir.path().join("files");
+    fs::create_dir(&files_dir).unwrap();
+    let now = time::SystemTime::now();
+    for i in 0..10 {
+        let file_path = files_dir.join(format!("file{}.txt", i));
+        fs::File::create(&file_path).unwrap();
+        let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 86400));
+        set_file_times(&file_path, ft, ft).unwrap();
+    }
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(&files_dir)
+            .arg("--sort")
+            .arg("mtime")
+            .arg("--print-only")
+            .args(args)
+            .output()
+            .expect("Failed to execute process")
+    };
+
+    let output = run(&["--keep", "1", "--format", "json"]);
+    assert!(output.status.success());
+    let plan_path = dir.path().join("plan.json");
+    fs::write(&plan_path, &output.stdout).unwrap();
+    let plan_path = plan_path.to_str().unwrap();
+
+    let output = run(&["--keep", "1", "--assert-plan", plan_path]);
+    println!(
+        "Program output: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert!(output.status.success());
+
+    // Keeping 2 files per time segment keeps 3 files the expected plan deletes
+    let output = run(&["--keep", "2", "--assert-plan", plan_path]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    println!("Program errors: {}", stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("differs from"));
+    assert!(stderr.contains("in 3 files"));
+    assert_eq!(
+        stderr
+            .lines()
+            .filter(|line| line.starts_with("- delete "))
+            .count(),
+        3
+    );
+    assert_eq!(
+        stderr
+            .lines()
+            .filter(|line| line.starts_with("+ keep "))
+            .count(),
+        3
+    );
+
+    // The JSON error holds the mismatches, so stderr stays a single JSON object
+    let output = run(&[
+        "--keep",
+        "2",
+        "--assert-plan",
+        plan_path,
+        "--error-format",
+        "json",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["code"], "assert_plan");
+    let mismatches = error["mismatches"].as_array().unwrap();
+    assert_eq!(mismatches.len(), 6);
+    assert_eq!(
+        mismatches
+            .iter()
+            .filter(|mismatch| mismatch["sign"] == "-" && mismatch["action"] == "delete")
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn test_with_count_only() {
+    println!("Running integration test for ExpDel with --count-only...");
+
+    let dir = tempdir().unwrap();
+    let now = time::SystemTime::now();
+
+    for i in 0..10 {
+        let file_path = dir.path().join(format!("file{}.txt", i));
+        fs::File::create(&file_path).unwrap();
+        let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 86400));
+        set_file_times(&file_path, ft, ft).unwrap();
+    } // Ages 0-9 days: 2 files in bucket 1, 1 in 2, 2 in 4, 4 in 8, 1 in 16
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--count-only")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("Younger than 1 days but older than 0 days: 2 files"));
+    assert!(stdout.contains("Younger than 2 days but older than 1 days: 1 files"));
+    assert!(stdout.contains("Younger than 4 days but older than 2 days: 2 files"));
+    assert!(stdout.contains("Younger than 8 days but older than 4 days: 4 files"));
+    assert!(stdout.contains("Younger than 16 days but older than 8 days: 1 files"));
+    assert!(!stdout.contains("to be deleted"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 10); // Nothing deleted
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--count-only")
+        .arg("--force")
+        .output()
+        .expect("Failed to execute process");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used together"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 10);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_invalid_sort() {
+    println!("Running integration test for ExpDel with an invalid --sort...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtiem")
+        .arg("--keep")
+        .arg("0")
+        .arg("--force")
+        .output()
+        .expect("Failed to execute process");
+
+    println!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid sort type"));
+    assert!(String::from_utf8_lossy(&output.stdout).is_empty()); // Rejected before scanning
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("Modified")
+        .arg("--keep")
+        .arg("1")
+        .arg("--print-only")
+        .output()
+        .expect("Failed to execute process");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("sorting by MTime"));
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_quiet_and_report_kept() {
+    println!("Running integration test for ExpDel with --quiet and --report-kept...");
+
+    let dir = tempdir().unwrap();
+    let sub_dir = dir.path().join("subdir");
+    fs::create_dir(&sub_dir).unwrap();
+    let ft = FileTime::from_system_time(time::SystemTime::now() - time::Duration::from_secs(100));
+    for i in 0..4 {
+        let file_path = dir.path().join(format!("file{}.txt", i));
+        fs::File::create(&file_path).unwrap();
+        set_file_times(&file_path, ft, ft).unwrap();
+    }
+    for i in 0..2 {
+        let file_path = sub_dir.join(format!("subfile{}.txt", i));
+        fs::File::create(&file_path).unwrap();
+        set_file_times(&file_path, ft, ft).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--keep")
+        .arg("1")
+        .arg("--recursive")
+        .arg("--quiet")
+        .arg("--report-kept")
+        .arg("--no-history")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let expected = format!(
+        "{}: 1 files kept, 3 files deleted\n{}: 1 files kept, 1 files deleted\n",
+        dir.path().display(),
+        sub_dir.display()
+    );
+    assert_eq!(stdout, expected); // Only the summary, no per-file lines
+    assert_eq!(fs::read_dir(&sub_dir).unwrap().count(), 1);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_jsonl_format() {
+    println!("Running integration test for ExpDel with --format jsonl...");
+
+    let dir = tempdir().unwrap();
+    let sub_dir = dir.path().join("subdir");
+    fs::create_dir(&sub_dir).unwrap();
+    for i in 0..30 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+    for i in 0..20 {
+        fs::File::create(sub_dir.join(format!("subfile{}.txt", i))).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("2")
+        .arg("--recursive")
+        .arg("--print-only")
+        .arg("--format")
+        .arg("jsonl")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 50); // One line per file considered, nothing else
+    for line in &lines {
+        let file: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert!(file["path"].is_string());
+        assert!(file["action"] == "keep" || file["action"] == "delete");
+    }
+    let kept = lines
+        .iter()
+        .filter(|line| line.contains(r#""action":"keep""#));
+    assert_eq!(kept.count(), 4); // 2 per directory, all files are in one time segment
+
+    // Files kept by --max-delete are not listed as deleted
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("2")
+        .arg("--recursive")
+        .arg("--print-only")
+        .arg("--max-delete")
+        .arg("10")
+        .arg("--format")
+        .arg("jsonl")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 50);
+    let deleted = lines
+        .iter()
+        .filter(|line| line.contains(r#""action":"delete""#));
+    assert_eq!(deleted.count(), 10);
+}
+
+#[test]
+fn test_with_skipped_entries() {
+    println!("Running integration test for ExpDel skipped entries in every format...");
+
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("subdir")).unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let run = |extra_args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("1")
+            .arg("--print-only")
+            .args(extra_args)
+            .output()
+            .expect("Failed to execute process");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let human = run(&["--verbose"]);
+    println!("Program output: {}", human);
+    assert!(human.contains("Skipped 1 entries:"));
+    assert!(human.contains("subdir: not a file (directory)"));
+    assert!(!run(&[]).contains("Skipped 1 entries:")); // Only listed in verbose mode
+
+    let json: serde_json::Value = serde_json::from_str(&run(&["--format", "json"])).unwrap();
+    assert_eq!(json["skipped"][0]["reason"], "directory");
+    assert!(
+        json["skipped"][0]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with("subdir")
+    );
+
+    let csv = run(&["--format", "csv"]);
+    assert!(
+        csv.lines()
+            .any(|line| line.ends_with("subdir,skipped,not a file (directory)"))
+    );
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_require_preview() {
+    println!("Running integration test for ExpDel with --require-preview...");
+
+    let dir = tempdir().unwrap();
+    let state_dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let run = |keep: &str, extra_arg: &str| {
+        Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .env("XDG_STATE_HOME", state_dir.path())
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg(keep)
+            .arg("--require-preview")
+            .arg(extra_arg)
+            .output()
+            .expect("Failed to execute process")
+    };
+
+    let output = run("1", "--force");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No preview of this plan"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+
+    assert!(run("2", "--print-only").status.success());
+    let output = run("1", "--force"); // A preview with other settings does not count
+    assert!(!output.status.success());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+
+    assert!(run("1", "--print-only").status.success());
+    let output = run("1", "--force");
+    println!(
+        "Program output: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert!(output.status.success());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_color() {
+    println!("Running integration test for ExpDel with --color...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let run = |color: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("1")
+            .arg("--print-only")
+            .arg("--color")
+            .arg(color)
+            .output()
+            .expect("Failed to execute process");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let plain = run("never");
+    assert!(!plain.contains("\x1b["));
+    assert!(plain.contains(" <-- to be deleted"));
+
+    let colored = run("always");
+    println!("Program output: {}", colored);
+    assert!(colored.contains("\x1b[32m")); // Kept files in green
+    assert!(colored.contains("\x1b[1;31m<-- to be deleted\x1b[0m"));
+
+    assert!(!run("auto").contains("\x1b[")); // The output is not a terminal
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_paths_file() {
+    println!("Running integration test for ExpDel with --paths-file...");
+
+    let dir1 = tempdir().unwrap();
+    let dir2 = tempdir().unwrap();
+    let list_dir = tempdir().unwrap();
+    for dir in [&dir1, &dir2] {
+        for i in 0..3 {
+            fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+        }
+    }
+    let paths_file = list_dir.path().join("paths.txt");
+    fs::write(
+        &paths_file,
+        format!(
+            "# Directories to clean\n{}\n\n{}\n",
+            dir1.path().display(),
+            dir2.path().display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--paths-file")
+        .arg(&paths_file)
+        .arg("--keep")
+        .arg("1")
+        .arg("--force")
+        .arg("--no-history")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("Summary: 2 files to keep"));
+    assert_eq!(fs::read_dir(dir1.path()).unwrap().count(), 1);
+    assert_eq!(fs::read_dir(dir2.path()).unwrap().count(), 1);
+
+    fs::write(&paths_file, "/nonexistent/expdel/dir\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--paths-file")
+        .arg(&paths_file)
+        .arg("--keep")
+        .arg("1")
+        .arg("--force")
+        .output()
+        .expect("Failed to execute process");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("/nonexistent/expdel/dir"));
+}
+
+#[test]
+fn test_with_lock() {
+    println!("Running integration test for ExpDel with --lock...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+    let lock = fs::File::create(dir.path().join(".expdel.lock")).unwrap();
+    lock.lock().unwrap(); // Another instance is running
+
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("1")
+            .arg("--force")
+            .arg("--no-history")
+            .arg("--lock")
+            .output()
+            .expect("Failed to execute process")
+    };
+
+    let started = time::Instant::now();
+    let output = run();
+    assert!(!output.status.success());
+    assert!(started.elapsed() < time::Duration::from_secs(5)); // Fails fast instead of waiting
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already running"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 4);
+
+    lock.unlock().unwrap();
+    let output = run();
+    assert!(output.status.success());
+    assert!(dir.path().join(".expdel.lock").exists()); // The lock file itself is never deleted
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn test_with_timing() {
+    println!("Running integration test for ExpDel with --timing...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let run = |extra_args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("2")
+            .arg("--no-history")
+            .args(extra_args)
+            .output()
+            .expect("Failed to execute process");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let stdout = run(&["--print-only"]);
+    assert!(!stdout.contains("Scanning:"));
+
+    let stdout = run(&["--print-only", "--timing"]);
+    println!("Program output: {}", stdout);
+    assert!(stdout.contains("Scanning: 3 entries in "));
+    assert!(!stdout.contains("Deleting: ")); // Nothing was deleted
+
+    let stdout = run(&["--force", "--quiet", "--timing"]);
+    println!("Program output: {}", stdout);
+    assert!(stdout.contains("Scanning: 3 entries in "));
+    assert!(stdout.contains("Deleting: 1 files in "));
+    assert!(stdout.contains("files/second"));
+
+    // Machine-readable output is left intact
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("2")
+        .arg("--print-only")
+        .arg("--timing")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .expect("Failed to execute process");
+    assert!(output.status.success());
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(plan["keep"].as_array().unwrap().len(), 2);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Scanning: 2 entries in "));
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_json_summary() {
+    println!("Running integration test for ExpDel with --json-summary...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::write(dir.path().join(format!("file{}.txt", i)), b"1234").unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("2")
+        .arg("--force")
+        .arg("--no-history")
+        .arg("--json-summary")
+        .output()
+        .expect("Failed to execute process");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(stdout.contains("Summary: 2 files to keep")); // The human output is still there
+    let summary: serde_json::Value =
+        serde_json::from_str(stdout.lines().last().unwrap()).expect("The last line should be JSON");
+    assert_eq!(summary["kept"], 2);
+    assert_eq!(summary["kept_bytes"], 8);
+    assert_eq!(summary["to_delete"], 1);
+    assert_eq!(summary["deleted"], 1);
+    assert_eq!(summary["freed_bytes"], 4);
+    assert_eq!(summary["failed"], 0);
+    assert_eq!(summary["print_only"], false);
+    assert!(summary["duration_seconds"].as_f64().unwrap() >= 0.0);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_max_delete() {
+    println!("Running integration test for ExpDel with --max-delete...");
+
+    let dir = tempdir().unwrap();
+    let now = time::SystemTime::now();
+    for i in 0..100 {
+        let file_path = dir.path().join(format!("file{}.txt", i));
+        fs::File::create(&file_path).unwrap();
+        let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 60));
+        set_file_times(&file_path, ft, ft).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("0")
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--force")
+        .arg("--no-history")
+        .arg("--max-delete")
+        .arg("10")
+        .output()
+        .expect("Failed to execute process");
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("100 files could be deleted, only the oldest 10 will be"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 90);
+    for i in 90..100 {
+        assert!(!dir.path().join(format!("file{}.txt", i)).exists());
+    }
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_environment_variables() {
+    println!("Running integration test for ExpDel configured with environment variables...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .env("EXPDEL_PATH", dir.path())
+            .env("EXPDEL_KEEP", "2")
+            .env("EXPDEL_SORT", "mtime")
+            .env("EXPDEL_PRINT_ONLY", "true")
+            .env("EXPDEL_NO_HISTORY", "true")
+            .args(args)
+            .output()
+            .expect("Failed to execute process");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let stdout = run(&[]);
+    println!("Program output: {}", stdout);
+    assert!(stdout.contains("sorting by MTime"));
+    assert!(stdout.contains("Summary: 2 files to keep"));
+    assert!(stdout.contains("Print-only enabled, no files were deleted."));
+
+    let stdout = run(&["--keep", "1", "--sort", "atime"]); // Flags take precedence
+    println!("Program output: {}", stdout);
+    assert!(stdout.contains("sorting by ATime"));
+    assert!(stdout.contains("Summary: 1 files to keep"));
+
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_condense() {
+    println!("Running integration test for ExpDel with --condense...");
+
+    let dir = tempdir().unwrap();
+    let kept_dir = dir.path().join("kept");
+    let pruned_dir = dir.path().join("pruned");
+    for sub_dir in [&kept_dir, &pruned_dir] {
+        fs::create_dir(sub_dir).unwrap();
+    }
+    for i in 0..2 {
+        fs::File::create(kept_dir.join(format!("file{}.txt", i))).unwrap();
+    }
+    for i in 0..4 {
+        fs::File::create(pruned_dir.join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep-for")
+        .arg("kept=2")
+        .arg("--keep")
+        .arg("1")
+        .arg("--recursive")
+        .arg("--condense")
+        .arg("--print-only")
+        .arg("--no-history")
+        .output()
+        .expect("Failed to execute process");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(stdout.contains(&format!("{}: 2 files, all kept", kept_dir.display())));
+    assert!(!stdout.contains(&format!("Opening {}", kept_dir.display())));
+    assert!(stdout.contains(&format!("Opening {}", pruned_dir.display()))); // Listed in full
+    assert!(stdout.contains("<-- to be deleted"));
+    assert!(stdout.contains("Summary: 3 files to keep"));
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_json_errors() {
+    println!("Running integration test for ExpDel with --error-format json...");
+
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("file.txt");
+    fs::File::create(&file_path).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(&file_path)
+        .arg("--keep")
+        .arg("1")
+        .arg("--error-format")
+        .arg("json")
+        .output()
+        .expect("Failed to execute process");
+    assert_eq!(output.status.code(), Some(1));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    println!("Program errors: {}", stderr);
+    assert_eq!(stderr.lines().count(), 1);
+    let error: serde_json::Value = serde_json::from_str(stderr.trim()).expect("Expected JSON");
+    assert_eq!(error["code"], "not_a_directory");
+    assert_eq!(error["path"], file_path.to_str().unwrap());
+    assert!(
+        error["message"]
+            .as_str()
+            .unwrap()
+            .contains("not a directory")
+    );
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_list() {
+    println!("Running integration test for ExpDel with --list...");
+
+    let dir = tempdir().unwrap();
+    let now = time::SystemTime::now();
+    let files: Vec<_> = (0..3)
+        .map(|i| {
+            let file_path = dir.path().join(format!("file{}.txt", i));
+            fs::File::create(&file_path).unwrap();
+            let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 60));
+            set_file_times(&file_path, ft, ft).unwrap();
+            file_path
+        })
+        .collect(); // file2 is the oldest
+
+    let list = |set: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("2")
+            .arg("--sort")
+            .arg("mtime")
+            .arg("--print-only")
+            .arg("--no-history")
+            .arg("--list")
+            .arg(set)
+            .output()
+            .expect("Failed to execute process");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let kept = list("kept");
+    let mut kept_lines: Vec<_> = kept.lines().collect();
+    kept_lines.sort();
+    let expected = [&files[1], &files[2]].map(|file| file.to_str().unwrap());
+    assert_eq!(kept_lines, expected); // Nothing but the paths
+    assert_eq!(list("deleted"), format!("{}\n", files[0].display()));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_print0() {
+    println!("Running integration test for ExpDel with --list and --print0...");
+
+    let dir = tempdir().unwrap();
+    let mut names = vec!["with space.txt", "plain.txt"];
+    if cfg!(unix) {
+        names.push("with\nnewline.txt");
+    }
+    for name in &names {
+        fs::File::create(dir.path().join(name)).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("0")
+        .arg("--print-only")
+        .arg("--no-history")
+        .arg("--list")
+        .arg("deleted")
+        .arg("--print0")
+        .output()
+        .expect("Failed to execute process");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with('\0'));
+    let mut paths: Vec<_> = stdout.trim_end_matches('\0').split('\0').collect();
+    paths.sort();
+    let mut expected: Vec<_> = names
+        .iter()
+        .map(|name| dir.path().join(name).to_str().unwrap().to_string())
+        .collect();
+    expected.sort();
+    assert_eq!(paths, expected); // Round-trips spaces and newlines
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_max_runtime() {
+    println!("Running integration test for ExpDel with --max-runtime...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..200 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("0")
+        .arg("--force")
+        .arg("--no-history")
+        .arg("--max-runtime")
+        .arg("0s")
+        .output()
+        .expect("Failed to execute process");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    println!("Program errors: {}", stderr);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr.contains("Time budget exceeded! 0 of 200 files were deleted, 200 remain."));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 200); // Stopped before deleting
+    dir.close().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_with_show_owner() {
+    println!("Running integration test for ExpDel with --show-owner...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+    let user = Command::new("id").arg("-un").output().unwrap();
+    let user = String::from_utf8_lossy(&user.stdout).trim().to_string();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("1")
+        .arg("--print-only")
+        .arg("--no-history")
+        .arg("--show-owner")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let lines: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.contains(".txt |"))
+        .collect();
+    assert_eq!(lines.len(), 3);
+    for line in lines {
+        assert!(line.contains(&format!(" | {} 0", user))); // Owner followed by the octal mode
+    }
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_auto_confirm_under() {
+    println!("Running integration test for ExpDel with --auto-confirm-under...");
+
+    let run = |files: usize| {
+        let dir = tempdir().unwrap();
+        for i in 0..=files {
+            fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+        }
+        let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("1")
+            .arg("--no-history")
+            .arg("--auto-confirm-under")
+            .arg("5")
+            .stdin(Stdio::null()) // Any prompt reads no answer and cancels
+            .output()
+            .expect("Failed to execute process");
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        println!("Program output: {}", stdout);
+        assert!(output.status.success());
+        (stdout, fs::read_dir(dir.path()).unwrap().count())
+    };
+
+    let (stdout, remaining) = run(1); // One file to delete
+    assert!(!stdout.contains("Do you want to proceed"));
+    assert_eq!(remaining, 1);
+
+    let (stdout, remaining) = run(10); // Ten files to delete
+    assert!(stdout.contains("Do you want to proceed"));
+    assert!(stdout.contains("Operation cancelled."));
+    assert_eq!(remaining, 11);
+}
+
+#[test]
+fn test_with_mark() {
+    println!("Running integration test for ExpDel with --mark...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..5 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+    let run = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("1")
+            .arg("--force")
+            .arg("--no-history")
+            .arg("--mark")
+            .arg(".deleted")
+            .output()
+            .expect("Failed to execute process");
+        println!(
+            "Program output: {}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        assert!(output.status.success());
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+
+    let names = run();
+    assert_eq!(names.len(), 5); // Renamed, not removed
+    assert_eq!(
+        names.iter().filter(|name| name.ends_with(".txt")).count(),
+        1
+    );
+    assert_eq!(
+        names
+            .iter()
+            .filter(|name| name.ends_with(".txt.deleted"))
+            .count(),
+        4
+    );
+
+    assert_eq!(run(), names); // Marked files are left alone, the kept one stays
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_explain() {
+    println!("Running integration test for ExpDel with --explain...");
+
+    let dir = tempdir().unwrap();
+    let now = time::SystemTime::now();
+    for (i, days) in [0, 3, 6].iter().enumerate() {
+        let file_path = dir.path().join(format!("file{}.txt", i));
+        fs::File::create(&file_path).unwrap();
+        let ft = FileTime::from_system_time(now - time::Duration::from_secs(days * 86400 + 3600));
+        set_file_times(&file_path, ft, ft).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--keep")
+        .arg("1")
+        .arg("--print-only")
+        .arg("--no-history")
+        .arg("--explain")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let explained: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.starts_with("Bucket "))
+        .collect();
+    assert_eq!(
+        explained,
+        vec![
+            "Bucket 1: files aged 0-1 days, spanning 2 days",
+            "Bucket 4: files aged 3-4 days, spanning 2 days",
+            "Bucket 8: files aged 5-8 days, spanning 4 days",
+        ]
+    ); // Only the time segments holding files
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_abort_if_over() {
+    println!("Running integration test for ExpDel with --abort-if-over...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..10 {
+        fs::write(dir.path().join(format!("file{}.txt", i)), "test").unwrap();
+    }
+    let run = |cap: &str, value: &str| {
+        Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("1")
+            .arg("--force")
+            .arg("--no-history")
+            .arg(cap)
+            .arg(value)
+            .output()
+            .expect("Failed to execute process")
+    };
+
+    let output = run("--abort-if-over", "5");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    println!("Program errors: {}", stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("9 files would be deleted, more than --abort-if-over 5."));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 10); // Nothing deleted
+
+    let output = run("--abort-if-over-bytes", "20");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    println!("Program errors: {}", stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("36 bytes would be deleted"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 10);
+
+    let output = run("--abort-if-over", "9"); // At the cap
+    assert!(output.status.success());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_infer_dir() {
+    println!("Running integration test for ExpDel with --infer-dir...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+    let run = |infer_dir: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_ExpDel"));
+        command
+            .arg("--path")
+            .arg(dir.path().join("file0.txt"))
+            .arg("--keep")
+            .arg("1")
+            .arg("--print-only")
+            .arg("--no-history");
+        if infer_dir {
+            command.arg("--infer-dir");
+        }
+        command.output().expect("Failed to execute process")
+    };
+
+    let output = run(false);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is a file, not a directory"));
+
+    let output = run(true);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains(&format!("Opening {},", dir.path().display())));
+    assert!(stdout.contains("Summary: 1 files to keep (0 bytes), 2 files to be deleted (0 bytes)"));
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_benchmark() {
+    println!("Running integration test for ExpDel with --benchmark...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..50 {
+        fs::write(dir.path().join(format!("file{}.txt", i)), "test").unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--no-history")
+        .arg("--benchmark")
+        .arg("--benchmark-iterations")
+        .arg("3")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let scans: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.starts_with("Scan "))
+        .collect();
+    assert_eq!(scans.len(), 3);
+    assert!(
+        scans
+            .iter()
+            .all(|line| line.contains(": 50 files in ") && line.ends_with(" files/s)"))
+    );
+    assert!(stdout.contains("Average: "));
+    assert!(stdout.contains("50 files in 1 time segments of 1 directories, 200 bytes"));
+    assert!(!stdout.contains("Summary:")); // No plan
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 50); // Nothing deleted
+    dir.close().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_with_syslog() {
+    println!("Running integration test for ExpDel with --syslog...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..5 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    // Whether a syslog daemon listens or not, the run goes on as usual
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("1")
+        .arg("--force")
+        .arg("--no-history")
+        .arg("--syslog")
+        .output()
+        .expect("Failed to execute process");
+
+    println!(
+        "Program output: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert!(output.status.success());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_exit_nonzero_if_deletions() {
+    println!("Running integration test for ExpDel with --exit-nonzero-if-deletions...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+    let run = |keep: &str| {
+        Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg(keep)
+            .arg("--print-only")
+            .arg("--no-history")
+            .arg("--exit-nonzero-if-deletions")
+            .output()
+            .expect("Failed to execute process")
+    };
+
+    assert_eq!(run("1").status.code(), Some(4)); // Two files would be deleted
+    assert_eq!(run("5").status.code(), Some(0)); // Nothing to delete
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_with_markdown() {
+    println!("Running integration test for ExpDel with --format markdown...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..4 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+    fs::File::create(dir.path().join("a|b.txt")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("1")
+        .arg("--print-only")
+        .arg("--format")
+        .arg("markdown")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines[0], "| Path | Time | Bucket | Action |");
+    assert_eq!(lines[1], "| --- | --- | --- | --- |");
+    let rows: Vec<_> = lines[2..]
+        .iter()
+        .take_while(|line| line.starts_with("| "))
+        .collect();
+    assert_eq!(rows.len(), 5);
+    assert!(
+        rows.iter()
+            .all(|row| row.ends_with(" | keep |") || row.ends_with(" | delete |"))
+    );
+    assert!(stdout.contains("a\\|b.txt")); // The pipe does not break the row
+    assert!(stdout.contains("## Summary"));
+    assert!(stdout.contains("- 1 files to keep"));
+    assert!(stdout.contains("- 4 files to be deleted"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 5);
+
+    // Files past the last custom boundary are in the oldest segment
+    let old = time::SystemTime::now() - time::Duration::from_secs(30 * 86400);
+    let ft = FileTime::from_system_time(old);
+    set_file_times(dir.path().join("file0.txt"), ft, ft).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--buckets")
+        .arg("7")
+        .arg("--keep")
+        .arg("1")
+        .arg("--print-only")
+        .arg("--format")
+        .arg("markdown")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let row = stdout
+        .lines()
+        .find(|line| line.contains("file0.txt"))
+        .unwrap();
+    assert!(row.ends_with(" | oldest | keep |"));
+    assert!(!stdout.contains(&u64::MAX.to_string()));
+}
+
+#[test]
+fn test_with_quarantine_declined() {
+    println!("Running integration test for ExpDel with --quarantine-days and a declined prompt...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::write(dir.path().join(format!("file{}.txt", i)), "x").unwrap();
+    }
+    let expired = dir.path().join(".expdel-quarantine").join("2020-01-01");
+    fs::create_dir_all(&expired).unwrap();
+    fs::write(expired.join("old.txt"), "x").unwrap();
+
+    let run = |answer: &[u8]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .arg("--path")
+            .arg(dir.path())
+            .arg("--keep")
+            .arg("1")
+            .arg("--quarantine-days")
+            .arg("7")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute process");
+        child
+            .stdin
+            .as_mut()
+            .expect("Failed to open stdin")
+            .write_all(answer)
+            .expect("Failed to write to stdin");
+        let output = child.wait_with_output().expect("Failed to read stdout");
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        println!("Program output: {}", stdout);
+        assert!(output.status.success());
+        stdout
+    };
+
+    let stdout = run(b"no\n");
+    assert!(stdout.contains("Expired quarantine to be purged"));
+    assert!(stdout.contains("Operation cancelled."));
+    assert!(expired.join("old.txt").exists()); // Nothing is purged without confirmation
+
+    let stdout = run(b"yes\n");
+    assert!(stdout.contains("Purged the expired quarantine"));
+    assert!(!expired.exists());
+}
+
+#[test]
+fn test_with_skip_unchanged_dirs() {
+    println!("Running integration test for ExpDel with --skip-unchanged-dirs...");
+
+    let parent = tempdir().unwrap();
+    let dir = parent.path().join("root");
+    let state_dir = tempdir().unwrap();
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    for i in 0..3 {
+        fs::write(dir.join(format!("file{}.txt", i)), "x").unwrap();
+        fs::write(dir.join("sub").join(format!("file{}.txt", i)), "x").unwrap();
+    }
+
+    let run = |path: &std::path::Path, current_dir: &std::path::Path| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+            .env("XDG_STATE_HOME", state_dir.path())
+            .current_dir(current_dir)
+            .arg("--path")
+            .arg(path)
+            .arg("--keep")
+            .arg("5")
+            .arg("--recursive")
+            .arg("--skip-unchanged-dirs")
+            .arg("--round-times")
+            .arg("--force")
+            .output()
+            .expect("Failed to execute process");
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        println!("Program output: {}", stdout);
+        assert!(output.status.success());
+        stdout
+    };
+
+    let stdout = run(&dir, parent.path());
+    assert_eq!(stdout.matches("Opening").count(), 2);
+
+    // The root itself is skipped too, also when reached through another path
+    let stdout = run(std::path::Path::new("root"), parent.path());
+    assert!(!stdout.contains("Opening"));
+    let stdout = run(std::path::Path::new("."), &dir);
+    assert!(!stdout.contains("Opening"));
+}
+
+#[test]
+fn test_with_json_deletion_errors() {
+    println!("Running integration test for ExpDel deletion errors with --error-format json...");
+
+    let dir = tempdir().unwrap();
+    let journal = dir.path().join("journal.txt");
+    let missing = dir.path().join("missing.txt");
+    fs::write(&journal, format!("- {}\n", missing.display())).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--journal")
+        .arg(&journal)
+        .arg("--resume")
+        .arg("--error-format")
+        .arg("json")
+        .output()
+        .expect("Failed to execute process");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    println!("Program errors: {}", stderr);
+    assert_eq!(output.status.code(), Some(2));
+    let errors: Vec<serde_json::Value> = stderr
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["code"], "delete_failed");
+    assert_eq!(errors[0]["path"], missing.to_str().unwrap());
+    assert_eq!(errors[1]["code"], "partial_failure");
+    assert_eq!(errors[1]["message"], "1 of 1 files could not be deleted.");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_with_recycle_without_trash() {
+    println!("Running integration test for ExpDel with --recycle and no home trash...");
+
+    let dir = tempdir().unwrap();
+    for i in 0..3 {
+        fs::File::create(dir.path().join(format!("file{}.txt", i))).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .env_remove("HOME")
+        .env_remove("XDG_DATA_HOME")
+        .env_remove("XDG_STATE_HOME")
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--keep")
+        .arg("1")
+        .arg("--recycle")
+        .arg("--no-history")
+        .arg("--force")
+        .output()
+        .expect("Failed to execute process");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    println!("Program errors: {}", stderr);
+    assert!(output.status.success());
+    assert!(stderr.contains("there is no trash"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1); // Deleted permanently instead
+}
+
+#[test]
+fn test_with_record_checksum_and_max_delete() {
+    println!("Running integration test for ExpDel with --record-checksum and --max-delete...");
+
+    let dir = tempdir().unwrap();
+    let now = time::SystemTime::now();
+    for i in 0..4 {
+        let file_path = dir.path().join(format!("file{}.txt", i));
+        fs::write(&file_path, b"abc").unwrap();
+        let ft = FileTime::from_system_time(now - time::Duration::from_secs(i * 60));
+        set_file_times(&file_path, ft, ft).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ExpDel"))
+        .arg("--path")
+        .arg(dir.path())
+        .arg("--sort")
+        .arg("mtime")
+        .arg("--keep")
+        .arg("1")
+        .arg("--max-delete")
+        .arg("1")
+        .arg("--record-checksum")
+        .arg("sha256")
+        .arg("--print-only")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .expect("Failed to execute process");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("Program output: {}", stdout);
+    assert!(output.status.success());
+    let plan: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let hashed: Vec<_> = plan["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|file| file.get("checksum").is_some())
+        .collect();
+    assert_eq!(hashed.len(), 1); // Only the file still deleted under --max-delete
+    assert_eq!(hashed[0]["action"], "delete");
+    assert!(hashed[0]["path"].as_str().unwrap().ends_with("file2.txt")); // The oldest deleted
+}